@@ -2,6 +2,7 @@
 #include <graphx.h>
 #include <fontlibc.h>
 #include <lcddrvce.h>
+#include <sys/lcd.h>
 
 #include "error.h"
 #include "usb.h"
@@ -9,6 +10,7 @@
 #include "video.h"
 
 uint8_t *ticevid_vbuffer;
+uint8_t *ticevid_vscreen;
 
 ticevid_result_t ticevid_draw_init(void) {
     lcd_Init();
@@ -41,6 +43,7 @@ static void ticevid_draw_status(char *text) {
 
 ticevid_result_t ticevid_draw_update(void) {
     ticevid_vbuffer = *gfx_vbuffer;
+    ticevid_vscreen = (uint8_t *)lcd_UpBase;
 
     switch (ticevid_ui_get_state()) {
         case TICEVID_UI_MAIN:
@@ -3,6 +3,8 @@
 #include "error.h"
 
 extern uint8_t *ticevid_vbuffer;
+// What's currently being shown
+extern uint8_t *ticevid_vscreen;
 
 ticevid_result_t ticevid_draw_init(void);
 
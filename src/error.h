@@ -20,6 +20,13 @@ typedef enum [[nodiscard]] {
     TICEVID_FONT_MISSING,
     TICEVID_FONT_INVALID,
     TICEVID_QOI_TAG,
+    TICEVID_PICTURE_CODEC,
+    TICEVID_MOTION_TAG,
+    TICEVID_MOTION_VECTOR,
+    TICEVID_VQ_TAG,
+    TICEVID_RAW_SIZE,
+    TICEVID_LZSS_OVERFLOW,
+    TICEVID_PICTURE_TOO_LARGE,
 } ticevid_result_t;
 
 void ticevid_error_print(char *text);
@@ -0,0 +1,92 @@
+#include <stdbool.h>
+#include <string.h>
+
+#include "draw.h"
+#include "lzss.h"
+
+// Matches the encoder's lzss crate parameters
+#define LZSS_INDEX_BITS 10
+#define LZSS_LENGTH_BITS 4
+#define LZSS_WINDOW_SIZE (1 << LZSS_INDEX_BITS)
+#define LZSS_WINDOW_MASK (LZSS_WINDOW_SIZE - 1)
+// Shorter matches are stored as literals
+#define LZSS_MIN_MATCH 2
+#define LZSS_MAX_MATCH ((1 << LZSS_LENGTH_BITS) + LZSS_MIN_MATCH - 1)
+
+static uint8_t window[LZSS_WINDOW_SIZE];
+
+static uint8_t *input;
+static uint8_t *input_end;
+static uint24_t bit_buffer;
+static uint8_t bits_in_buffer;
+
+// Reads the next bits, most significant first
+// Returns false once the input runs out
+static bool read_bits(uint8_t count, uint24_t *value) {
+    while (bits_in_buffer < count) {
+        if (input >= input_end) {
+            return false;
+        }
+
+        bit_buffer = (bit_buffer << 8) | *input++;
+        bits_in_buffer += 8;
+    }
+
+    bits_in_buffer -= count;
+    *value = (bit_buffer >> bits_in_buffer) & ((1 << count) - 1);
+    return true;
+}
+
+ticevid_result_t ticevid_lzss_decode(
+    uint16_t length,
+    uint8_t *input_buffer,
+    uint24_t pixel_offset,
+    uint24_t max_pixels
+) {
+    uint8_t *output = &ticevid_vbuffer[pixel_offset];
+    uint8_t *output_end = output + max_pixels;
+    uint24_t window_index = LZSS_WINDOW_SIZE - LZSS_MAX_MATCH;
+    uint24_t token;
+
+    input = input_buffer;
+    input_end = input_buffer + length;
+    bit_buffer = 0;
+    bits_in_buffer = 0;
+    memset(window, 0, sizeof(window));
+
+    // Trailing padding bits are too short to be a token
+    while (read_bits(9, &token)) {
+        if (token & 0x100) {
+            if (output >= output_end) {
+                RETURN_ERROR(TICEVID_LZSS_OVERFLOW);
+            }
+
+            *output++ = token;
+            window[window_index] = token;
+            window_index = (window_index + 1) & LZSS_WINDOW_MASK;
+        } else {
+            uint24_t low;
+
+            if (!read_bits(LZSS_INDEX_BITS + LZSS_LENGTH_BITS - 8, &low)) {
+                break;
+            }
+
+            token = (token << (LZSS_INDEX_BITS + LZSS_LENGTH_BITS - 8)) | low;
+            uint24_t source = token >> LZSS_LENGTH_BITS;
+            uint8_t match = (token & ((1 << LZSS_LENGTH_BITS) - 1)) + LZSS_MIN_MATCH;
+
+            if (output + match > output_end) {
+                RETURN_ERROR(TICEVID_LZSS_OVERFLOW);
+            }
+
+            for (uint8_t i = 0; i < match; i++) {
+                uint8_t pixel = window[(source + i) & LZSS_WINDOW_MASK];
+                *output++ = pixel;
+                window[window_index] = pixel;
+                window_index = (window_index + 1) & LZSS_WINDOW_MASK;
+            }
+        }
+    }
+
+    return TICEVID_SUCCESS;
+}
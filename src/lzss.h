@@ -0,0 +1,14 @@
+#include <stdint.h>
+
+#include "error.h"
+
+// Decodes an LZSS compressed image
+ticevid_result_t ticevid_lzss_decode(
+    // How many bytes to read from the input buffer
+    uint16_t length,
+    uint8_t *input_buffer,
+    // How many pixels offset the image is
+    uint24_t pixel_offset,
+    // The size of the image in pixels
+    uint24_t max_pixels
+);
@@ -50,6 +50,28 @@ static int ticevid_handle_errors(ticevid_result_t result) {
             break;
         case TICEVID_QOI_TAG:
             ticevid_error_print("QOI Tag invalid.");
+            break;
+        case TICEVID_PICTURE_CODEC:
+            ticevid_error_print("Picture codec unsupported.");
+            break;
+        case TICEVID_MOTION_TAG:
+            ticevid_error_print("Motion tag invalid.");
+            break;
+        case TICEVID_MOTION_VECTOR:
+            ticevid_error_print("Motion vector out of bounds.");
+            break;
+        case TICEVID_VQ_TAG:
+            ticevid_error_print("VQ tag invalid.");
+            break;
+        case TICEVID_RAW_SIZE:
+            ticevid_error_print("Raw image size invalid.");
+            break;
+        case TICEVID_LZSS_OVERFLOW:
+            ticevid_error_print("LZSS image overflowed.");
+            break;
+        case TICEVID_PICTURE_TOO_LARGE:
+            ticevid_error_print("Picture chunk too large.");
+            break;
         // Should never be ran
         case TICEVID_MSD_ASYNC_WAIT:
             break;
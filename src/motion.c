@@ -0,0 +1,117 @@
+#include <string.h>
+
+#include "draw.h"
+#include "motion.h"
+#include "qoi.h"
+
+#define MOTION_BLOCK_SIZE 8
+
+const uint8_t MOTION_TAG_SKIP = 0;
+const uint8_t MOTION_TAG_MOVE = 0b01000000;
+const uint8_t MOTION_TAG_PATCH = 0b01000001;
+const uint8_t MOTION_TAG_INTRA = 0b01000010;
+const uint8_t MOTION_TAG_DATA_MASK = 0b00111111;
+
+// Sign extends a 4-bit vector component
+static int8_t vector_component(uint8_t nibble) {
+    return nibble & 0b1000 ? (int8_t)nibble - 16 : (int8_t)nibble;
+}
+
+ticevid_result_t ticevid_motion_decode(
+    uint16_t length,
+    uint8_t *input_buffer,
+    uint24_t pixel_offset,
+    uint24_t width,
+    uint8_t height
+) {
+    uint8_t *input_end = input_buffer + length;
+    uint8_t *reference = &ticevid_vscreen[pixel_offset];
+    uint8_t *output = &ticevid_vbuffer[pixel_offset];
+    // Intra runs can't cross blocks but may overshoot them
+    uint8_t intra_block[MOTION_BLOCK_SIZE * MOTION_BLOCK_SIZE * 2];
+
+    uint24_t block_x = 0;
+    uint24_t block_y = 0;
+
+    while (input_buffer < input_end) {
+        uint8_t tag = *input_buffer;
+        input_buffer++;
+
+        // Skipped blocks are left as they were in the previous frame
+        uint8_t blocks = 1;
+
+        if ((tag & 0b11000000) == MOTION_TAG_SKIP) {
+            blocks = (tag & MOTION_TAG_DATA_MASK) + 1;
+        } else {
+            if (block_y >= height) {
+                RETURN_ERROR(TICEVID_MOTION_TAG);
+            }
+
+            uint8_t block_width = width - block_x < MOTION_BLOCK_SIZE ? width - block_x : MOTION_BLOCK_SIZE;
+            uint8_t block_height = height - block_y < MOTION_BLOCK_SIZE ? height - block_y : MOTION_BLOCK_SIZE;
+            uint8_t *destination = &output[block_y * width + block_x];
+
+            if (tag == MOTION_TAG_INTRA) {
+                input_buffer = ticevid_qoi_decode_block(input_buffer, intra_block, block_width * block_height);
+
+                if (input_buffer == NULL) {
+                    RETURN_ERROR(TICEVID_QOI_TAG);
+                }
+
+                for (uint8_t row = 0; row < block_height; row++) {
+                    memcpy(&destination[row * width], &intra_block[row * block_width], block_width);
+                }
+            } else if (tag == MOTION_TAG_MOVE || tag == MOTION_TAG_PATCH) {
+                uint8_t vector = *input_buffer;
+                input_buffer++;
+
+                int24_t source_x = (int24_t)block_x + vector_component(vector >> 4);
+                int24_t source_y = (int24_t)block_y + vector_component(vector & 0x0F);
+
+                // Out of bounds check
+                if (
+                    source_x < 0
+                    || source_y < 0
+                    || source_x + block_width > (int24_t)width
+                    || source_y + block_height > (int24_t)height
+                ) {
+                    RETURN_ERROR(TICEVID_MOTION_VECTOR);
+                }
+
+                uint8_t *source = &reference[source_y * width + source_x];
+
+                for (uint8_t row = 0; row < block_height; row++) {
+                    uint8_t *destination_row = &destination[row * width];
+
+                    memcpy(destination_row, &source[row * width], block_width);
+
+                    if (tag == MOTION_TAG_PATCH) {
+                        // Leftmost pixel is the highest bit
+                        uint8_t mask = *input_buffer;
+                        input_buffer++;
+
+                        for (uint8_t column = 0; column < block_width; column++) {
+                            if (mask & (0x80 >> column)) {
+                                destination_row[column] = *input_buffer;
+                                input_buffer++;
+                            }
+                        }
+                    }
+                }
+            } else {
+                RETURN_ERROR(TICEVID_MOTION_TAG);
+            }
+        }
+
+        for (uint8_t i = 0; i < blocks; i++) {
+            block_x += MOTION_BLOCK_SIZE;
+
+            if (block_x >= width) {
+                block_x = 0;
+                block_y += MOTION_BLOCK_SIZE;
+            }
+        }
+    }
+
+    return TICEVID_SUCCESS;
+}
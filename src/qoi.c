@@ -5,9 +5,18 @@
 
 const uint8_t QOI_TAG_LITERAL = 0xFF;
 const uint8_t QOI_TAG_DIFF = 0;
+const uint8_t QOI_TAG_DIFF_DATA_MASK = 0b00011111;
+const uint8_t QOI_TAG_UP = 0b00100000;
+const uint8_t QOI_TAG_UP_DATA_MASK = 0b00011111;
+const uint8_t QOI_TAG_SKIP = 0b01000000;
+const uint8_t QOI_TAG_SKIP_LONG = 0b01111111;
 const uint8_t QOI_TAG_INDEX = 0b10000000;
 const uint8_t QOI_TAG_RUN = 0b11000000;
 const uint8_t QOI_TAG_DATA_MASK = 0b00111111;
+// Pixels skipped per unit of a long skip
+const uint24_t QOI_SKIP_LONG_PIXELS = 64;
+// Matches LCD_WIDTH; up ops copy from one row back
+#define QOI_ROW_WIDTH 320
 
 static uint8_t index[64];
 static uint8_t previous_pixel;
@@ -22,56 +31,90 @@ static void index_insert(uint8_t value) {
 }
 
 void ticevid_qoi_init_frame(uint24_t pixel_offset) {
-    //memset(index, 0, sizeof(index));
+    // Every frame starts with an empty index, like the encoder's, rather than
+    // with whatever the last frame left in it
+    memset(index, 0, sizeof(index));
     previous_pixel = 0;
     output_buffer = &ticevid_vbuffer[pixel_offset];
 }
 
+// Decodes a single op, returning the next op or NULL if the tag is invalid
+static uint8_t *decode_op(uint8_t *input_buffer) {
+    uint8_t tag = *input_buffer;
+    input_buffer++;
+
+    if ((tag & QOI_TAG_LITERAL) == QOI_TAG_LITERAL) {
+        uint8_t pixel = *input_buffer;
+        input_buffer++;
+        previous_pixel = pixel;
+        *output_buffer = pixel;
+        output_buffer++;
+        index_insert(pixel);
+    } else if ((tag & 0b11000000) == QOI_TAG_RUN) {
+        uint8_t repeat = (tag & QOI_TAG_DATA_MASK) + 1;
+
+        memset(output_buffer, previous_pixel, repeat);
+        output_buffer += repeat;
+    } else if ((tag & 0b11000000) == QOI_TAG_SKIP) {
+        // Pixels are left as they were in the previous frame
+        uint24_t skip;
+
+        if (tag == QOI_TAG_SKIP_LONG) {
+            skip = ((uint24_t)*input_buffer + 1) * QOI_SKIP_LONG_PIXELS;
+            input_buffer++;
+        } else {
+            skip = (tag & QOI_TAG_DATA_MASK) + 1;
+        }
+
+        output_buffer += skip;
+        previous_pixel = output_buffer[-1];
+    } else if ((tag & 0b11100000) == QOI_TAG_UP) {
+        // Never overlaps, as runs are shorter than a row
+        uint8_t repeat = (tag & QOI_TAG_UP_DATA_MASK) + 1;
+
+        memcpy(output_buffer, output_buffer - QOI_ROW_WIDTH, repeat);
+        output_buffer += repeat;
+        previous_pixel = output_buffer[-1];
+    } else if ((tag & 0b11100000) == QOI_TAG_DIFF) {
+        // 1 to 16 forward, then 16 to 1 back
+        uint8_t diff = tag & QOI_TAG_DIFF_DATA_MASK;
+        uint8_t pixel;
+
+        if (diff < 16) {
+            pixel = previous_pixel + (diff + 1);
+        } else {
+            pixel = previous_pixel - (32 - diff);
+        }
+
+        previous_pixel = pixel;
+        *output_buffer = pixel;
+        output_buffer++;
+        index_insert(pixel);
+    } else if ((tag & 0b11000000) == QOI_TAG_INDEX) {
+        uint8_t hash = tag & QOI_TAG_DATA_MASK;
+        uint8_t pixel = index[hash];
+        previous_pixel = pixel;
+        *output_buffer = pixel;
+        output_buffer++;
+    } else {
+        return NULL;
+    }
+
+    return input_buffer;
+}
+
 ticevid_result_t ticevid_qoi_decode(
     uint16_t length,
     uint24_t *remaining_pixels,
     uint8_t *input_buffer
 ) {
     uint24_t start = (uint24_t)output_buffer;
+    uint8_t *input_end = input_buffer + length;
 
-    for (uint24_t i = 0; i < length; i++) {
-        uint8_t tag = input_buffer[i];
-
-        if ((tag & QOI_TAG_LITERAL) == QOI_TAG_LITERAL) {
-            i++;
-            uint8_t pixel = input_buffer[i];
-            previous_pixel = pixel;
-            *output_buffer = pixel;
-            output_buffer++;
-            index_insert(pixel);
-        } else if ((tag & 0b11000000) == QOI_TAG_RUN) {
-            uint8_t repeat = (tag & QOI_TAG_DATA_MASK) + 1;
-
-            memset(output_buffer, previous_pixel, repeat);
-            output_buffer += repeat;
-        } else if ((tag & 0b10000000) == QOI_TAG_DIFF) {
-            // TODO: Fix diff
-            uint8_t diff = tag & 0b0111111;
-            
-            uint8_t pixel;
-
-            if (diff <= 63) {
-                pixel = previous_pixel - (diff + 1);
-            } else {
-                pixel = previous_pixel + (129 - diff);
-            }
-
-            previous_pixel = pixel;
-            *output_buffer = pixel;
-            output_buffer++;
-            index_insert(pixel);
-        } else if ((tag & 0b11000000) == QOI_TAG_INDEX) {
-            uint8_t hash = tag & QOI_TAG_DATA_MASK;
-            uint8_t pixel = index[hash];
-            previous_pixel = pixel;
-            *output_buffer = pixel;
-            output_buffer++;
-        } else {
+    while (input_buffer < input_end) {
+        input_buffer = decode_op(input_buffer);
+
+        if (input_buffer == NULL) {
             RETURN_ERROR(TICEVID_QOI_TAG);
         }
 
@@ -87,3 +130,16 @@ ticevid_result_t ticevid_qoi_decode(
 
     return TICEVID_SUCCESS;
 }
+
+uint8_t *ticevid_qoi_decode_block(uint8_t *input_buffer, uint8_t *block, uint8_t pixels) {
+    uint8_t *frame_output_buffer = output_buffer;
+    output_buffer = block;
+
+    while (input_buffer != NULL && output_buffer < block + pixels) {
+        input_buffer = decode_op(input_buffer);
+    }
+
+    output_buffer = frame_output_buffer;
+
+    return input_buffer;
+}
@@ -12,3 +12,9 @@ ticevid_result_t ticevid_qoi_decode(
     uint24_t *remaining_pixels,
     uint8_t *input_buffer
 );
+
+// Decodes a block's worth of pixels into `block`, sharing the frame's index and previous pixel
+// Blocks aren't rows of the frame, so can't contain up ops
+// Runs may write up to 63 pixels past the end of the block
+// Returns the input after the block or NULL if a tag is invalid
+uint8_t *ticevid_qoi_decode_block(uint8_t *input_buffer, uint8_t *block, uint8_t pixels);
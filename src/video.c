@@ -1,4 +1,5 @@
 #include <stdlib.h>
+#include <string.h>
 
 #include <msddrvce.h>
 #include <sys/lcd.h>
@@ -6,7 +7,11 @@
 #include <fontlibc.h>
 #include <ti/sprintf.h>
 
+#include "draw.h"
+#include "lzss.h"
+#include "motion.h"
 #include "qoi.h"
+#include "vq.h"
 #include "usb.h"
 #include "video.h"
 #include "io.h"
@@ -105,7 +110,7 @@ static ticevid_result_t ticevid_video_caption_track_init(ticevid_caption_track_t
 
 // Unsures every offset is a valid pointer
 static void ticevid_video_chapter_init(ticevid_chapter_t *chapter) {
-    offset_pointer_null(chapter->name);
+    offset_pointer_null(&chapter->name);
 }
 
 // Unsures every offset is a valid pointer
@@ -155,9 +160,9 @@ offset_pointer_null(&title->color_palette);
     return TICEVID_SUCCESS;
 }
 
-// Supports versions: [0.1.0, 0.2.0)
+// Supports versions: [0.4.0, 0.5.0)
 static ticevid_result_t check_version(ticevid_container_version_t version) {
-    if (version.major == 0 && version.minor == 1) {
+    if (version.major == 0 && version.minor == 4) {
         return TICEVID_SUCCESS;
     } else {
         RETURN_ERROR(TICEVID_VIDEO_CONTAINER_VERSION);
@@ -332,24 +337,13 @@ static void _draw_debug(ticevid_picture_chunk_info_t *chunk_info) {
 ticevid_result_t ticevid_video_play_draw(void) {
     ticevid_picture_chunk_info_t chunk_info = picture_chunk_table->chunks[picture_chunk_table_index];
 
-    // How many blocks need to be loaded of the current frame
-    uint16_t remaining_blocks = chunk_info.block_count;
-    // The current block
-    uint24_t current_block = chunk_info.block_index;
-
-    // How many blocks to read into the buffer
-    uint16_t block_count;
-
-    if (remaining_blocks > TICEVID_BUFFER_BLOCKS) {
-        block_count = TICEVID_BUFFER_BLOCKS;
-        remaining_blocks -= TICEVID_BUFFER_BLOCKS;
-    } else {
-        block_count = remaining_blocks;
-        remaining_blocks = 0;
+    // The whole picture chunk is read into the buffer at once
+    if (chunk_info.block_count > TICEVID_BUFFER_BLOCKS) {
+        RETURN_ERROR(TICEVID_PICTURE_TOO_LARGE);
     }
 
     // Async read start
-    EARLY_EXIT(_load_picture_buffer(current_block, block_count));
+    EARLY_EXIT(_load_picture_buffer(chunk_info.block_index, chunk_info.block_count));
 
     // Do things in mean time
     ticevid_qoi_init_frame(pixel_offset);
@@ -358,41 +352,54 @@ ticevid_result_t ticevid_video_play_draw(void) {
     // Async read finish
     EARLY_EXIT(ticevid_usb_msd_block());
 
-    uint24_t remaining_bytes = *(uint24_t *)picture_buffer;
-    uint24_t remaining_pixels = max_pixels;
-
-    EARLY_EXIT(ticevid_qoi_decode(
-        remaining_bytes,
-        &remaining_pixels,
-        picture_buffer + sizeof(uint24_t)
-    ));
-
-    do {
-        // Broken
-        break;
-        if (remaining_blocks > TICEVID_BUFFER_BLOCKS) {
-            block_count = TICEVID_BUFFER_BLOCKS;
-            remaining_blocks -= TICEVID_BUFFER_BLOCKS;
-        } else {
-            block_count = remaining_blocks;
-            remaining_blocks = 0;
-        }
-
-        current_block += TICEVID_BUFFER_BLOCKS;
-        remaining_bytes -= TICEVID_BUFFER_SIZE;
-
-        // Async read start
-        EARLY_EXIT(_load_picture_buffer(current_block, block_count));
+    ticevid_picture_chunk_t *picture_chunk = (ticevid_picture_chunk_t *)picture_buffer;
 
-        // Async read finish
-        EARLY_EXIT(ticevid_usb_msd_block());
+    // Delta frames decode on top of the previous frame still in the buffer
+    uint24_t remaining_bytes = picture_chunk->image_size;
+    uint24_t remaining_pixels = max_pixels;
 
-        EARLY_EXIT(ticevid_qoi_decode(
-            remaining_bytes,
-            &remaining_pixels,
-            picture_buffer
-        ));
-    } while (remaining_bytes > 0);
+    switch (picture_chunk->codec) {
+        case TICEVID_CODEC_QOI:
+            EARLY_EXIT(ticevid_qoi_decode(
+                remaining_bytes,
+                &remaining_pixels,
+                picture_chunk->image
+            ));
+            break;
+        case TICEVID_CODEC_MOTION:
+            return ticevid_motion_decode(
+                remaining_bytes,
+                picture_chunk->image,
+                pixel_offset,
+                LCD_WIDTH,
+                selected_title->height
+            );
+        case TICEVID_CODEC_VQ:
+            return ticevid_vq_decode(
+                remaining_bytes,
+                picture_chunk->image,
+                pixel_offset,
+                LCD_WIDTH,
+                selected_title->height,
+                picture_chunk->frame_type == TICEVID_FRAME_KEY
+            );
+        case TICEVID_CODEC_RAW:
+            if (remaining_bytes != max_pixels) {
+                RETURN_ERROR(TICEVID_RAW_SIZE);
+            }
+
+            memcpy(&ticevid_vbuffer[pixel_offset], picture_chunk->image, remaining_bytes);
+            return TICEVID_SUCCESS;
+        case TICEVID_CODEC_LZSS:
+            return ticevid_lzss_decode(
+                remaining_bytes,
+                picture_chunk->image,
+                pixel_offset,
+                max_pixels
+            );
+        default:
+            RETURN_ERROR(TICEVID_PICTURE_CODEC);
+    }
 
     return TICEVID_SUCCESS;
 }
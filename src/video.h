@@ -65,8 +65,30 @@ typedef struct ticevid_picture_chunk_table {
     ticevid_picture_chunk_info_t chunks[TICEVID_FRAME_TABLE_BLOCKS];
 } ticevid_picture_chunk_table_t;
 
+typedef enum ticevid_frame_type {
+    // Decodes on its own
+    TICEVID_FRAME_KEY = 0,
+    // Decodes on top of the previous frame
+    TICEVID_FRAME_DELTA,
+} ticevid_frame_type_t;
+
+typedef enum ticevid_codec {
+    TICEVID_CODEC_QOI = 0,
+    // Block based motion compensation
+    TICEVID_CODEC_MOTION,
+    // Vector quantization
+    TICEVID_CODEC_VQ,
+    // Uncompressed
+    TICEVID_CODEC_RAW,
+    TICEVID_CODEC_LZSS,
+} ticevid_codec_t;
+
 typedef struct ticevid_picture_chunk {
     uint16_t image_size;
+    // See ticevid_frame_type_t
+    uint8_t frame_type;
+    // See ticevid_codec_t
+    uint8_t codec;
     uint8_t image[];
 } ticevid_picture_chunk_t;
 
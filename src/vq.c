@@ -0,0 +1,111 @@
+#include <string.h>
+
+#include "draw.h"
+#include "vq.h"
+
+#define VQ_BLOCK_SIZE 4
+#define VQ_CODEBOOK_SIZE 256
+// One bit per codebook entry
+#define VQ_UPDATE_FLAGS_SIZE (VQ_CODEBOOK_SIZE / 8)
+
+const uint8_t VQ_TAG_SKIP = 0;
+const uint8_t VQ_TAG_V1 = 0b01000000;
+const uint8_t VQ_TAG_V4 = 0b10000000;
+const uint8_t VQ_TAG_DATA_MASK = 0b00111111;
+
+// 2x2 blocks; top left, top right, bottom left, then bottom right
+static uint8_t codebook[VQ_CODEBOOK_SIZE][4];
+
+ticevid_result_t ticevid_vq_decode(
+    uint16_t length,
+    uint8_t *input_buffer,
+    uint24_t pixel_offset,
+    uint24_t width,
+    uint8_t height,
+    bool keyframe
+) {
+    if (keyframe) {
+        memset(codebook, 0, sizeof(codebook));
+    }
+
+    if (length < VQ_UPDATE_FLAGS_SIZE) {
+        RETURN_ERROR(TICEVID_VQ_TAG);
+    }
+
+    uint8_t *input_end = input_buffer + length;
+    uint8_t *update_flags = input_buffer;
+    input_buffer += VQ_UPDATE_FLAGS_SIZE;
+
+    for (uint24_t entry = 0; entry < VQ_CODEBOOK_SIZE; entry++) {
+        // Highest bit is the first entry
+        if (update_flags[entry / 8] & (0x80 >> (entry % 8))) {
+            memcpy(codebook[entry], input_buffer, 4);
+            input_buffer += 4;
+        }
+    }
+
+    uint8_t *output = &ticevid_vbuffer[pixel_offset];
+
+    uint24_t block_x = 0;
+    uint24_t block_y = 0;
+
+    while (input_buffer < input_end) {
+        uint8_t tag = *input_buffer;
+        input_buffer++;
+
+        uint8_t blocks = (tag & VQ_TAG_DATA_MASK) + 1;
+
+        for (uint8_t i = 0; i < blocks; i++) {
+            if (block_y >= height) {
+                RETURN_ERROR(TICEVID_VQ_TAG);
+            }
+
+            uint8_t rows = height - block_y < VQ_BLOCK_SIZE ? height - block_y : VQ_BLOCK_SIZE;
+            uint8_t *destination = &output[block_y * width + block_x];
+
+            if ((tag & 0b11000000) == VQ_TAG_SKIP) {
+                // Left as they were in the previous frame
+            } else if ((tag & 0b11000000) == VQ_TAG_V1) {
+                // Each pixel of the entry covers 2x2 pixels
+                uint8_t *entry = codebook[*input_buffer];
+                input_buffer++;
+
+                for (uint8_t row = 0; row < rows; row++) {
+                    uint8_t *entry_row = &entry[(row / 2) * 2];
+                    uint8_t *destination_row = &destination[row * width];
+
+                    destination_row[0] = entry_row[0];
+                    destination_row[1] = entry_row[0];
+                    destination_row[2] = entry_row[1];
+                    destination_row[3] = entry_row[1];
+                }
+            } else if ((tag & 0b11000000) == VQ_TAG_V4) {
+                uint8_t *entries[4];
+
+                for (uint8_t quadrant = 0; quadrant < 4; quadrant++) {
+                    entries[quadrant] = codebook[*input_buffer];
+                    input_buffer++;
+                }
+
+                for (uint8_t row = 0; row < rows; row++) {
+                    uint8_t *left = &entries[(row / 2) * 2][(row % 2) * 2];
+                    uint8_t *right = &entries[(row / 2) * 2 + 1][(row % 2) * 2];
+
+                    memcpy(&destination[row * width], left, 2);
+                    memcpy(&destination[row * width + 2], right, 2);
+                }
+            } else {
+                RETURN_ERROR(TICEVID_VQ_TAG);
+            }
+
+            block_x += VQ_BLOCK_SIZE;
+
+            if (block_x >= width) {
+                block_x = 0;
+                block_y += VQ_BLOCK_SIZE;
+            }
+        }
+    }
+
+    return TICEVID_SUCCESS;
+}
@@ -0,0 +1,18 @@
+#include <stdbool.h>
+#include <stdint.h>
+
+#include "error.h"
+
+// Decodes a vector quantized image on top of the previous frame
+ticevid_result_t ticevid_vq_decode(
+    // How many bytes to read from the input buffer
+    uint16_t length,
+    uint8_t *input_buffer,
+    // How many pixels offset the image is
+    uint24_t pixel_offset,
+    // The size of the image in pixels
+    uint24_t width,
+    uint8_t height,
+    // Keyframes start with an empty codebook
+    bool keyframe
+);
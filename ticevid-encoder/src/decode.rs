@@ -0,0 +1,220 @@
+use anyhow::Context;
+use lzss::ResultLzssErrorVoidExt;
+
+use crate::{
+    LCD_WIDTH,
+    encode::{
+        Codec, FrameType, Lzss, QOI_SKIP_LONG_PIXELS, QOI_TAG_INDEX, QOI_TAG_LITERAL, QOI_TAG_RUN,
+        QOI_TAG_SKIP, QOI_TAG_SKIP_LONG, QOI_TAG_UP,
+    },
+};
+
+pub mod motion;
+pub mod vq;
+
+const QOI_TAG_MASK: u8 = 0b1100_0000;
+const QOI_TAG_DATA_MASK: u8 = 0b0011_1111;
+const QOI_TAG_UP_MASK: u8 = 0b1110_0000;
+const QOI_TAG_UP_DATA_MASK: u8 = 0b0001_1111;
+const QOI_TAG_DIFF_DATA_MASK: u8 = 0b0001_1111;
+
+/// The player's QOI decoder state, which carries over between the blocks of a motion image.
+struct QoiDecoder {
+    index: [u8; 64],
+    previous_pixel: u8,
+}
+
+impl Default for QoiDecoder {
+    fn default() -> Self {
+        Self {
+            index: [0; 64],
+            previous_pixel: 0,
+        }
+    }
+}
+
+impl QoiDecoder {
+    fn next_byte(input: &mut &[u8]) -> anyhow::Result<u8> {
+        let (&byte, rest) = input
+            .split_first()
+            .context("QOI image ended partway through an op")?;
+        *input = rest;
+        Ok(byte)
+    }
+
+    /// Writes a pixel, dropping any past the end of the output like the player's unused buffer space.
+    fn put(output: &mut [u8], position: &mut usize, pixel: u8) {
+        if let Some(output_pixel) = output.get_mut(*position) {
+            *output_pixel = pixel;
+        }
+
+        *position += 1;
+    }
+
+    fn pixel(&mut self, pixel: u8, output: &mut [u8], position: &mut usize) {
+        self.previous_pixel = pixel;
+        Self::put(output, position, pixel);
+    }
+
+    /// Decodes a single op, advancing `input` and `position`.
+    ///
+    /// Up ops copy from `row_width` pixels back, and aren't allowed without one.
+    fn decode_op(
+        &mut self,
+        input: &mut &[u8],
+        output: &mut [u8],
+        position: &mut usize,
+        row_width: Option<usize>,
+    ) -> anyhow::Result<()> {
+        let tag = Self::next_byte(input)?;
+
+        if tag == QOI_TAG_LITERAL {
+            let pixel = Self::next_byte(input)?;
+            self.pixel(pixel, output, position);
+            self.index[usize::from(pixel % 64)] = pixel;
+            return Ok(());
+        }
+
+        match tag & QOI_TAG_MASK {
+            QOI_TAG_RUN => {
+                for _ in 0..=(tag & QOI_TAG_DATA_MASK) {
+                    Self::put(output, position, self.previous_pixel);
+                }
+            }
+            QOI_TAG_SKIP => {
+                let skip = if tag == QOI_TAG_SKIP_LONG {
+                    (usize::from(Self::next_byte(input)?) + 1) * QOI_SKIP_LONG_PIXELS
+                } else {
+                    usize::from(tag & QOI_TAG_DATA_MASK) + 1
+                };
+
+                *position += skip;
+                self.previous_pixel = output
+                    .get(*position - 1)
+                    .copied()
+                    .unwrap_or(self.previous_pixel);
+            }
+            QOI_TAG_INDEX => {
+                let pixel = self.index[usize::from(tag & QOI_TAG_DATA_MASK)];
+                self.pixel(pixel, output, position);
+            }
+            _ if tag & QOI_TAG_UP_MASK == QOI_TAG_UP => {
+                let row_width = row_width.context("QOI up op used without a row above")?;
+                anyhow::ensure!(
+                    *position >= row_width,
+                    "QOI up op used in the first row at pixel {position}"
+                );
+
+                for _ in 0..=(tag & QOI_TAG_UP_DATA_MASK) {
+                    let pixel = output
+                        .get(*position - row_width)
+                        .copied()
+                        .unwrap_or_default();
+                    self.pixel(pixel, output, position);
+                }
+            }
+            _ => {
+                let diff = tag & QOI_TAG_DIFF_DATA_MASK;
+                let pixel = if diff < 16 {
+                    self.previous_pixel.wrapping_add(diff + 1)
+                } else {
+                    self.previous_pixel.wrapping_sub(32 - diff)
+                };
+                self.pixel(pixel, output, position);
+                self.index[usize::from(pixel % 64)] = pixel;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes picture chunks the same way the player does.
+///
+/// Each frame is decoded on top of the last, like the player's frame buffer,
+/// so delta frames need every frame since the last keyframe decoded first.
+pub struct PictureDecoder {
+    frame: Vec<u8>,
+    vq: vq::VqDecoder,
+}
+
+impl PictureDecoder {
+    pub fn new(pixels: usize) -> Self {
+        Self {
+            frame: vec![0; pixels],
+            vq: vq::VqDecoder::default(),
+        }
+    }
+
+    /// The last frame decoded.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    fn height(&self) -> anyhow::Result<usize> {
+        let width = usize::from(LCD_WIDTH);
+        anyhow::ensure!(
+            self.frame.len().is_multiple_of(width),
+            "Block based images need whole rows; {} pixels",
+            self.frame.len()
+        );
+        Ok(self.frame.len() / width)
+    }
+
+    pub fn decode(
+        &mut self,
+        frame_type: FrameType,
+        codec: Codec,
+        image: &[u8],
+    ) -> anyhow::Result<&[u8]> {
+        let width = usize::from(LCD_WIDTH);
+
+        match codec {
+            Codec::Qoi => {
+                let mut qoi = QoiDecoder::default();
+                let mut input = image;
+                let mut position = 0;
+
+                while !input.is_empty() && position < self.frame.len() {
+                    qoi.decode_op(&mut input, &mut self.frame, &mut position, Some(width))?;
+                }
+            }
+            Codec::Motion => {
+                self.height()?;
+                // Blocks are copied from what's on screen, not what's been decoded so far
+                let reference = self.frame.clone();
+                motion::decode(image, &mut self.frame, &reference, width)?;
+            }
+            Codec::Vq => {
+                self.height()?;
+                self.vq
+                    .decode(image, &mut self.frame, width, frame_type == FrameType::Key)?;
+            }
+            Codec::Raw => {
+                anyhow::ensure!(
+                    image.len() == self.frame.len(),
+                    "Raw image size doesn't match the frame; {} != {}",
+                    image.len(),
+                    self.frame.len()
+                );
+                self.frame.copy_from_slice(image);
+            }
+            Codec::Lzss => {
+                let pixels = Lzss::decompress_stack(
+                    lzss::SliceReader::new(image),
+                    lzss::VecWriter::with_capacity(self.frame.len()),
+                )
+                .void_unwrap();
+
+                self.frame
+                    .get_mut(..pixels.len())
+                    .with_context(|| {
+                        format!("LZSS image overflowed the frame; {} pixels", pixels.len())
+                    })?
+                    .copy_from_slice(&pixels);
+            }
+        }
+
+        Ok(&self.frame)
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Context;
+
+use crate::{
+    decode::QoiDecoder,
+    encode::motion::{
+        MOTION_BLOCK_SIZE, MOTION_TAG_INTRA, MOTION_TAG_MOVE, MOTION_TAG_PATCH, MOTION_TAG_SKIP,
+    },
+};
+
+const MOTION_TAG_MASK: u8 = 0b1100_0000;
+const MOTION_TAG_DATA_MASK: u8 = 0b0011_1111;
+
+/// Sign extends a 4-bit vector component.
+fn vector_component(nibble: u8) -> isize {
+    if nibble & 0b1000 == 0 {
+        nibble as isize
+    } else {
+        nibble as isize - 16
+    }
+}
+
+fn next_byte(input: &mut &[u8]) -> anyhow::Result<u8> {
+    let (&byte, rest) = input
+        .split_first()
+        .context("Motion image ended partway through a block")?;
+    *input = rest;
+    Ok(byte)
+}
+
+/// Decodes a motion image onto `frame`, copying blocks from `reference`.
+pub fn decode(
+    image: &[u8],
+    frame: &mut [u8],
+    reference: &[u8],
+    width: usize,
+) -> anyhow::Result<()> {
+    let height = frame.len() / width;
+    let mut input = image;
+    let mut qoi = QoiDecoder::default();
+
+    let mut block_x = 0;
+    let mut block_y = 0;
+
+    while !input.is_empty() {
+        let tag = next_byte(&mut input)?;
+        let mut blocks = 1;
+
+        if tag & MOTION_TAG_MASK == MOTION_TAG_SKIP {
+            blocks = usize::from(tag & MOTION_TAG_DATA_MASK) + 1;
+        } else {
+            anyhow::ensure!(
+                block_y < height,
+                "Motion block at row {block_y} is past the bottom of the frame"
+            );
+
+            let block_width = (width - block_x).min(MOTION_BLOCK_SIZE);
+            let block_height = (height - block_y).min(MOTION_BLOCK_SIZE);
+            let destination = block_y * width + block_x;
+
+            match tag {
+                MOTION_TAG_INTRA => {
+                    // Runs can't cross blocks but may overshoot them
+                    let mut block = [0; MOTION_BLOCK_SIZE * MOTION_BLOCK_SIZE * 2];
+                    let pixels = block_width * block_height;
+                    let mut position = 0;
+
+                    while position < pixels {
+                        qoi.decode_op(&mut input, &mut block, &mut position, None)?;
+                    }
+
+                    for (row, block_row) in block[..pixels].chunks(block_width).enumerate() {
+                        let start = destination + row * width;
+                        frame[start..start + block_width].copy_from_slice(block_row);
+                    }
+                }
+                MOTION_TAG_MOVE | MOTION_TAG_PATCH => {
+                    let vector = next_byte(&mut input)?;
+                    let source_x = block_x.checked_add_signed(vector_component(vector >> 4));
+                    let source_y = block_y.checked_add_signed(vector_component(vector & 0x0F));
+
+                    let source = source_x.zip(source_y).filter(|&(source_x, source_y)| {
+                        source_x + block_width <= width && source_y + block_height <= height
+                    });
+                    let (source_x, source_y) = source.with_context(|| {
+                        format!(
+                            "Motion vector {vector:#04x} of block ({block_x}, {block_y}) points outside the frame"
+                        )
+                    })?;
+
+                    for row in 0..block_height {
+                        let start = destination + row * width;
+                        let source = (source_y + row) * width + source_x;
+                        let destination_row = &mut frame[start..start + block_width];
+
+                        destination_row.copy_from_slice(&reference[source..source + block_width]);
+
+                        if tag == MOTION_TAG_PATCH {
+                            // Leftmost pixel is the highest bit
+                            let mask = next_byte(&mut input)?;
+
+                            for (column, pixel) in destination_row.iter_mut().enumerate() {
+                                if mask & (0x80 >> column) != 0 {
+                                    *pixel = next_byte(&mut input)?;
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => anyhow::bail!("Unknown motion tag: {tag:#010b}"),
+            }
+        }
+
+        for _ in 0..blocks {
+            block_x += MOTION_BLOCK_SIZE;
+
+            if block_x >= width {
+                block_x = 0;
+                block_y += MOTION_BLOCK_SIZE;
+            }
+        }
+    }
+
+    Ok(())
+}
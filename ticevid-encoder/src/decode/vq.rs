@@ -0,0 +1,113 @@
+use anyhow::Context;
+
+use crate::encode::vq::{VQ_BLOCK_SIZE, VQ_CODEBOOK_SIZE, VQ_TAG_SKIP, VQ_TAG_V1, VQ_TAG_V4};
+
+const VQ_TAG_MASK: u8 = 0b1100_0000;
+const VQ_TAG_DATA_MASK: u8 = 0b0011_1111;
+/// One bit per codebook entry.
+const VQ_UPDATE_FLAGS_SIZE: usize = VQ_CODEBOOK_SIZE / 8;
+
+/// The codebook carries over between frames, so a decoder has to see every frame in order.
+pub struct VqDecoder {
+    /// 2x2 blocks; top left, top right, bottom left, then bottom right.
+    codebook: [[u8; 4]; VQ_CODEBOOK_SIZE],
+}
+
+impl Default for VqDecoder {
+    fn default() -> Self {
+        Self {
+            codebook: [[0; 4]; VQ_CODEBOOK_SIZE],
+        }
+    }
+}
+
+fn next_bytes<const N: usize>(input: &mut &[u8]) -> anyhow::Result<[u8; N]> {
+    let (bytes, rest) = input
+        .split_first_chunk()
+        .context("VQ image ended partway through a macroblock")?;
+    *input = rest;
+    Ok(*bytes)
+}
+
+impl VqDecoder {
+    pub fn decode(
+        &mut self,
+        image: &[u8],
+        frame: &mut [u8],
+        width: usize,
+        keyframe: bool,
+    ) -> anyhow::Result<()> {
+        if keyframe {
+            self.codebook = [[0; 4]; VQ_CODEBOOK_SIZE];
+        }
+
+        let height = frame.len() / width;
+        let (update_flags, mut input) = image
+            .split_first_chunk::<VQ_UPDATE_FLAGS_SIZE>()
+            .context("VQ image is smaller than its update flags")?;
+
+        for (entry_index, entry) in self.codebook.iter_mut().enumerate() {
+            // Highest bit is the first entry
+            if update_flags[entry_index / 8] & (0x80 >> (entry_index % 8)) != 0 {
+                *entry = next_bytes(&mut input)?;
+            }
+        }
+
+        let mut block_x = 0;
+        let mut block_y = 0;
+
+        while !input.is_empty() {
+            let [tag] = next_bytes(&mut input)?;
+
+            for _ in 0..=(tag & VQ_TAG_DATA_MASK) {
+                anyhow::ensure!(
+                    block_y < height,
+                    "VQ macroblock at row {block_y} is past the bottom of the frame"
+                );
+
+                let rows = (height - block_y).min(VQ_BLOCK_SIZE);
+                let destination = block_y * width + block_x;
+
+                match tag & VQ_TAG_MASK {
+                    // Left as they were in the previous frame
+                    VQ_TAG_SKIP => (),
+                    VQ_TAG_V1 => {
+                        // Each pixel of the entry covers 2x2 pixels
+                        let [entry] = next_bytes(&mut input)?;
+                        let entry = self.codebook[usize::from(entry)];
+
+                        for row in 0..rows {
+                            let left = entry[(row / 2) * 2];
+                            let right = entry[(row / 2) * 2 + 1];
+                            let start = destination + row * width;
+                            frame[start..start + VQ_BLOCK_SIZE]
+                                .copy_from_slice(&[left, left, right, right]);
+                        }
+                    }
+                    VQ_TAG_V4 => {
+                        let entries = next_bytes::<4>(&mut input)?
+                            .map(|entry| self.codebook[usize::from(entry)]);
+
+                        for row in 0..rows {
+                            let left = &entries[(row / 2) * 2][(row % 2) * 2..][..2];
+                            let right = &entries[(row / 2) * 2 + 1][(row % 2) * 2..][..2];
+                            let start = destination + row * width;
+                            frame[start..start + 2].copy_from_slice(left);
+                            frame[start + 2..start + VQ_BLOCK_SIZE].copy_from_slice(right);
+                        }
+                    }
+                    _ => anyhow::bail!("Unknown VQ tag: {tag:#010b}"),
+                }
+
+                block_x += VQ_BLOCK_SIZE;
+
+                if block_x >= width {
+                    block_x = 0;
+                    block_y += VQ_BLOCK_SIZE;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,19 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
 
 use serde::Deserialize;
 
+use crate::{
+    BLOCK_SIZE, BLOCKS_PER_CHUNK, EZ80_CLOCK_HZ, LCD_WIDTH,
+    encode::{
+        NearLosslessSettings, QoiSettings, max_image_size,
+        motion::{MOTION_MAX_SEARCH_RANGE, MotionSettings},
+        rate::RateSettings,
+        registry::{CodecOptions, CodecSelection},
+        vq::{VQ_CODEBOOK_SIZE, VqSettings},
+    },
+    metrics::QualityFloor,
+};
+
 #[derive(Debug, Deserialize)]
 pub struct TitleDefinition {
     /// The name of the title that is displayed to the user.
@@ -26,6 +38,284 @@ pub struct TitleDefinition {
     // TODO: Make optional.
     /// The height of the video.
     pub height: u8,
+    /// Chapters the user can skip to.
+    #[serde(default)]
+    pub chapters: Vec<ChapterDefinition>,
+    /// The maximum amount of frames between keyframes. Defaults to 10 seconds worth of frames.
+    /// Set to `1` to only encode keyframes.
+    #[serde(default)]
+    pub keyframe_interval: Option<u32>,
+    /// The fraction of pixels that have to change for a frame to be encoded as a keyframe.
+    #[serde(default = "default_scene_cut_threshold")]
+    pub scene_cut_threshold: f32,
+    /// Which codec to compress frames with. Defaults to `qoi`.
+    ///
+    /// See [`CodecRegistry`](crate::encode::registry::CodecRegistry) for the available codecs.
+    #[serde(default)]
+    pub codec: CodecChoice,
+    /// Settings for QOI, including delta frames of the `motion` codec.
+    #[serde(default)]
+    pub qoi: QoiDefinition,
+    /// Treats similar colors as equal to help QOI compress noisy video. Lossy.
+    #[serde(default)]
+    pub near_lossless: NearLosslessDefinition,
+    /// Keeps how much the player has to read each frame under a budget, lowering quality where needed.
+    #[serde(default)]
+    pub rate_control: Option<RateControlDefinition>,
+    /// The most blocks a frame may take up. Defaults to and can't exceed the player's buffer of `16`.
+    ///
+    /// Frames that don't fit have their colors reduced until they do.
+    #[serde(default)]
+    pub max_blocks: Option<u8>,
+    /// How to pick between codecs when more than one is given.
+    #[serde(default)]
+    pub codec_selection: CodecSelection,
+    /// Settings for the `motion` codec.
+    #[serde(default)]
+    pub motion: MotionDefinition,
+    /// Settings for the `vq` codec.
+    #[serde(default)]
+    pub vq: VqDefinition,
+    /// Flags scenes where frames score below a PSNR or SSIM, measuring every frame's quality.
+    #[serde(default)]
+    pub quality_floor: Option<QualityFloorDefinition>,
+}
+
+fn default_scene_cut_threshold() -> f32 {
+    0.5
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum CodecChoice {
+    /// Every frame uses the same codec.
+    Single(String),
+    /// Each frame uses whichever codec is best.
+    BestOf(Vec<String>),
+}
+
+impl Default for CodecChoice {
+    fn default() -> Self {
+        Self::Single("qoi".to_string())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MotionDefinition {
+    /// How many pixels in each direction to search for a matching block. At most `7`.
+    #[serde(default = "default_motion_search_range")]
+    pub search_range: u8,
+    /// The fraction of each frame's time the player may spend decoding it.
+    #[serde(default = "default_motion_decode_budget")]
+    pub decode_budget: f32,
+}
+
+impl Default for MotionDefinition {
+    fn default() -> Self {
+        Self {
+            search_range: default_motion_search_range(),
+            decode_budget: default_motion_decode_budget(),
+        }
+    }
+}
+
+fn default_motion_search_range() -> u8 {
+    MOTION_MAX_SEARCH_RANGE
+}
+
+fn default_motion_decode_budget() -> f32 {
+    0.5
+}
+
+impl MotionDefinition {
+    pub fn settings(&self, fps: u8) -> MotionSettings {
+        let frame_cycles = EZ80_CLOCK_HZ / u32::from(fps.max(1));
+
+        MotionSettings {
+            search_range: self.search_range.min(MOTION_MAX_SEARCH_RANGE),
+            max_decode_cycles: (frame_cycles as f32 * self.decode_budget) as u32,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct QoiDefinition {
+    /// Archive quality; searches for the smallest encoding of each frame.
+    /// Many times slower than the default. Defaults to `false`.
+    #[serde(default)]
+    pub optimal_parse: bool,
+}
+
+impl QoiDefinition {
+    pub fn settings(&self) -> QoiSettings {
+        QoiSettings {
+            optimal_parse: self.optimal_parse,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NearLosslessDefinition {
+    /// How far a color can be from the previous or an indexed color to reuse it.
+    /// Each step is one level of red or green, or half a level of blue. Defaults to `0`, which is lossless.
+    #[serde(default)]
+    pub tolerance: u8,
+    /// If set, each frame uses the lowest tolerance up to `tolerance` that's at most this many bytes.
+    #[serde(default)]
+    pub target_size: Option<u16>,
+}
+
+impl NearLosslessDefinition {
+    pub fn settings(&self) -> NearLosslessSettings {
+        NearLosslessSettings {
+            tolerance: self.tolerance,
+            target_size: self.target_size.map(usize::from),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RateControlDefinition {
+    #[serde(flatten)]
+    pub budget: RateBudget,
+    /// How many upcoming frames to spread the budget over. Defaults to `8`.
+    #[serde(default = "default_rate_look_ahead")]
+    pub look_ahead: u8,
+    /// The most near lossless tolerance to use to stay under the budget. Defaults to `4`.
+    #[serde(default = "default_rate_max_tolerance")]
+    pub max_tolerance: u8,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateBudget {
+    /// The most bytes the player reads each second on average.
+    BytesPerSecond(u32),
+    /// The most 512 byte blocks the player reads each frame on average.
+    BlocksPerFrame(f32),
+}
+
+fn default_rate_look_ahead() -> u8 {
+    8
+}
+
+fn default_rate_max_tolerance() -> u8 {
+    4
+}
+
+impl RateControlDefinition {
+    pub fn settings(&self, fps: u8) -> RateSettings {
+        let bytes_per_frame = match self.budget {
+            RateBudget::BytesPerSecond(bytes) => bytes / u32::from(fps.max(1)),
+            RateBudget::BlocksPerFrame(blocks) => (blocks * f32::from(BLOCK_SIZE)) as u32,
+        };
+
+        RateSettings {
+            bytes_per_frame: bytes_per_frame as usize,
+            look_ahead: self.look_ahead.into(),
+            max_tolerance: self.max_tolerance,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VqDefinition {
+    /// How many codebook entries to use. At most `256`.
+    #[serde(default = "default_vq_codebook_size")]
+    pub codebook_size: u16,
+    /// How many times to refine the codebook each frame. Higher is slower but better quality.
+    #[serde(default = "default_vq_iterations")]
+    pub iterations: u8,
+}
+
+impl Default for VqDefinition {
+    fn default() -> Self {
+        Self {
+            codebook_size: default_vq_codebook_size(),
+            iterations: default_vq_iterations(),
+        }
+    }
+}
+
+fn default_vq_codebook_size() -> u16 {
+    VQ_CODEBOOK_SIZE as u16
+}
+
+fn default_vq_iterations() -> u8 {
+    4
+}
+
+impl VqDefinition {
+    pub fn settings(&self) -> VqSettings {
+        VqSettings {
+            codebook_size: self.codebook_size,
+            iterations: self.iterations,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QualityFloorDefinition {
+    /// The lowest PSNR in decibels a frame should have.
+    #[serde(default)]
+    pub psnr: Option<f64>,
+    /// The lowest SSIM a frame should have, up to `1`.
+    #[serde(default)]
+    pub ssim: Option<f64>,
+}
+
+impl QualityFloorDefinition {
+    pub fn settings(&self) -> QualityFloor {
+        QualityFloor {
+            psnr: self.psnr,
+            ssim: self.ssim,
+        }
+    }
+}
+
+impl TitleDefinition {
+    pub fn keyframe_interval(&self) -> u32 {
+        self.keyframe_interval
+            .unwrap_or_else(|| u32::from(self.fps) * 10)
+    }
+
+    pub fn max_blocks(&self) -> u8 {
+        self.max_blocks.unwrap_or(BLOCKS_PER_CHUNK)
+    }
+
+    pub fn codec_options(&self) -> CodecOptions {
+        CodecOptions {
+            width: LCD_WIDTH.into(),
+            max_image_size: max_image_size(self.max_blocks()),
+            qoi: self.qoi.settings(),
+            motion: self.motion.settings(self.fps),
+            vq: self.vq.settings(),
+        }
+    }
+
+    /// The frame index each chapter starts at.
+    pub fn chapter_frames(&self) -> impl Iterator<Item = u32> {
+        self.chapters
+            .iter()
+            .map(|chapter| chapter.start_frame(self.fps))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChapterDefinition {
+    /// The name of the chapter that is displayed to the user.
+    #[serde(default)]
+    pub name: String,
+    /// When the chapter starts, relative from the start of the title.
+    #[serde(default)]
+    pub start: TitleDuration,
+}
+
+impl ChapterDefinition {
+    pub fn start_frame(&self, fps: u8) -> u32 {
+        let start = Duration::from(self.start).as_secs_f64() * f64::from(fps);
+        start.round() as u32
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -48,13 +338,10 @@ pub struct TitleDuration {
     pub hours: u64,
 }
 
-impl From<TitleDuration> for rust_ffmpeg::Duration {
+impl From<TitleDuration> for Duration {
     fn from(value: TitleDuration) -> Self {
-        std::time::Duration::new(
-            value.seconds + (value.minutes * 60) + (value.hours * 60 * 60),
-            value.milliseconds * 1_000,
-        )
-        .into()
+        Duration::from_secs(value.seconds + (value.minutes * 60) + (value.hours * 60 * 60))
+            + Duration::from_millis(value.milliseconds.into())
     }
 }
 
@@ -72,3 +359,14 @@ pub enum CaptionSource {
         index: u8,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_carries_milliseconds_over() {
+        let duration: TitleDuration = toml::from_str("minutes = 1\nmilliseconds = 90500").unwrap();
+        assert_eq!(Duration::from(duration), Duration::from_millis(150_500));
+    }
+}
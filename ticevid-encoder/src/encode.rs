@@ -1,24 +1,75 @@
-use std::iter::Peekable;
+use std::{collections::BTreeSet, iter::Peekable, sync::Arc, time::Duration};
 
-const QOI_TAG_LITERAL: u8 = 0xFF;
-const QOI_TAG_DIFF: u8 = 0b0000_0000;
-const QOI_TAG_INDEX: u8 = 0b1000_0000;
-const QOI_TAG_RUN: u8 = 0b1100_0000;
+use anyhow::Context;
+use log::debug;
+use serde::Serialize;
+
+use crate::{
+    BLOCK_SIZE, BLOCKS_PER_CHUNK, LCD_WIDTH,
+    definition::title::{CodecChoice, TitleDefinition},
+    encode::{
+        rate::{RateController, RateSettings},
+        registry::{CodecRegistry, TitleEncoder},
+    },
+    serialize::{MAX_REQUANTIZE_LEVEL, color_distance, requantize},
+};
+
+pub mod motion;
+pub mod rate;
+pub mod registry;
+pub mod vq;
+
+pub const QOI_TAG_LITERAL: u8 = 0xFF;
+pub const QOI_TAG_DIFF: u8 = 0b0000_0000;
+pub const QOI_TAG_UP: u8 = 0b0010_0000;
+pub const QOI_TAG_SKIP: u8 = 0b0100_0000;
+pub const QOI_TAG_SKIP_LONG: u8 = 0b0111_1111;
+pub const QOI_TAG_INDEX: u8 = 0b1000_0000;
+pub const QOI_TAG_RUN: u8 = 0b1100_0000;
+
+/// How many pixels each unit of a long skip covers.
+pub const QOI_SKIP_LONG_PIXELS: usize = 64;
+/// The most pixels a single up op copies.
+const QOI_MAX_UP: usize = 32;
+/// The furthest a diff can move from the previous pixel, in either direction.
+const QOI_MAX_DIFF: i8 = 16;
+const QOI_MAX_RUN: usize = 63;
 
 pub trait FrameEncoder {
+    /// Returns the amount of bytes written.
+    ///
+    /// Errors instead of writing past the end of `output_buffer`.
     fn encode(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize>;
 }
 
-type Lzss = lzss::Lzss<10, 4, 0x00, { 1 << 10 }, { 2 << 10 }>;
+/// Stores frames uncompressed.
+///
+/// Frames short enough to fit the player's buffer fall back to this when compression doesn't help.
+pub struct RawEncoder;
+
+impl FrameEncoder for RawEncoder {
+    fn encode(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
+        output_buffer
+            .get_mut(..frame.len())
+            .with_context(|| format!("Raw frame overflowed; {} bytes", frame.len()))?
+            .copy_from_slice(frame);
+
+        Ok(frame.len())
+    }
+}
+
+pub type Lzss = lzss::Lzss<10, 4, 0x00, { 1 << 10 }, { 2 << 10 }>;
 
 pub struct LzssEncoder;
 
 impl FrameEncoder for LzssEncoder {
     fn encode(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
+        let max_bytes = output_buffer.len();
         let compressed_bytes = Lzss::compress_stack(
             lzss::SliceReader::new(frame),
             lzss::SliceWriter::new(output_buffer),
-        )?;
+        )
+        .with_context(|| format!("LZSS frame overflowed; more than {max_bytes} bytes"))?;
 
         Ok(compressed_bytes)
     }
@@ -28,14 +79,106 @@ impl FrameEncoder for LzssEncoder {
 /// Based on <https://qoiformat.org/qoi-specification.pdf/>
 ///
 /// The major difference is there's only one color channel.
-/// Diff and luma have been replaced with a 7-bit diff
-pub struct QoiEncoder {
+/// Diff and luma have been replaced with a 5-bit diff, sharing its tag bits with copying from the row above.
+/// Delta frames can also skip pixels unchanged from the reference frame.
+/// Pixels can be copied from the row above, like PNG's up filter.
+#[derive(Clone)]
+pub struct QoiEncoder<'a> {
     output_index: usize,
     index_table: [u8; 64],
     previous_pixel: u8,
+    reference_frame: Option<&'a [u8]>,
+    /// The width of a row, if the pixels are laid out in rows of the frame.
+    row_width: Option<usize>,
+    optimal_parse: bool,
 }
 
-impl QoiEncoder {
+impl<'a> QoiEncoder<'a> {
+    /// Encodes pixels that aren't rows of the frame, such as motion blocks, so can't copy from above.
+    pub fn unaligned() -> Self {
+        Self {
+            row_width: None,
+            ..Default::default()
+        }
+    }
+
+    /// Encodes a delta frame against the previously displayed frame.
+    pub fn delta(reference_frame: &'a [u8]) -> Self {
+        Self {
+            reference_frame: Some(reference_frame),
+            ..Default::default()
+        }
+    }
+
+    /// Searches for the smallest sequence of chunks instead of taking the first that fits.
+    ///
+    /// Much slower, so only worth it for archive quality encodes.
+    #[must_use]
+    pub fn with_optimal_parse(mut self, optimal_parse: bool) -> Self {
+        self.optimal_parse = optimal_parse;
+        self
+    }
+}
+
+impl QoiEncoder<'_> {
+    /// Replaces pixels close to a color QOI can reuse with that color, lengthening runs and index hits.
+    ///
+    /// Follows the encoder's state, so the returned frame must be encoded by an encoder in the same state.
+    pub fn near_lossless(&self, frame: &[u8], tolerance: u8) -> Vec<u8> {
+        let mut index_table = self.index_table;
+        let mut previous_pixel = self.previous_pixel;
+        let mut output = Vec::with_capacity(frame.len());
+
+        for (position, &pixel) in frame.iter().enumerate() {
+            let reference = self
+                .reference_frame
+                .map(|reference_frame| reference_frame[position]);
+            let above = self
+                .row_width
+                .and_then(|width| position.checked_sub(width))
+                .map(|above| output[above]);
+
+            let pixel = if reference == Some(pixel) {
+                // Skipped
+                pixel
+            } else if color_distance(pixel, previous_pixel) <= tolerance {
+                previous_pixel
+            } else if let Some(above) =
+                above.filter(|&above| color_distance(pixel, above) <= tolerance)
+            {
+                above
+            } else {
+                index_table
+                    .iter()
+                    .copied()
+                    .filter(|&entry| index_table[usize::from(Self::index_hash(entry))] == entry)
+                    .filter(|&entry| color_distance(pixel, entry) <= tolerance)
+                    .min_by_key(|&entry| color_distance(pixel, entry))
+                    .unwrap_or(pixel)
+            };
+
+            // Only literals are added to the index
+            let reused = reference == Some(pixel)
+                || pixel == previous_pixel
+                || above == Some(pixel)
+                || index_table[usize::from(Self::index_hash(pixel))] == pixel;
+
+            if !reused {
+                index_table[usize::from(Self::index_hash(pixel))] = pixel;
+            }
+
+            previous_pixel = pixel;
+            output.push(pixel);
+        }
+
+        output
+    }
+
+    /// Keeps the index and previous pixel, but writes from the start of the next output buffer.
+    fn continue_output(&mut self) {
+        self.output_index = 0;
+    }
+
     fn index_insert(&mut self, value: u8) {
         let index = Self::index_hash(value);
         self.index_table[index as usize] = value;
@@ -50,85 +193,200 @@ impl QoiEncoder {
         value % 64
     }
 
-    fn write(&mut self, value: u8, output_buffer: &mut [u8]) {
-        output_buffer[self.output_index] = value;
+    fn write(&mut self, value: u8, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        let max_bytes = output_buffer.len();
+        *output_buffer
+            .get_mut(self.output_index)
+            .with_context(|| format!("QOI frame overflowed; more than {max_bytes} bytes"))? = value;
         self.output_index += 1;
+        Ok(())
     }
 
-    fn write_run(&mut self, value: u8, output_buffer: &mut [u8]) {
+    fn write_run(&mut self, value: u8, output_buffer: &mut [u8]) -> anyhow::Result<()> {
         assert!((0..63).contains(&value));
-        self.write(QOI_TAG_RUN | value, output_buffer);
+        self.write(QOI_TAG_RUN | value, output_buffer)
+    }
+
+    fn write_literal(&mut self, value: u8, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        self.write(QOI_TAG_LITERAL, output_buffer)?;
+        self.write(value, output_buffer)
+    }
+
+    fn write_index(&mut self, value: u8, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        self.write(QOI_TAG_INDEX | Self::index_hash(value), output_buffer)
+    }
+
+    /// Positive diffs are stored as `0..16`, and negative ones as `16..32`.
+    fn write_diff(&mut self, value: i8, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        assert!(
+            value != 0 && (-QOI_MAX_DIFF..=QOI_MAX_DIFF).contains(&value),
+            "Invalid diff chunk value of {value}"
+        );
+        let diff = if value > 0 {
+            value.cast_unsigned() - 1
+        } else {
+            32u8.strict_add_signed(value)
+        };
+
+        self.write(QOI_TAG_DIFF | diff, output_buffer)
+    }
+
+    /// The diff from `previous_pixel` to `pixel`, if a diff chunk can encode it.
+    fn diff(previous_pixel: u8, pixel: u8) -> Option<i8> {
+        let diff = pixel.wrapping_sub(previous_pixel).cast_signed();
+        (diff != 0 && (-QOI_MAX_DIFF..=QOI_MAX_DIFF).contains(&diff)).then_some(diff)
     }
 
-    fn write_literal(&mut self, value: u8, output_buffer: &mut [u8]) {
-        self.write(QOI_TAG_LITERAL, output_buffer);
-        self.write(value, output_buffer);
+    fn write_up(&mut self, pixels: usize, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        assert!((1..=QOI_MAX_UP).contains(&pixels));
+        self.write(QOI_TAG_UP | (pixels - 1) as u8, output_buffer)
     }
 
-    fn write_index(&mut self, value: u8, output_buffer: &mut [u8]) {
-        self.write(QOI_TAG_INDEX | Self::index_hash(value), output_buffer);
+    fn write_skip(&mut self, pixels: usize, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        assert!((1..64).contains(&pixels));
+        self.write(QOI_TAG_SKIP | (pixels - 1) as u8, output_buffer)
     }
 
-    fn write_diff(&mut self, value: i8, output_buffer: &mut [u8]) {
-        let diff = match value {
-            i8::MIN..-64 | 0 | 65..=i8::MAX => panic!("Invalid diff chunk value of {value}"),
-            -64..0 => 127u8.strict_add_signed(value + 1),
-            1..=64 => value.cast_unsigned() - 1,
+    fn write_skip_long(&mut self, units: usize, output_buffer: &mut [u8]) -> anyhow::Result<()> {
+        assert!((1..=256).contains(&units));
+        self.write(QOI_TAG_SKIP_LONG, output_buffer)?;
+        self.write((units - 1) as u8, output_buffer)
+    }
+
+    fn create_skip<I: ExactSizeIterator<Item = u8>>(
+        &mut self,
+        frame: &[u8],
+        pixels: &mut Peekable<I>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<QoiControl> {
+        let Some(reference_frame) = self.reference_frame else {
+            // Key frames can't skip
+            return Ok(QoiControl::Invalid);
+        };
+
+        if pixels.peek().is_none() {
+            // No more data to encode
+            return Ok(QoiControl::Done);
+        }
+
+        let position = frame.len() - pixels.len();
+        let skip = frame[position..]
+            .iter()
+            .zip(&reference_frame[position..])
+            .take_while(|(pixel, reference)| pixel == reference)
+            .count();
+
+        if skip == 0 {
+            // Pixel has changed
+            return Ok(QoiControl::Invalid);
+        }
+
+        let mut remaining = skip;
+
+        while remaining >= QOI_SKIP_LONG_PIXELS {
+            let units = (remaining / QOI_SKIP_LONG_PIXELS).min(256);
+            self.write_skip_long(units, output_buffer)?;
+            remaining -= units * QOI_SKIP_LONG_PIXELS;
+        }
+
+        if remaining > 0 {
+            self.write_skip(remaining, output_buffer)?;
+        }
+
+        pixels.nth(skip - 1);
+        // The decoder continues from the last skipped pixel
+        self.previous_pixel = reference_frame[position + skip - 1];
+
+        Ok(QoiControl::Wrote)
+    }
+
+    fn create_up<I: ExactSizeIterator<Item = u8>>(
+        &mut self,
+        frame: &[u8],
+        pixels: &mut Peekable<I>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<QoiControl> {
+        let position = frame.len() - pixels.len();
+
+        let Some(above) = self.row_width.and_then(|width| position.checked_sub(width)) else {
+            // No row above
+            return Ok(QoiControl::Invalid);
         };
 
-        self.write(QOI_TAG_DIFF | diff, output_buffer);
+        let up = frame[position..]
+            .iter()
+            .zip(&frame[above..])
+            .take(QOI_MAX_UP)
+            .take_while(|(pixel, above)| pixel == above)
+            .count();
+        let run = frame[position..]
+            .iter()
+            .take(QOI_MAX_RUN)
+            .take_while(|&&pixel| pixel == self.previous_pixel)
+            .count();
+
+        if up <= run {
+            // A run is at least as good
+            return Ok(QoiControl::Invalid);
+        }
+
+        self.write_up(up, output_buffer)?;
+        pixels.nth(up - 1);
+        self.previous_pixel = frame[position + up - 1];
+
+        Ok(QoiControl::Wrote)
     }
 
     fn create_run<I: Iterator<Item = u8>>(
         &mut self,
         pixels: &mut Peekable<I>,
         output_buffer: &mut [u8],
-    ) -> QoiControl {
+    ) -> anyhow::Result<QoiControl> {
         for run_index in 0..63 {
             if let Some(&pixel) = pixels.peek() {
                 if pixel == self.previous_pixel {
                     pixels.next();
                     if let Some(&next_pixel) = pixels.peek() {
                         if next_pixel != self.previous_pixel || run_index == 62 {
-                            self.write_run(run_index, output_buffer);
+                            self.write_run(run_index, output_buffer)?;
                             // Write run
-                            return QoiControl::Wrote;
+                            return Ok(QoiControl::Wrote);
                         }
                     } else {
-                        self.write_run(run_index, output_buffer);
+                        self.write_run(run_index, output_buffer)?;
                         // Write run and no more data to encode
-                        return QoiControl::Done;
+                        return Ok(QoiControl::Done);
                     }
                 } else {
                     // Pixel can't run
-                    return QoiControl::Invalid;
+                    return Ok(QoiControl::Invalid);
                 }
             } else {
                 // No more data to encode
-                return QoiControl::Done;
+                return Ok(QoiControl::Done);
             }
         }
 
         // Pixel can't run
-        QoiControl::Invalid
+        Ok(QoiControl::Invalid)
     }
 
     fn create_index<I: Iterator<Item = u8>>(
         &mut self,
         pixels: &mut Peekable<I>,
         output_buffer: &mut [u8],
-    ) -> QoiControl {
+    ) -> anyhow::Result<QoiControl> {
         if let Some(&pixel) = pixels.peek() {
             if self.index_has(pixel) {
                 pixels.next();
-                self.write_index(pixel, output_buffer);
+                self.write_index(pixel, output_buffer)?;
                 self.previous_pixel = pixel;
-                QoiControl::Wrote
+                Ok(QoiControl::Wrote)
             } else {
-                QoiControl::Invalid
+                Ok(QoiControl::Invalid)
             }
         } else {
-            QoiControl::Done
+            Ok(QoiControl::Done)
         }
     }
 
@@ -136,24 +394,19 @@ impl QoiEncoder {
         &mut self,
         pixels: &mut Peekable<I>,
         output_buffer: &mut [u8],
-    ) -> QoiControl {
-        // TODO: Fix diff
-        return QoiControl::Invalid;
+    ) -> anyhow::Result<QoiControl> {
         if let Some(&pixel) = pixels.peek() {
-            let diff = pixel.wrapping_sub(self.previous_pixel).cast_signed();
+            let Some(diff) = Self::diff(self.previous_pixel, pixel) else {
+                return Ok(QoiControl::Invalid);
+            };
 
-            match diff {
-                i8::MIN..-64 | 0 | 65..=i8::MAX => QoiControl::Invalid,
-                -64..0 | 1..=64 => {
-                    pixels.next();
-                    self.index_insert(pixel);
-                    self.write_diff(diff, output_buffer);
-                    self.previous_pixel = pixel;
-                    QoiControl::Wrote
-                }
-            }
+            pixels.next();
+            self.index_insert(pixel);
+            self.write_diff(diff, output_buffer)?;
+            self.previous_pixel = pixel;
+            Ok(QoiControl::Wrote)
         } else {
-            QoiControl::Done
+            Ok(QoiControl::Done)
         }
     }
 }
@@ -167,41 +420,306 @@ enum QoiControl {
     Done,
 }
 
-impl Default for QoiEncoder {
+impl Default for QoiEncoder<'_> {
     fn default() -> Self {
         Self {
             index_table: [0; 64],
             previous_pixel: 0,
             output_index: 0,
+            reference_frame: None,
+            row_width: Some(LCD_WIDTH.into()),
+            optimal_parse: false,
         }
     }
 }
 
-impl FrameEncoder for QoiEncoder {
+impl FrameEncoder for QoiEncoder<'_> {
     fn encode(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
+        if let Some(reference_frame) = self.reference_frame {
+            anyhow::ensure!(
+                reference_frame.len() == frame.len(),
+                "Reference frame size doesn't match; {} != {}",
+                reference_frame.len(),
+                frame.len()
+            );
+        }
+
+        if self.optimal_parse {
+            self.encode_optimal(frame, output_buffer)
+        } else {
+            self.encode_greedy(frame, output_buffer)
+        }
+    }
+}
+
+/// A chunk chosen by [`QoiEncoder::encode_optimal`].
+#[derive(Debug, Clone, Copy)]
+enum QoiOp {
+    Skip(usize),
+    /// In units of [`QOI_SKIP_LONG_PIXELS`].
+    SkipLong(usize),
+    Run(usize),
+    Up(usize),
+    Index,
+    Diff,
+    Literal,
+}
+
+impl QoiOp {
+    fn pixels(self) -> usize {
+        match self {
+            Self::Skip(pixels) | Self::Run(pixels) | Self::Up(pixels) => pixels,
+            Self::SkipLong(units) => units * QOI_SKIP_LONG_PIXELS,
+            Self::Index | Self::Diff | Self::Literal => 1,
+        }
+    }
+}
+
+/// How many times [`QoiEncoder::encode_optimal`] refines its guess of which pixels are indexed.
+const QOI_OPTIMAL_PASSES: usize = 4;
+
+impl QoiEncoder<'_> {
+    /// Finds the fewest bytes needed to encode the frame with dynamic programming.
+    ///
+    /// The previous pixel is always the last pixel encoded, so every chunk but index can be costed exactly.
+    /// Which colors are indexed depends on where literals were written,
+    /// so that's guessed from the last parse and refined over a few passes.
+    /// The greedy parse is kept if it's still smaller.
+    fn encode_optimal(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
+        let mut best = self.clone();
+        let mut best_buffer = vec![0; output_buffer.len()];
+        let mut best_result = best.encode_greedy(frame, &mut best_buffer);
+
+        // Start by assuming every earlier pixel was a literal
+        let mut index_hits = self.index_hits(frame, &vec![QoiOp::Literal; frame.len()]);
+
+        for _ in 0..QOI_OPTIMAL_PASSES {
+            let ops = self.parse(frame, &index_hits);
+            let mut encoder = self.clone();
+            let mut buffer = vec![0; output_buffer.len()];
+            let result = encoder.replay(frame, &ops, &mut buffer);
+
+            let smaller = match (&result, &best_result) {
+                (Ok(bytes), Ok(best_bytes)) => bytes < best_bytes,
+                (Ok(_), Err(_)) => true,
+                (Err(_), _) => false,
+            };
+
+            if smaller {
+                best = encoder;
+                best_buffer = buffer;
+                best_result = result;
+            }
+
+            let next_hits = self.index_hits(frame, &ops);
+
+            if next_hits == index_hits {
+                break;
+            }
+
+            index_hits = next_hits;
+        }
+
+        let bytes = best_result?;
+        output_buffer[..bytes].copy_from_slice(&best_buffer[..bytes]);
+        *self = best;
+
+        Ok(bytes)
+    }
+
+    /// Whether each pixel is in the index when it's reached, if encoded with `ops`.
+    fn index_hits(&self, frame: &[u8], ops: &[QoiOp]) -> Vec<bool> {
+        let mut index_table = self.index_table;
+        let mut hits = Vec::with_capacity(frame.len());
+        let mut position = 0;
+
+        for &op in ops {
+            let end = position + op.pixels();
+
+            hits.extend(
+                frame[position..end]
+                    .iter()
+                    .map(|&pixel| index_table[usize::from(Self::index_hash(pixel))] == pixel),
+            );
+
+            // Index misses are written as literals
+            if let QoiOp::Index | QoiOp::Diff | QoiOp::Literal = op {
+                let pixel = frame[position];
+                index_table[usize::from(Self::index_hash(pixel))] = pixel;
+            }
+
+            position = end;
+        }
+
+        hits
+    }
+
+    /// The chunks that encode the frame in the fewest bytes, assuming `index_hits` is right.
+    fn parse(&self, frame: &[u8], index_hits: &[bool]) -> Vec<QoiOp> {
+        let length = frame.len();
+
+        // How many pixels each chunk could cover from each position
+        let mut skip_lengths = vec![0; length + 1];
+        let mut run_lengths = vec![0; length + 1];
+        let mut up_lengths = vec![0; length + 1];
+
+        for position in (0..length).rev() {
+            let pixel = frame[position];
+            let previous_pixel = position
+                .checked_sub(1)
+                .map_or(self.previous_pixel, |previous| frame[previous]);
+
+            if self
+                .reference_frame
+                .is_some_and(|reference_frame| reference_frame[position] == pixel)
+            {
+                skip_lengths[position] = skip_lengths[position + 1] + 1;
+            }
+
+            if pixel == previous_pixel {
+                run_lengths[position] = run_lengths[position + 1] + 1;
+            }
+
+            if self
+                .row_width
+                .and_then(|width| position.checked_sub(width))
+                .is_some_and(|above| frame[above] == pixel)
+            {
+                up_lengths[position] = up_lengths[position + 1] + 1;
+            }
+        }
+
+        // The fewest bytes to encode the rest of the frame from each position
+        let mut costs = vec![usize::MAX; length + 1];
+        let mut choices = vec![QoiOp::Literal; length];
+        costs[length] = 0;
+
+        for position in (0..length).rev() {
+            let mut consider = |op: QoiOp, bytes: usize| {
+                let cost = bytes + costs[position + op.pixels()];
+
+                if cost < costs[position] {
+                    costs[position] = cost;
+                    choices[position] = op;
+                }
+            };
+
+            consider(QoiOp::Literal, 2);
+
+            if index_hits[position] {
+                consider(QoiOp::Index, 1);
+            }
+
+            let previous_pixel = position
+                .checked_sub(1)
+                .map_or(self.previous_pixel, |previous| frame[previous]);
+
+            if Self::diff(previous_pixel, frame[position]).is_some() {
+                consider(QoiOp::Diff, 1);
+            }
+
+            for pixels in 1..=run_lengths[position].min(QOI_MAX_RUN) {
+                consider(QoiOp::Run(pixels), 1);
+            }
+
+            for pixels in 1..=up_lengths[position].min(QOI_MAX_UP) {
+                consider(QoiOp::Up(pixels), 1);
+            }
+
+            for pixels in 1..=skip_lengths[position].min(QOI_SKIP_LONG_PIXELS - 1) {
+                consider(QoiOp::Skip(pixels), 1);
+            }
+
+            for units in 1..=(skip_lengths[position] / QOI_SKIP_LONG_PIXELS).min(256) {
+                consider(QoiOp::SkipLong(units), 2);
+            }
+        }
+
+        let mut ops = Vec::new();
+        let mut position = 0;
+
+        while position < length {
+            let op = choices[position];
+            ops.push(op);
+            position += op.pixels();
+        }
+
+        ops
+    }
+
+    fn replay(
+        &mut self,
+        frame: &[u8],
+        ops: &[QoiOp],
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<usize> {
+        let mut position = 0;
+
+        for &op in ops {
+            let pixel = frame[position];
+
+            match op {
+                QoiOp::Skip(pixels) => self.write_skip(pixels, output_buffer)?,
+                QoiOp::SkipLong(units) => self.write_skip_long(units, output_buffer)?,
+                QoiOp::Run(pixels) => self.write_run((pixels - 1) as u8, output_buffer)?,
+                QoiOp::Up(pixels) => self.write_up(pixels, output_buffer)?,
+                QoiOp::Index if self.index_has(pixel) => self.write_index(pixel, output_buffer)?,
+                QoiOp::Diff => {
+                    let diff = Self::diff(self.previous_pixel, pixel)
+                        .context("Diff chosen for a pixel out of range")?;
+                    self.write_diff(diff, output_buffer)?;
+                    self.index_insert(pixel);
+                }
+                QoiOp::Index | QoiOp::Literal => {
+                    self.write_literal(pixel, output_buffer)?;
+                    self.index_insert(pixel);
+                }
+            }
+
+            position += op.pixels();
+            self.previous_pixel = frame[position - 1];
+        }
+
+        Ok(self.output_index)
+    }
+
+    /// Writes the first chunk that fits at each pixel.
+    fn encode_greedy(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
         let mut pixels = frame.iter().copied().peekable();
 
         loop {
-            match self.create_run(&mut pixels, output_buffer) {
+            match self.create_skip(frame, &mut pixels, output_buffer)? {
+                QoiControl::Wrote => continue,
+                QoiControl::Invalid => (),
+                QoiControl::Done => break,
+            }
+
+            match self.create_up(frame, &mut pixels, output_buffer)? {
+                QoiControl::Wrote => continue,
+                QoiControl::Invalid => (),
+                QoiControl::Done => break,
+            }
+
+            match self.create_run(&mut pixels, output_buffer)? {
                 QoiControl::Wrote => continue,
                 QoiControl::Invalid => (),
                 QoiControl::Done => break,
             }
 
-            match self.create_index(&mut pixels, output_buffer) {
+            match self.create_index(&mut pixels, output_buffer)? {
                 QoiControl::Wrote => continue,
                 QoiControl::Invalid => (),
                 QoiControl::Done => break,
             }
 
-            match self.create_difference(&mut pixels, output_buffer) {
+            match self.create_difference(&mut pixels, output_buffer)? {
                 QoiControl::Wrote => continue,
                 QoiControl::Invalid => (),
                 QoiControl::Done => break,
             }
 
             if let Some(pixel) = pixels.next() {
-                self.write_literal(pixel, output_buffer);
+                self.write_literal(pixel, output_buffer)?;
                 self.index_insert(pixel);
                 self.previous_pixel = pixel;
             } else {
@@ -212,3 +730,774 @@ impl FrameEncoder for QoiEncoder {
         Ok(self.output_index)
     }
 }
+
+/// The size of a picture chunk before its image.
+pub const PICTURE_CHUNK_HEADER_SIZE: usize = 4;
+
+/// The most bytes an image may take up in a picture chunk of `max_blocks`.
+pub const fn max_image_size(max_blocks: u8) -> usize {
+    let image_size = max_blocks as usize * BLOCK_SIZE as usize - PICTURE_CHUNK_HEADER_SIZE;
+
+    // Images sizes are stored as `u16`
+    if image_size > u16::MAX as usize {
+        u16::MAX as usize
+    } else {
+        image_size
+    }
+}
+
+/// Whether a picture chunk's image depends on the previous frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[repr(u8)]
+pub enum FrameType {
+    /// Decodes on its own.
+    Key = 0,
+    /// Decodes on top of the previous frame.
+    Delta = 1,
+}
+
+/// Which algorithm a picture chunk's image is compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[repr(u8)]
+pub enum Codec {
+    /// See [`QoiEncoder`].
+    Qoi = 0,
+    /// See [`MotionEncoder`](motion::MotionEncoder).
+    Motion = 1,
+    /// See [`VqEncoder`](vq::VqEncoder).
+    Vq = 2,
+    /// See [`RawEncoder`].
+    Raw = 3,
+    /// See [`LzssEncoder`].
+    Lzss = 4,
+}
+
+impl TryFrom<u8> for FrameType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(Self::Key),
+            1 => Ok(Self::Delta),
+            _ => anyhow::bail!("Unknown frame type: {value}"),
+        }
+    }
+}
+
+impl TryFrom<u8> for Codec {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> anyhow::Result<Self> {
+        match value {
+            0 => Ok(Self::Qoi),
+            1 => Ok(Self::Motion),
+            2 => Ok(Self::Vq),
+            3 => Ok(Self::Raw),
+            4 => Ok(Self::Lzss),
+            _ => anyhow::bail!("Unknown codec: {value}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EncodedFrame {
+    /// The size of the picture chunk's image in bytes.
+    pub size: usize,
+    pub frame_type: FrameType,
+    pub codec: Codec,
+}
+
+/// A frame's time in the source video, as `hh:mm:ss.mmm`.
+pub fn frame_timestamp(start: Duration, fps: u8, frame_index: u32) -> String {
+    let time = start + Duration::from_secs_f64(f64::from(frame_index) / f64::from(fps.max(1)));
+    let seconds = time.as_secs();
+
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        time.subsec_millis()
+    )
+}
+
+/// Encodes a title's frames in order, choosing between key and delta frames.
+pub struct PictureEncoder {
+    /// The last frame given to the encoder, as the player will display it.
+    previous_frame: Option<Vec<u8>>,
+    /// Index of the next frame.
+    frame_index: u32,
+    frames_since_keyframe: u32,
+    keyframe_interval: u32,
+    scene_cut_threshold: f32,
+    /// Frames that must always be keyframes, such as chapter starts.
+    forced_keyframes: Arc<BTreeSet<u32>>,
+    /// Defaults to QOI.
+    encoder: Box<dyn TitleEncoder>,
+    /// The most blocks a picture chunk may take up.
+    max_blocks: u8,
+    fps: u8,
+    /// Where the first frame is in the source video.
+    start: Duration,
+    near_lossless: NearLosslessSettings,
+    rate_control: Option<RateController>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QoiSettings {
+    /// Use [`QoiEncoder::with_optimal_parse`].
+    pub optimal_parse: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NearLosslessSettings {
+    /// How far a pixel's color can be from a color QOI can reuse. `0` is lossless.
+    pub tolerance: u8,
+    /// If set, each frame uses the lowest tolerance that's at most this many bytes.
+    pub target_size: Option<usize>,
+}
+
+impl PictureEncoder {
+    pub fn new(
+        keyframe_interval: u32,
+        scene_cut_threshold: f32,
+        forced_keyframes: impl IntoIterator<Item = u32>,
+    ) -> Self {
+        Self {
+            previous_frame: None,
+            frame_index: 0,
+            frames_since_keyframe: 0,
+            keyframe_interval,
+            scene_cut_threshold,
+            forced_keyframes: Arc::new(forced_keyframes.into_iter().collect()),
+            encoder: Box::new(registry::StatelessEncoder::qoi()),
+            max_blocks: BLOCKS_PER_CHUNK,
+            fps: 0,
+            start: Duration::ZERO,
+            near_lossless: NearLosslessSettings::default(),
+            rate_control: None,
+        }
+    }
+
+    /// Frames that don't fit are requantized until they do.
+    #[must_use]
+    pub fn with_max_blocks(mut self, max_blocks: u8) -> Self {
+        self.max_blocks = max_blocks;
+        self
+    }
+
+    #[must_use]
+    pub fn with_near_lossless(mut self, near_lossless: NearLosslessSettings) -> Self {
+        self.near_lossless = near_lossless;
+        self
+    }
+
+    /// Frames over their share of the budget are made coarser until they fit.
+    ///
+    /// Upcoming frames have to be given to [`Self::look_ahead`].
+    #[must_use]
+    pub fn with_rate_control(mut self, settings: RateSettings) -> Self {
+        self.rate_control = Some(RateController::new(settings));
+        self
+    }
+
+    /// Used to report when frames are in the source video.
+    #[must_use]
+    pub fn with_timing(mut self, fps: u8, start: Duration) -> Self {
+        self.fps = fps;
+        self.start = start;
+        self
+    }
+
+    #[must_use]
+    pub fn with_encoder(mut self, encoder: Box<dyn TitleEncoder>) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    pub fn from_title(title: &TitleDefinition, registry: &CodecRegistry) -> anyhow::Result<Self> {
+        let options = title.codec_options();
+        let encoder = match &title.codec {
+            CodecChoice::Single(name) => registry.create(name, &options),
+            CodecChoice::BestOf(names) => {
+                registry.create_best(names, title.codec_selection, &options)
+            }
+        }
+        .with_context(|| format!("Failed to create codec for title: {}", title.name))?;
+
+        let max_blocks = title.max_blocks();
+        anyhow::ensure!(
+            (1..=BLOCKS_PER_CHUNK).contains(&max_blocks),
+            "Max blocks per frame must be from 1 to {BLOCKS_PER_CHUNK}; got {max_blocks}"
+        );
+
+        // Uncompressed frames never get smaller, so frames taller than the buffer could never be played
+        let raw_only = match &title.codec {
+            CodecChoice::Single(name) => name == "raw",
+            CodecChoice::BestOf(names) => matches!(names.as_slice(), [name] if name == "raw"),
+        };
+        let frame_size = usize::from(LCD_WIDTH) * usize::from(title.height);
+        let max_image_size = max_image_size(max_blocks);
+        anyhow::ensure!(
+            !raw_only || frame_size <= max_image_size,
+            "Title \"{}\" can't use only the `raw` codec at a height of {}; {frame_size} > {max_image_size} bytes fit in {max_blocks} blocks, so use at most {} rows or add other codecs",
+            title.name,
+            title.height,
+            max_image_size / usize::from(LCD_WIDTH)
+        );
+
+        let picture_encoder = Self::new(
+            title.keyframe_interval(),
+            title.scene_cut_threshold,
+            title.chapter_frames(),
+        )
+        .with_encoder(encoder)
+        .with_max_blocks(max_blocks)
+        .with_timing(
+            title.fps,
+            title.start.map(Duration::from).unwrap_or_default(),
+        )
+        .with_near_lossless(title.near_lossless.settings());
+
+        let Some(rate_control) = &title.rate_control else {
+            return Ok(picture_encoder);
+        };
+
+        let settings = rate_control.settings(title.fps);
+        anyhow::ensure!(
+            settings.bytes_per_frame >= usize::from(BLOCK_SIZE),
+            "Rate control budget must be at least one block per frame; got {} bytes",
+            settings.bytes_per_frame
+        );
+
+        Ok(picture_encoder.with_rate_control(settings))
+    }
+
+    /// How many frames ahead of the next frame to encode [`Self::look_ahead`] expects.
+    pub fn look_ahead_frames(&self) -> usize {
+        self.rate_control
+            .as_ref()
+            .map_or(0, |rate_control| rate_control.settings().look_ahead)
+    }
+
+    /// Gives rate control an upcoming frame, including the next frame to encode.
+    pub fn look_ahead(&mut self, frame: &[u8]) {
+        if let Some(rate_control) = &mut self.rate_control {
+            rate_control.look_ahead(frame);
+        }
+    }
+
+    /// The frame the player displays after the last encoded frame.
+    pub fn previous_frame(&self) -> Option<&[u8]> {
+        self.previous_frame.as_deref()
+    }
+
+    /// Index of the next frame.
+    pub fn frame_index(&self) -> u32 {
+        self.frame_index
+    }
+
+    /// Splits off an encoder for the next frame, to encode it on another thread,
+    /// then moves on as if the player displays the frame unchanged.
+    ///
+    /// Only possible when the codec keeps nothing between frames and nothing makes frames coarser on purpose.
+    /// Frames that don't fit are still requantized or fall back to another codec,
+    /// so once the fork has encoded the frame, check its [`Self::previous_frame`] against the frame;
+    /// if they differ, the fork is where encoding has to carry on from.
+    pub fn fork(&mut self, frame: &[u8]) -> Option<Self> {
+        if self.rate_control.is_some() || self.near_lossless.tolerance != 0 {
+            return None;
+        }
+
+        let fork = Self {
+            previous_frame: self.previous_frame.clone(),
+            frame_index: self.frame_index,
+            frames_since_keyframe: self.frames_since_keyframe,
+            keyframe_interval: self.keyframe_interval,
+            scene_cut_threshold: self.scene_cut_threshold,
+            forced_keyframes: Arc::clone(&self.forced_keyframes),
+            encoder: self.encoder.fork()?,
+            max_blocks: self.max_blocks,
+            fps: self.fps,
+            start: self.start,
+            near_lossless: self.near_lossless,
+            rate_control: None,
+        };
+
+        let frame_type = self.frame_type(frame);
+        self.advance(frame_type, frame.to_vec());
+        Some(fork)
+    }
+
+    fn frame_type(&self, frame: &[u8]) -> FrameType {
+        let Some(previous_frame) = &self.previous_frame else {
+            return FrameType::Key;
+        };
+
+        if self.forced_keyframes.contains(&self.frame_index)
+            || self.frames_since_keyframe + 1 >= self.keyframe_interval
+        {
+            return FrameType::Key;
+        }
+
+        let changed = frame
+            .iter()
+            .zip(previous_frame)
+            .filter(|(pixel, previous)| pixel != previous)
+            .count();
+
+        if changed as f32 >= frame.len() as f32 * self.scene_cut_threshold {
+            // Scene cut
+            FrameType::Key
+        } else {
+            FrameType::Delta
+        }
+    }
+
+    /// The most bytes a frame's image may take up.
+    pub fn max_image_size(&self) -> usize {
+        max_image_size(self.max_blocks)
+    }
+
+    /// The frame's time in the source video.
+    fn timestamp(&self) -> String {
+        frame_timestamp(self.start, self.fps, self.frame_index)
+    }
+
+    fn too_large(&self, size: usize) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Frame {} at {} doesn't fit in {} blocks; {size} > {} bytes",
+            self.frame_index,
+            self.timestamp(),
+            self.max_blocks,
+            self.max_image_size()
+        )
+    }
+
+    /// Applies lossy passes to make the frame easier to compress.
+    fn preprocess(&self, frame: &[u8], frame_type: FrameType, level: u8, tolerance: u8) -> Vec<u8> {
+        let frame = requantize(frame, level);
+
+        if tolerance == 0 {
+            return frame;
+        }
+
+        match (frame_type, &self.previous_frame) {
+            (FrameType::Delta, Some(previous_frame)) => {
+                QoiEncoder::delta(previous_frame).near_lossless(&frame, tolerance)
+            }
+            _ => QoiEncoder::default().near_lossless(&frame, tolerance),
+        }
+    }
+
+    pub fn encode(
+        &mut self,
+        frame: Vec<u8>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<EncodedFrame> {
+        let frame_type = self.frame_type(&frame);
+        let max_image_size = self.max_image_size();
+        let NearLosslessSettings {
+            mut tolerance,
+            mut target_size,
+        } = self.near_lossless;
+
+        if let Some(rate_control) = &self.rate_control {
+            tolerance = tolerance.max(rate_control.settings().max_tolerance);
+            target_size = Some(
+                target_size.map_or(rate_control.target_size(), |target_size| {
+                    target_size.min(rate_control.target_size())
+                }),
+            );
+        }
+
+        let max_tolerance = tolerance;
+        let min_tolerance = if target_size.is_some() {
+            0
+        } else {
+            max_tolerance
+        };
+        let target_size = target_size.map_or(max_image_size, |target_size| {
+            target_size.min(max_image_size)
+        });
+        // Rate control also requantizes to meet its target
+        let max_level = if self.rate_control.is_some() {
+            MAX_REQUANTIZE_LEVEL
+        } else {
+            0
+        };
+
+        let mut size = 0;
+        // Codecs that keep state start each attempt from where the last frame left them
+        self.encoder.checkpoint();
+
+        for level in 0..=MAX_REQUANTIZE_LEVEL {
+            for tolerance in min_tolerance..=max_tolerance {
+                if level > 0 || tolerance > min_tolerance {
+                    self.encoder.restore();
+                }
+
+                let processed_frame = self.preprocess(&frame, frame_type, level, tolerance);
+                let (codec, encoded_size, displayed_frame) =
+                    self.encode_image(processed_frame, frame_type, output_buffer)?;
+                size = encoded_size;
+
+                let last_attempt = tolerance == max_tolerance && level >= max_level;
+
+                if size <= target_size || (last_attempt && size <= max_image_size) {
+                    if level > 0 {
+                        debug!(
+                            "Frame {} requantized {level} times to fit",
+                            self.frame_index
+                        );
+                    }
+
+                    if let Some(rate_control) = &mut self.rate_control {
+                        rate_control.update(size);
+                    }
+
+                    self.advance(frame_type, displayed_frame);
+                    return Ok(EncodedFrame {
+                        size,
+                        frame_type,
+                        codec,
+                    });
+                }
+            }
+        }
+
+        Err(self.too_large(size))
+    }
+
+    /// Returns the codec, the amount of bytes written, and the frame the player displays.
+    fn encode_image(
+        &mut self,
+        frame: Vec<u8>,
+        frame_type: FrameType,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<(Codec, usize, Vec<u8>)> {
+        let reference_frame = match frame_type {
+            FrameType::Key => None,
+            FrameType::Delta => self.previous_frame.as_deref(),
+        };
+
+        let encoded = self.encoder.encode(&frame, reference_frame, output_buffer);
+
+        Ok(match encoded {
+            // Lossy codecs have later frames build on what the player actually displays
+            Ok((codec, size))
+                if size < frame.len() || codec == Codec::Raw || self.encoder.keeps_state() =>
+            {
+                let displayed_frame = self.encoder.reconstruction().map_or(frame, <[u8]>::to_vec);
+                (codec, size, displayed_frame)
+            }
+            // Skipping a frame would desync the player's state
+            Err(error) if self.encoder.keeps_state() => {
+                return Err(error.context(format!("Failed to encode frame {}", self.frame_index)));
+            }
+            encoded => {
+                if let Err(error) = encoded {
+                    debug!("Frame {} fell back: {error:#}", self.frame_index);
+                }
+
+                let (codec, size) = self.fallback(&frame, output_buffer)?;
+                (codec, size, frame)
+            }
+        })
+    }
+
+    /// Stores a frame the codec couldn't compress.
+    ///
+    /// Uncompressed frames decode the fastest, but only fit the player's buffer when they're a few rows tall,
+    /// so taller frames are compressed with LZSS instead.
+    fn fallback(&self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<(Codec, usize)> {
+        if frame.len() > self.max_image_size() {
+            match LzssEncoder.encode(frame, output_buffer) {
+                Ok(size) if size < frame.len() => return Ok((Codec::Lzss, size)),
+                Ok(_) => (),
+                Err(error) => debug!("Frame {} stored uncompressed: {error:#}", self.frame_index),
+            }
+        }
+
+        let size = RawEncoder
+            .encode(frame, output_buffer)
+            .with_context(|| format!("Failed to store frame {}", self.frame_index))?;
+        Ok((Codec::Raw, size))
+    }
+
+    fn advance(&mut self, frame_type: FrameType, displayed_frame: Vec<u8>) {
+        self.frames_since_keyframe = match frame_type {
+            FrameType::Key => 0,
+            FrameType::Delta => self.frames_since_keyframe + 1,
+        };
+        self.frame_index += 1;
+        self.previous_frame = Some(displayed_frame);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::LCD_HEIGHT;
+
+    /// A deterministic source of noise.
+    pub(crate) fn lcg(seed: u32) -> impl Iterator<Item = u32> {
+        std::iter::successors(Some(seed), |state| {
+            Some(state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223))
+        })
+        .map(|state| state >> 16)
+    }
+
+    /// A small square moving over a static background.
+    pub(crate) fn moving_square_frames(count: u32) -> Vec<Vec<u8>> {
+        let width = u32::from(LCD_WIDTH);
+        let height = u32::from(LCD_HEIGHT);
+
+        (0..count)
+            .map(|frame_index| {
+                let square_x = frame_index * 7 % (width - 16);
+                let square_y = frame_index * 3 % (height - 16);
+
+                (0..height)
+                    .flat_map(|y| (0..width).map(move |x| (x, y)))
+                    .map(|(x, y)| {
+                        if (square_x..square_x + 16).contains(&x)
+                            && (square_y..square_y + 16).contains(&y)
+                        {
+                            0xE0
+                        } else {
+                            ((x / 16 + y / 16) % 4) as u8
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Noise that QOI can only write as literals, doubling its size.
+    pub(crate) fn grain_frames(count: u32, height: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|frame_index| {
+                lcg(frame_index)
+                    .take(LCD_WIDTH as usize * height)
+                    .map(|sample| sample as u8)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// A moving square with film grain flipping the lowest bits of each pixel.
+    pub(crate) fn grainy_frames(count: u32) -> Vec<Vec<u8>> {
+        moving_square_frames(count)
+            .into_iter()
+            .zip(0..)
+            .map(|(frame, seed)| {
+                frame
+                    .into_iter()
+                    .zip(lcg(seed + 0x6A1))
+                    .map(|(pixel, grain)| pixel ^ (grain % 2) as u8)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Columns of noise, so every row matches the one above.
+    pub(crate) fn vertical_frame(height: usize) -> Vec<u8> {
+        let row = lcg(0xC01)
+            .take(LCD_WIDTH.into())
+            .map(|sample| sample as u8)
+            .collect::<Vec<_>>();
+        row.repeat(height)
+    }
+
+    pub(crate) fn qoi_encode(frame: &[u8]) -> Vec<u8> {
+        let mut output_buffer = vec![0; LCD_WIDTH as usize * LCD_HEIGHT as usize];
+        let bytes = QoiEncoder::default()
+            .encode(frame, &mut output_buffer)
+            .unwrap();
+        output_buffer.truncate(bytes);
+        output_buffer
+    }
+
+    #[test]
+    fn qoi_skip_long() {
+        let frame = vec![7; 200];
+        let mut output = vec![0; 3];
+        let bytes = QoiEncoder::delta(&frame)
+            .encode(&frame, &mut output)
+            .unwrap();
+
+        let expected = vec![0b0111_1111, 2, 0b0100_0111];
+        assert_eq!(bytes, expected.len());
+        assert_eq!(output, expected);
+    }
+
+    /// Diffs reach 16 either way from the previous pixel, wrapping around.
+    #[test]
+    fn qoi_diff_range() {
+        let frame = [16, 1, 241, 2];
+        let mut output = vec![0; 5];
+        let bytes = QoiEncoder::default().encode(&frame, &mut output).unwrap();
+
+        let expected = vec![15, 17, 16, QOI_TAG_LITERAL, 2];
+        assert_eq!(bytes, expected.len());
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn qoi_overflow_is_an_error() {
+        let frame = grain_frames(1, 8).remove(0);
+        let mut output_buffer = vec![0; frame.len()];
+        assert!(
+            QoiEncoder::default()
+                .encode(&frame, &mut output_buffer)
+                .is_err()
+        );
+    }
+
+    /// Frames that can't fit in the player's buffer error instead of panicking.
+    #[test]
+    fn raw_fallback_too_large() {
+        let frame = grain_frames(1, LCD_HEIGHT.into()).remove(0);
+        let mut output_buffer = vec![0; frame.len()];
+        let error = PictureEncoder::new(100, 0.5, [])
+            .encode(frame, &mut output_buffer)
+            .unwrap_err();
+        assert!(error.to_string().contains("doesn't fit"));
+    }
+
+    #[test]
+    fn raw_only_titles_fit() {
+        let title = |height: u8, codec: &str| {
+            toml::from_str::<TitleDefinition>(&format!(
+                r#"
+                name = "Title"
+                video = "video.mp4"
+                fps = 30
+                height = {height}
+                codec = {codec}
+                "#
+            ))
+            .unwrap()
+        };
+        let registry = registry::CodecRegistry::default();
+
+        assert!(PictureEncoder::from_title(&title(25, r#""raw""#), &registry).is_ok());
+        assert!(PictureEncoder::from_title(&title(120, r#"["qoi", "raw"]"#), &registry).is_ok());
+
+        let error = PictureEncoder::from_title(&title(26, r#"["raw"]"#), &registry)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Title \"Title\" can't use only the `raw` codec at a height of 26; 8320 > 8188 bytes fit in 16 blocks, so use at most 25 rows or add other codecs"
+        );
+    }
+
+    /// Only frames that don't build on how the last frame was encoded can be split off.
+    #[test]
+    fn fork_only_independent_frames() {
+        let registry = registry::CodecRegistry::default();
+        let options = registry::CodecOptions::default();
+        let frame = vec![0; 16];
+        let forks = |picture_encoder: fn() -> PictureEncoder| {
+            ["qoi", "motion", "vq"].map(|codec| {
+                picture_encoder()
+                    .with_encoder(registry.create(codec, &options).unwrap())
+                    .fork(&frame)
+                    .is_some()
+            })
+        };
+
+        assert_eq!(
+            forks(|| PictureEncoder::new(100, 0.5, [])),
+            [true, false, false]
+        );
+        assert_eq!(
+            forks(|| {
+                PictureEncoder::new(100, 0.5, []).with_near_lossless(NearLosslessSettings {
+                    tolerance: 1,
+                    target_size: None,
+                })
+            }),
+            [false; 3]
+        );
+        assert_eq!(
+            forks(|| {
+                PictureEncoder::new(100, 0.5, []).with_rate_control(RateSettings {
+                    bytes_per_frame: 4096,
+                    look_ahead: 0,
+                    max_tolerance: 0,
+                })
+            }),
+            [false; 3]
+        );
+    }
+
+    #[test]
+    fn max_blocks_error_names_frame() {
+        let frame = grain_frames(1, 200).remove(0);
+        let mut output_buffer = vec![0; frame.len()];
+        let error = PictureEncoder::new(100, 0.5, [])
+            .with_timing(30, Duration::from_secs(61))
+            .encode(frame, &mut output_buffer)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Frame 0 at 00:01:01.000 doesn't fit in 16 blocks; 62113 > 8188 bytes"
+        );
+    }
+
+    #[test]
+    fn near_lossless_stays_within_tolerance() {
+        let frame = grainy_frames(1).remove(0);
+        let processed_frame = QoiEncoder::default().near_lossless(&frame, 1);
+
+        assert!(
+            frame
+                .iter()
+                .zip(&processed_frame)
+                .all(|(&pixel, &processed)| color_distance(pixel, processed) <= 1)
+        );
+        assert!(qoi_encode(&processed_frame).len() < qoi_encode(&frame).len() / 2);
+    }
+
+    #[test]
+    fn qoi_up_compresses_columns() {
+        let frame = vertical_frame(24);
+        let mut output_buffer = vec![0; frame.len() * 2];
+        let unaligned = QoiEncoder::unaligned()
+            .encode(&frame, &mut output_buffer)
+            .unwrap();
+
+        assert!(qoi_encode(&frame).len() < unaligned / 4);
+    }
+
+    #[test]
+    fn qoi_optimal_parse_compresses() {
+        let mut frames = moving_square_frames(3);
+        frames.extend(grainy_frames(2));
+        frames.push(vertical_frame(LCD_HEIGHT.into()));
+
+        let mut saved = 0;
+
+        for (index, frame) in frames.iter().enumerate() {
+            let reference_frame = index
+                .checked_sub(1)
+                .map(|previous| frames[previous].as_slice());
+            let encoder = reference_frame.map_or_else(QoiEncoder::default, QoiEncoder::delta);
+            let mut output_buffer = vec![0; frame.len() * 2];
+
+            let greedy = encoder.clone().encode(frame, &mut output_buffer).unwrap();
+            let optimal = encoder
+                .with_optimal_parse(true)
+                .encode(frame, &mut output_buffer)
+                .unwrap();
+
+            assert!(optimal <= greedy, "Frame {index}: {optimal} > {greedy}");
+            saved += greedy - optimal;
+        }
+
+        assert!(saved > 0);
+    }
+}
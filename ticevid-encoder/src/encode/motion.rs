@@ -0,0 +1,424 @@
+use anyhow::Context;
+use log::trace;
+
+use crate::encode::{FrameEncoder, QoiEncoder};
+
+pub const MOTION_TAG_SKIP: u8 = 0b0000_0000;
+pub const MOTION_TAG_MOVE: u8 = 0b0100_0000;
+pub const MOTION_TAG_PATCH: u8 = 0b0100_0001;
+pub const MOTION_TAG_INTRA: u8 = 0b0100_0010;
+
+/// The width and height of a block in pixels.
+pub const MOTION_BLOCK_SIZE: usize = 8;
+/// Motion vectors are stored as 4-bit signed values.
+pub const MOTION_MAX_SEARCH_RANGE: u8 = 7;
+const MOTION_MAX_SKIP: usize = 64;
+/// How many times to trade size for decode speed before giving up.
+const MOTION_MAX_ATTEMPTS: i32 = 10;
+
+// Rough eZ80 cycle costs of the player's decoder.
+// Used to keep motion frames from taking longer to decode than a frame lasts.
+const CYCLES_TAG: u32 = 60;
+const CYCLES_ROW: u32 = 40;
+const CYCLES_PIXEL_COPY: u32 = 3;
+const CYCLES_PATCH_ROW: u32 = 80;
+const CYCLES_PATCH_PIXEL: u32 = 20;
+const CYCLES_QOI_BYTE: u32 = 120;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MotionSettings {
+    /// How many pixels in each direction to search for a matching block.
+    pub search_range: u8,
+    /// The most cycles the player may spend decoding a frame.
+    pub max_decode_cycles: u32,
+}
+
+impl Default for MotionSettings {
+    fn default() -> Self {
+        Self {
+            search_range: MOTION_MAX_SEARCH_RANGE,
+            max_decode_cycles: u32::MAX,
+        }
+    }
+}
+
+/// Block based motion compensation.
+///
+/// Frames are split into 8x8 blocks.
+/// Each block is either unchanged, copied from elsewhere in the reference frame,
+/// copied then patched with the pixels that differ, or replaced with QOI data.
+pub struct MotionEncoder<'a> {
+    reference_frame: &'a [u8],
+    width: usize,
+    settings: MotionSettings,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Block {
+    fn pixels(self) -> usize {
+        self.width * self.height
+    }
+
+    fn rows(self, frame: &[u8], frame_width: usize) -> impl Iterator<Item = &[u8]> {
+        (self.y..self.y + self.height).map(move |y| {
+            let start = y * frame_width + self.x;
+            &frame[start..start + self.width]
+        })
+    }
+}
+
+/// The best motion vector found for a block.
+#[derive(Debug, Clone, Copy)]
+struct BlockSearch {
+    block: Block,
+    /// Unchanged from the reference frame.
+    unchanged: bool,
+    vector: (i8, i8),
+    /// How many pixels differ after applying the motion vector.
+    mismatches: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockMode {
+    Move,
+    Patch,
+    Intra,
+}
+
+impl<'a> MotionEncoder<'a> {
+    pub fn new(reference_frame: &'a [u8], width: usize, settings: MotionSettings) -> Self {
+        Self {
+            reference_frame,
+            width,
+            settings,
+        }
+    }
+
+    /// Counts how many pixels differ, giving up once `limit` is reached.
+    fn mismatches(&self, frame: &[u8], block: Block, vector: (i8, i8), limit: usize) -> usize {
+        let source = Block {
+            x: block.x.strict_add_signed(vector.0.into()),
+            y: block.y.strict_add_signed(vector.1.into()),
+            ..block
+        };
+
+        let mut mismatches = 0;
+
+        for (row, source_row) in block
+            .rows(frame, self.width)
+            .zip(source.rows(self.reference_frame, self.width))
+        {
+            mismatches += row
+                .iter()
+                .zip(source_row)
+                .filter(|(pixel, source)| pixel != source)
+                .count();
+
+            if mismatches >= limit {
+                break;
+            }
+        }
+
+        mismatches
+    }
+
+    fn search(&self, frame: &[u8], block: Block, height: usize) -> BlockSearch {
+        let unchanged = self.mismatches(frame, block, (0, 0), 1) == 0;
+
+        let mut search = BlockSearch {
+            block,
+            unchanged,
+            vector: (0, 0),
+            mismatches: self.mismatches(frame, block, (0, 0), usize::MAX),
+        };
+
+        if unchanged {
+            return search;
+        }
+
+        let range = i8::try_from(self.settings.search_range.min(MOTION_MAX_SEARCH_RANGE))
+            .unwrap_or_default();
+
+        for dy in -range..=range {
+            for dx in -range..=range {
+                let in_bounds = block
+                    .x
+                    .checked_add_signed(dx.into())
+                    .is_some_and(|x| x + block.width <= self.width)
+                    && block
+                        .y
+                        .checked_add_signed(dy.into())
+                        .is_some_and(|y| y + block.height <= height);
+
+                if !in_bounds || (dx, dy) == (0, 0) {
+                    continue;
+                }
+
+                let mismatches = self.mismatches(frame, block, (dx, dy), search.mismatches);
+
+                if mismatches < search.mismatches {
+                    search.vector = (dx, dy);
+                    search.mismatches = mismatches;
+
+                    if mismatches == 0 {
+                        return search;
+                    }
+                }
+            }
+        }
+
+        search
+    }
+
+    fn write_vector(vector: (i8, i8), output: &mut Vec<u8>) {
+        let (dx, dy) = vector;
+        output.push((dx.cast_unsigned() << 4) | (dy.cast_unsigned() & 0x0F));
+    }
+
+    fn write_skips(skips: &mut usize, output: &mut Vec<u8>) -> u32 {
+        let mut cycles = 0;
+
+        while *skips > 0 {
+            let run = (*skips).min(MOTION_MAX_SKIP);
+            output.push(MOTION_TAG_SKIP | (run - 1) as u8);
+            *skips -= run;
+            cycles += CYCLES_TAG;
+        }
+
+        cycles
+    }
+
+    fn write_patch(&self, frame: &[u8], search: BlockSearch, output: &mut Vec<u8>) {
+        let BlockSearch { block, vector, .. } = search;
+        let source = Block {
+            x: block.x.strict_add_signed(vector.0.into()),
+            y: block.y.strict_add_signed(vector.1.into()),
+            ..block
+        };
+
+        for (row, source_row) in block
+            .rows(frame, self.width)
+            .zip(source.rows(self.reference_frame, self.width))
+        {
+            let mask_index = output.len();
+            output.push(0);
+
+            for (column, (&pixel, &source)) in row.iter().zip(source_row).enumerate() {
+                if pixel != source {
+                    output[mask_index] |= 0x80 >> column;
+                    output.push(pixel);
+                }
+            }
+        }
+    }
+
+    /// Chooses how to encode each block, minimizing `bytes + lambda * cycles`.
+    ///
+    /// Returns the encoded frame and its estimated decode cycles.
+    fn encode_blocks(
+        &self,
+        frame: &[u8],
+        searches: &[BlockSearch],
+        lambda: f32,
+    ) -> anyhow::Result<(Vec<u8>, u32)> {
+        let mut output = Vec::new();
+        let mut cycles = 0;
+        let mut skips = 0;
+
+        let mut intra = QoiEncoder::unaligned();
+        let mut intra_buffer = [0; MOTION_BLOCK_SIZE * MOTION_BLOCK_SIZE * 2];
+
+        for &search in searches {
+            let block = search.block;
+
+            if search.unchanged {
+                skips += 1;
+                continue;
+            }
+
+            let rows = block.height as u32;
+            let pixels = block.pixels() as u32;
+            let copy_cycles = CYCLES_TAG + rows * CYCLES_ROW + pixels * CYCLES_PIXEL_COPY;
+
+            let block_pixels = block
+                .rows(frame, self.width)
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>();
+            let mut intra_trial = intra.clone();
+            intra_trial.continue_output();
+            let intra_bytes = intra_trial.encode(&block_pixels, &mut intra_buffer)?;
+
+            let intra_candidate = (
+                BlockMode::Intra,
+                1 + intra_bytes,
+                copy_cycles + intra_bytes as u32 * CYCLES_QOI_BYTE,
+            );
+
+            let motion_candidate = if search.mismatches == 0 {
+                (BlockMode::Move, 2, copy_cycles)
+            } else {
+                (
+                    BlockMode::Patch,
+                    2 + block.height + search.mismatches,
+                    copy_cycles
+                        + rows * CYCLES_PATCH_ROW
+                        + search.mismatches as u32 * CYCLES_PATCH_PIXEL,
+                )
+            };
+
+            let cost =
+                |(_, bytes, cycles): (BlockMode, usize, u32)| bytes as f32 + lambda * cycles as f32;
+
+            let (mode, _, block_cycles) = if cost(motion_candidate) <= cost(intra_candidate) {
+                motion_candidate
+            } else {
+                intra_candidate
+            };
+
+            cycles += Self::write_skips(&mut skips, &mut output) + block_cycles;
+
+            match mode {
+                BlockMode::Move => {
+                    output.push(MOTION_TAG_MOVE);
+                    Self::write_vector(search.vector, &mut output);
+                }
+                BlockMode::Patch => {
+                    output.push(MOTION_TAG_PATCH);
+                    Self::write_vector(search.vector, &mut output);
+                    self.write_patch(frame, search, &mut output);
+                }
+                BlockMode::Intra => {
+                    output.push(MOTION_TAG_INTRA);
+                    output.extend_from_slice(&intra_buffer[..intra_bytes]);
+                    intra = intra_trial;
+                }
+            }
+        }
+
+        // Trailing skips are implied by the end of the image
+
+        Ok((output, cycles))
+    }
+}
+
+impl FrameEncoder for MotionEncoder<'_> {
+    fn encode(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
+        anyhow::ensure!(
+            self.reference_frame.len() == frame.len(),
+            "Reference frame size doesn't match; {} != {}",
+            self.reference_frame.len(),
+            frame.len()
+        );
+        anyhow::ensure!(
+            frame.len().is_multiple_of(self.width),
+            "Frame isn't a whole number of rows; {} % {} != 0",
+            frame.len(),
+            self.width
+        );
+
+        let height = frame.len() / self.width;
+
+        let mut searches = Vec::new();
+
+        for y in (0..height).step_by(MOTION_BLOCK_SIZE) {
+            for x in (0..self.width).step_by(MOTION_BLOCK_SIZE) {
+                let block = Block {
+                    x,
+                    y,
+                    width: MOTION_BLOCK_SIZE.min(self.width - x),
+                    height: MOTION_BLOCK_SIZE.min(height - y),
+                };
+
+                searches.push(self.search(frame, block, height));
+            }
+        }
+
+        // Trade size for decode speed until the frame fits the budget
+        let mut lambda = 0.0;
+        let mut attempts = 0;
+        let (output, cycles) = loop {
+            let (output, cycles) = self.encode_blocks(frame, &searches, lambda)?;
+
+            if cycles <= self.settings.max_decode_cycles {
+                break (output, cycles);
+            }
+
+            attempts += 1;
+
+            anyhow::ensure!(
+                attempts < MOTION_MAX_ATTEMPTS,
+                "Motion frame can't be decoded in time; {cycles} > {} cycles",
+                self.settings.max_decode_cycles
+            );
+
+            lambda = 0.001 * 4.0f32.powi(attempts - 1);
+        };
+
+        trace!("Motion frame: {} bytes, {cycles} cycles", output.len());
+
+        output_buffer
+            .get_mut(..output.len())
+            .with_context(|| format!("Motion frame overflowed; {} bytes", output.len()))?
+            .copy_from_slice(&output);
+
+        Ok(output.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        LCD_WIDTH,
+        encode::{QoiEncoder, tests::lcg},
+    };
+
+    const WIDTH: usize = LCD_WIDTH as usize;
+
+    /// Blocky noise, shifted left by `shift` pixels.
+    fn texture(shift: usize) -> Vec<u8> {
+        let texture = lcg(0xBEEF).take(64 * 64).collect::<Vec<_>>();
+
+        (0..WIDTH * 64)
+            .map(|pixel| {
+                let x = (pixel % WIDTH + shift) / 2 % 64;
+                let y = pixel / WIDTH / 2;
+                (texture[y * 64 + x] % 4) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_frame_is_empty() {
+        let frame = texture(0);
+        let mut output_buffer = vec![0; frame.len()];
+        let bytes = MotionEncoder::new(&frame, WIDTH, MotionSettings::default())
+            .encode(&frame, &mut output_buffer)
+            .unwrap();
+        assert_eq!(bytes, 0);
+    }
+
+    #[test]
+    fn panned_frame_beats_qoi() {
+        let reference_frame = texture(0);
+        let frame = texture(3);
+        let mut output_buffer = vec![0; frame.len() * 2];
+
+        let motion = MotionEncoder::new(&reference_frame, WIDTH, MotionSettings::default())
+            .encode(&frame, &mut output_buffer)
+            .unwrap();
+        let qoi = QoiEncoder::delta(&reference_frame)
+            .encode(&frame, &mut output_buffer)
+            .unwrap();
+
+        assert!(motion < qoi / 2, "{motion} >= {qoi} / 2");
+    }
+}
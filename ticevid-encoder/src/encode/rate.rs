@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+
+use crate::{
+    BLOCK_SIZE,
+    encode::{FrameEncoder, PICTURE_CHUNK_HEADER_SIZE, QoiEncoder},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateSettings {
+    /// The most bytes the player should read each frame on average.
+    pub bytes_per_frame: usize,
+    /// How many upcoming frames to spread the budget over.
+    pub look_ahead: usize,
+    /// The most near lossless tolerance to use to stay under the budget.
+    pub max_tolerance: u8,
+}
+
+/// Keeps the blocks read each frame under a budget.
+///
+/// Each frame's share of the budget is weighed against a quick estimate of how large the next few frames are,
+/// so simple frames leave room for complex frames soon after.
+/// Frames over their share are made coarser by [`PictureEncoder`](crate::encode::PictureEncoder).
+pub struct RateController {
+    settings: RateSettings,
+    /// Estimated sizes of the frames waiting to be encoded, starting with the next.
+    window: VecDeque<usize>,
+    /// The last frame given to [`Self::look_ahead`].
+    previous_frame: Option<Vec<u8>>,
+    /// Bytes read beyond the budget so far. Negative when under.
+    debt: i64,
+}
+
+impl RateController {
+    pub fn new(settings: RateSettings) -> Self {
+        Self {
+            settings,
+            window: VecDeque::with_capacity(settings.look_ahead + 1),
+            previous_frame: None,
+            debt: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &RateSettings {
+        &self.settings
+    }
+
+    /// Estimates the size of an upcoming frame.
+    ///
+    /// Frames have to be given in order, up to [`RateSettings::look_ahead`] frames before they're encoded.
+    pub fn look_ahead(&mut self, frame: &[u8]) {
+        let mut output_buffer = vec![0; frame.len() * 2];
+        let encoded = match &self.previous_frame {
+            Some(previous_frame) if previous_frame.len() == frame.len() => {
+                QoiEncoder::delta(previous_frame)
+            }
+            _ => QoiEncoder::default(),
+        }
+        .encode(frame, &mut output_buffer);
+
+        // Output is twice the size of a frame, so only fails on mismatched frames
+        let size = encoded.unwrap_or(frame.len());
+
+        self.window.push_back(size.max(1));
+        self.previous_frame = Some(frame.to_vec());
+    }
+
+    /// The most bytes the next frame's image should take up.
+    pub fn target_size(&self) -> usize {
+        let block_size = i64::from(BLOCK_SIZE);
+        let budget = self.settings.bytes_per_frame as i64;
+        let window = self.window.len().max(1) as i64;
+        let estimate = self.window.front().copied().unwrap_or(1) as i64;
+        let total_estimate = self.window.iter().sum::<usize>().max(1) as i64;
+
+        let available = window * budget - self.debt;
+        let share = available * estimate / total_estimate;
+
+        // Chunks are read in whole blocks, so there's no point aiming between them
+        let blocks = (share / block_size).max(1);
+        (blocks * block_size) as usize - PICTURE_CHUNK_HEADER_SIZE
+    }
+
+    /// Records the size of the next frame's image once encoded.
+    pub fn update(&mut self, size: usize) {
+        let block_size = usize::from(BLOCK_SIZE);
+        let read = (size + PICTURE_CHUNK_HEADER_SIZE).div_ceil(block_size) * block_size;
+        let budget = self.settings.bytes_per_frame as i64;
+
+        // Savings can only be spent within the look-ahead window
+        let min_debt = -(self.settings.look_ahead as i64) * budget;
+        self.debt = (self.debt + read as i64 - budget).max(min_debt);
+        self.window.pop_front();
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use log::debug;
+use serde::Deserialize;
+
+use crate::{
+    BLOCKS_PER_CHUNK, LCD_WIDTH,
+    encode::{
+        Codec, FrameEncoder, LzssEncoder, QoiEncoder, QoiSettings, RawEncoder, max_image_size,
+        motion::{MotionEncoder, MotionSettings},
+        vq::{VqEncoder, VqSettings},
+    },
+};
+
+/// Settings shared by every codec of a title.
+#[derive(Debug, Clone, Copy)]
+pub struct CodecOptions {
+    /// The width of each frame in pixels.
+    pub width: usize,
+    /// The most bytes a frame's image may take up in the player's buffer.
+    pub max_image_size: usize,
+    pub qoi: QoiSettings,
+    pub motion: MotionSettings,
+    pub vq: VqSettings,
+}
+
+impl Default for CodecOptions {
+    fn default() -> Self {
+        Self {
+            width: LCD_WIDTH.into(),
+            max_image_size: max_image_size(BLOCKS_PER_CHUNK),
+            qoi: QoiSettings::default(),
+            motion: MotionSettings::default(),
+            vq: VqSettings::default(),
+        }
+    }
+}
+
+/// Encodes a title's frames with a single codec, keeping any state it needs between frames.
+pub trait TitleEncoder: Send {
+    /// `reference_frame` is what the player displays before this frame, or `None` for keyframes.
+    ///
+    /// Returns the codec used and the amount of bytes written.
+    fn encode(
+        &mut self,
+        frame: &[u8],
+        reference_frame: Option<&[u8]>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<(Codec, usize)>;
+
+    /// What the player displays after the last encoded frame, if it differs from the frame given.
+    fn reconstruction(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Whether the player keeps state for the codec beyond the previous frame.
+    ///
+    /// These can't be mixed with other codecs, as frames that aren't used would desync the player.
+    fn keeps_state(&self) -> bool {
+        false
+    }
+
+    /// Saves any state kept between frames, before a frame that may be encoded more than once.
+    fn checkpoint(&mut self) {}
+
+    /// Goes back to the state at the last [`Self::checkpoint`], undoing frames encoded since.
+    fn restore(&mut self) {}
+
+    /// A copy of the encoder for encoding frames on other threads,
+    /// if each frame's image only depends on the frame and the one before it.
+    fn fork(&self) -> Option<Box<dyn TitleEncoder>> {
+        None
+    }
+}
+
+/// How [`BestOfEncoder`] picks between codecs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecSelection {
+    /// The smallest image.
+    #[default]
+    Size,
+    /// The image estimated to decode the fastest, then the smallest.
+    ///
+    /// Only images that fit the player's buffer are considered, if any do.
+    DecodeTime,
+}
+
+// Rough eZ80 cycle costs of the player's decoders.
+const CYCLES_RAW_PIXEL: u64 = 3;
+const CYCLES_QOI_BYTE: u64 = 120;
+const CYCLES_QOI_PIXEL: u64 = 10;
+const CYCLES_LZSS_BYTE: u64 = 150;
+const CYCLES_LZSS_PIXEL: u64 = 30;
+const CYCLES_BLOCK_BYTE: u64 = 60;
+const CYCLES_BLOCK_PIXEL: u64 = 4;
+
+/// Estimates how many cycles the player takes to decode an image.
+pub fn decode_cycles(codec: Codec, bytes: usize, pixels: usize) -> u64 {
+    let (byte_cycles, pixel_cycles) = match codec {
+        Codec::Raw => (0, CYCLES_RAW_PIXEL),
+        Codec::Qoi => (CYCLES_QOI_BYTE, CYCLES_QOI_PIXEL),
+        Codec::Lzss => (CYCLES_LZSS_BYTE, CYCLES_LZSS_PIXEL),
+        Codec::Motion | Codec::Vq => (CYCLES_BLOCK_BYTE, CYCLES_BLOCK_PIXEL),
+    };
+
+    bytes as u64 * byte_cycles + pixels as u64 * pixel_cycles
+}
+
+/// Creates a title's [`TitleEncoder`].
+pub type TitleEncoderConstructor = dyn Fn(&CodecOptions) -> Box<dyn TitleEncoder> + Send + Sync;
+
+/// Creates a [`FrameEncoder`] for a single frame.
+///
+/// Given the reference frame of delta frames.
+pub type FrameEncoderConstructor =
+    for<'a> fn(&CodecOptions, Option<&'a [u8]>) -> Box<dyn FrameEncoder + 'a>;
+
+fn qoi_constructor<'a>(
+    options: &CodecOptions,
+    reference_frame: Option<&'a [u8]>,
+) -> Box<dyn FrameEncoder + 'a> {
+    let encoder = match reference_frame {
+        Some(reference_frame) => QoiEncoder::delta(reference_frame),
+        None => QoiEncoder::default(),
+    };
+
+    Box::new(encoder.with_optimal_parse(options.qoi.optimal_parse))
+}
+
+/// A [`FrameEncoder`] that doesn't need state between frames.
+#[derive(Clone, Copy)]
+pub(crate) struct StatelessEncoder {
+    codec: Codec,
+    options: CodecOptions,
+    constructor: FrameEncoderConstructor,
+}
+
+impl StatelessEncoder {
+    pub(crate) fn qoi() -> Self {
+        Self {
+            codec: Codec::Qoi,
+            options: CodecOptions::default(),
+            constructor: qoi_constructor,
+        }
+    }
+}
+
+impl TitleEncoder for StatelessEncoder {
+    fn encode(
+        &mut self,
+        frame: &[u8],
+        reference_frame: Option<&[u8]>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<(Codec, usize)> {
+        let bytes =
+            (self.constructor)(&self.options, reference_frame).encode(frame, output_buffer)?;
+        Ok((self.codec, bytes))
+    }
+
+    fn fork(&self) -> Option<Box<dyn TitleEncoder>> {
+        Some(Box::new(*self))
+    }
+}
+
+/// Keyframes are QOI; delta frames use motion compensation if it's smaller than QOI.
+struct MotionTitleEncoder {
+    options: CodecOptions,
+}
+
+impl TitleEncoder for MotionTitleEncoder {
+    fn encode(
+        &mut self,
+        frame: &[u8],
+        reference_frame: Option<&[u8]>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<(Codec, usize)> {
+        let optimal_parse = self.options.qoi.optimal_parse;
+
+        let Some(reference_frame) = reference_frame else {
+            return Ok((
+                Codec::Qoi,
+                QoiEncoder::default()
+                    .with_optimal_parse(optimal_parse)
+                    .encode(frame, output_buffer)?,
+            ));
+        };
+
+        let qoi = QoiEncoder::delta(reference_frame)
+            .with_optimal_parse(optimal_parse)
+            .encode(frame, output_buffer);
+        let mut motion_buffer = vec![0; output_buffer.len()];
+        let motion = MotionEncoder::new(reference_frame, self.options.width, self.options.motion)
+            .encode(frame, &mut motion_buffer);
+
+        match (qoi, motion) {
+            (Ok(qoi_bytes), Ok(motion_bytes)) if qoi_bytes <= motion_bytes => {
+                Ok((Codec::Qoi, qoi_bytes))
+            }
+            (_, Ok(motion_bytes)) => {
+                output_buffer[..motion_bytes].copy_from_slice(&motion_buffer[..motion_bytes]);
+                Ok((Codec::Motion, motion_bytes))
+            }
+            (Ok(qoi_bytes), Err(error)) => {
+                debug!("Frame fell back to QOI: {error}");
+                Ok((Codec::Qoi, qoi_bytes))
+            }
+            (Err(error), Err(_)) => Err(error),
+        }
+    }
+}
+
+/// Tries every codec on each frame, keeping the best.
+struct BestOfEncoder {
+    encoders: Vec<(String, Box<dyn TitleEncoder>)>,
+    selection: CodecSelection,
+    max_image_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl TitleEncoder for BestOfEncoder {
+    fn encode(
+        &mut self,
+        frame: &[u8],
+        reference_frame: Option<&[u8]>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<(Codec, usize)> {
+        let mut best: Option<((bool, u64, usize), Codec, usize)> = None;
+
+        for (name, encoder) in &mut self.encoders {
+            self.buffer.resize(output_buffer.len(), 0);
+
+            let (codec, bytes) = match encoder.encode(frame, reference_frame, &mut self.buffer) {
+                Ok(encoded) => encoded,
+                Err(error) => {
+                    debug!("Codec `{name}` failed: {error:#}");
+                    continue;
+                }
+            };
+
+            // Images too large to play are only kept to be requantized if nothing fits,
+            // in which case the smallest one has the best chance
+            let too_large = bytes > self.max_image_size;
+            let cycles = match self.selection {
+                CodecSelection::DecodeTime if !too_large => {
+                    decode_cycles(codec, bytes, frame.len())
+                }
+                _ => 0,
+            };
+            let key = (too_large, cycles, bytes);
+
+            if best.is_none_or(|(best_key, ..)| key < best_key) {
+                output_buffer[..bytes].copy_from_slice(&self.buffer[..bytes]);
+                best = Some((key, codec, bytes));
+            }
+        }
+
+        let (_, codec, bytes) = best.context("No codec could encode the frame")?;
+        Ok((codec, bytes))
+    }
+
+    /// Only if every codec can be forked.
+    fn fork(&self) -> Option<Box<dyn TitleEncoder>> {
+        let encoders = self
+            .encoders
+            .iter()
+            .map(|(name, encoder)| Some((name.clone(), encoder.fork()?)))
+            .collect::<Option<_>>()?;
+
+        Some(Box::new(Self {
+            encoders,
+            selection: self.selection,
+            max_image_size: self.max_image_size,
+            buffer: Vec::new(),
+        }))
+    }
+}
+
+impl TitleEncoder for VqEncoder {
+    fn encode(
+        &mut self,
+        frame: &[u8],
+        reference_frame: Option<&[u8]>,
+        output_buffer: &mut [u8],
+    ) -> anyhow::Result<(Codec, usize)> {
+        if reference_frame.is_none() {
+            self.reset();
+        }
+
+        let bytes = FrameEncoder::encode(self, frame, output_buffer)?;
+        Ok((Codec::Vq, bytes))
+    }
+
+    fn reconstruction(&self) -> Option<&[u8]> {
+        VqEncoder::reconstruction(self)
+    }
+
+    /// The codebook carries over between frames.
+    fn keeps_state(&self) -> bool {
+        true
+    }
+
+    fn checkpoint(&mut self) {
+        VqEncoder::checkpoint(self);
+    }
+
+    fn restore(&mut self) {
+        VqEncoder::restore(self);
+    }
+}
+
+/// Maps the names used by [`TitleDefinition::codec`](crate::definition::title::TitleDefinition::codec)
+/// to codecs.
+pub struct CodecRegistry {
+    codecs: BTreeMap<&'static str, Box<TitleEncoderConstructor>>,
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            codecs: BTreeMap::new(),
+        };
+
+        registry.register_stateless("qoi", Codec::Qoi, qoi_constructor);
+        registry.register_stateless("raw", Codec::Raw, |_, _| Box::new(RawEncoder));
+        registry.register_stateless("lzss", Codec::Lzss, |_, _| Box::new(LzssEncoder));
+        registry.register("motion", |options| {
+            Box::new(MotionTitleEncoder { options: *options })
+        });
+        registry.register("vq", |options| {
+            Box::new(VqEncoder::new(options.width, options.vq))
+        });
+
+        registry
+    }
+}
+
+impl CodecRegistry {
+    /// Adds a codec, replacing any with the same name.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        constructor: impl Fn(&CodecOptions) -> Box<dyn TitleEncoder> + Send + Sync + 'static,
+    ) {
+        self.codecs.insert(name, Box::new(constructor));
+    }
+
+    /// Adds a codec that encodes each frame on its own.
+    pub fn register_stateless(
+        &mut self,
+        name: &'static str,
+        codec: Codec,
+        constructor: FrameEncoderConstructor,
+    ) {
+        self.register(name, move |options| {
+            Box::new(StatelessEncoder {
+                codec,
+                options: *options,
+                constructor,
+            })
+        });
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        self.codecs.keys().copied()
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        options: &CodecOptions,
+    ) -> anyhow::Result<Box<dyn TitleEncoder>> {
+        let constructor = self.codecs.get(name).with_context(|| {
+            format!(
+                "Unknown codec `{name}`; expected one of: {}",
+                self.names().collect::<Vec<_>>().join(", ")
+            )
+        })?;
+
+        Ok(constructor(options))
+    }
+
+    /// Creates an encoder that uses whichever of the codecs is best for each frame.
+    pub fn create_best(
+        &self,
+        names: &[impl AsRef<str>],
+        selection: CodecSelection,
+        options: &CodecOptions,
+    ) -> anyhow::Result<Box<dyn TitleEncoder>> {
+        if let [name] = names {
+            return self.create(name.as_ref(), options);
+        }
+
+        anyhow::ensure!(!names.is_empty(), "No codecs to choose from");
+
+        let encoders = names
+            .iter()
+            .map(|name| {
+                let name = name.as_ref();
+                let encoder = self.create(name, options)?;
+
+                anyhow::ensure!(
+                    !encoder.keeps_state(),
+                    "Codec `{name}` keeps state between frames, so it can't be mixed with others"
+                );
+
+                Ok((name.to_string(), encoder))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Box::new(BestOfEncoder {
+            encoders,
+            selection,
+            max_image_size: options.max_image_size,
+            buffer: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_codec() {
+        let error = CodecRegistry::default()
+            .create("h264", &CodecOptions::default())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("lzss"));
+    }
+
+    #[test]
+    fn best_of_rejects_stateful_codecs() {
+        let error = CodecRegistry::default()
+            .create_best(
+                &["qoi", "vq"],
+                CodecSelection::Size,
+                &CodecOptions::default(),
+            )
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("vq"));
+    }
+}
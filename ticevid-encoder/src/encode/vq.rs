@@ -0,0 +1,571 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use log::trace;
+
+use crate::encode::FrameEncoder;
+
+pub const VQ_TAG_SKIP: u8 = 0b0000_0000;
+pub const VQ_TAG_V1: u8 = 0b0100_0000;
+pub const VQ_TAG_V4: u8 = 0b1000_0000;
+
+/// The width and height of a macroblock in pixels.
+pub const VQ_BLOCK_SIZE: usize = 4;
+/// The most entries a codebook can have.
+pub const VQ_CODEBOOK_SIZE: usize = 256;
+const VQ_MAX_RUN: usize = 64;
+
+/// A 2x2 block of pixels; top left, top right, bottom left, then bottom right.
+type Vector = [u8; 4];
+/// A vector's pixels split into 8-bit red, green, and blue.
+type ExpandedVector = [i32; 12];
+
+#[derive(Debug, Clone, Copy)]
+pub struct VqSettings {
+    /// How many codebook entries to use. At most `256`.
+    pub codebook_size: u16,
+    /// How many times to refine the codebook each frame.
+    pub iterations: u8,
+}
+
+impl Default for VqSettings {
+    fn default() -> Self {
+        Self {
+            codebook_size: VQ_CODEBOOK_SIZE as u16,
+            iterations: 4,
+        }
+    }
+}
+
+/// Cinepak style vector quantization.
+///
+/// Frames are split into 4x4 macroblocks.
+/// Each macroblock is either unchanged, one codebook entry scaled up (V1),
+/// or four codebook entries, one per 2x2 quadrant (V4).
+/// The codebook carries over between frames; each frame only sends the entries that changed.
+///
+/// Lossy unless the frame has no more unique 2x2 blocks than codebook entries.
+pub struct VqEncoder {
+    width: usize,
+    settings: VqSettings,
+    codebook: [Vector; VQ_CODEBOOK_SIZE],
+    /// What the player displays after the last encoded frame.
+    /// The next frame is a keyframe if unset.
+    reconstruction: Option<Vec<u8>>,
+    /// The codebook and reconstruction [`Self::restore`] goes back to.
+    checkpoint: Option<([Vector; VQ_CODEBOOK_SIZE], Option<Vec<u8>>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Macroblock {
+    Skip,
+    V1(u8),
+    V4([u8; 4]),
+}
+
+impl Macroblock {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Skip => VQ_TAG_SKIP,
+            Self::V1(_) => VQ_TAG_V1,
+            Self::V4(_) => VQ_TAG_V4,
+        }
+    }
+}
+
+/// Splits a pixel made by `compress_color_space` into 8-bit red, green, and blue.
+fn expand_pixel(pixel: u8) -> [i32; 3] {
+    let red = i32::from(pixel >> 5);
+    let blue = i32::from((pixel >> 3) & 0b11);
+    let green = i32::from(pixel & 0b111);
+    [red * 255 / 7, green * 255 / 7, blue * 255 / 3]
+}
+
+fn compress_pixel(channels: [i32; 3]) -> u8 {
+    let [red, green, blue] = channels.map(|channel| channel.clamp(0, 255));
+    let red = ((red * 7 + 127) / 255) as u8;
+    let green = ((green * 7 + 127) / 255) as u8;
+    let blue = ((blue * 3 + 127) / 255) as u8;
+    (red << 5) | (blue << 3) | green
+}
+
+fn expand(vector: Vector) -> ExpandedVector {
+    let mut expanded = [0; 12];
+
+    for (channels, pixel) in expanded.chunks_exact_mut(3).zip(vector) {
+        channels.copy_from_slice(&expand_pixel(pixel));
+    }
+
+    expanded
+}
+
+fn distance(a: &ExpandedVector, b: &ExpandedVector) -> i32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+fn nearest(codebook: &[ExpandedVector], vector: &ExpandedVector) -> (u8, i32) {
+    codebook
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (index as u8, distance(entry, vector)))
+        .min_by_key(|&(_, distance)| distance)
+        .unwrap_or_default()
+}
+
+/// Remembers the nearest codebook entry to each vector searched this frame.
+struct NearestCache<'a> {
+    codebook: &'a [ExpandedVector],
+    cache: HashMap<ExpandedVector, (u8, i32)>,
+}
+
+impl<'a> NearestCache<'a> {
+    fn new(codebook: &'a [ExpandedVector]) -> Self {
+        Self {
+            codebook,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn nearest(&mut self, vector: &ExpandedVector) -> (u8, i32) {
+        *self
+            .cache
+            .entry(*vector)
+            .or_insert_with(|| nearest(self.codebook, vector))
+    }
+}
+
+impl VqEncoder {
+    pub fn new(width: usize, settings: VqSettings) -> Self {
+        Self {
+            width,
+            settings,
+            codebook: [Vector::default(); VQ_CODEBOOK_SIZE],
+            reconstruction: None,
+            checkpoint: None,
+        }
+    }
+
+    /// Saves the state before encoding a frame, so the frame can be encoded again.
+    pub fn checkpoint(&mut self) {
+        self.checkpoint = Some((self.codebook, self.reconstruction.clone()));
+    }
+
+    /// Goes back to the last checkpoint, as if nothing since had been encoded.
+    pub fn restore(&mut self) {
+        if let Some((codebook, reconstruction)) = &self.checkpoint {
+            self.codebook = *codebook;
+            self.reconstruction.clone_from(reconstruction);
+        }
+    }
+
+    /// Makes the next frame a keyframe, which starts with an empty codebook.
+    pub fn reset(&mut self) {
+        self.reconstruction = None;
+    }
+
+    /// What the player displays after the last encoded frame.
+    pub fn reconstruction(&self) -> Option<&[u8]> {
+        self.reconstruction.as_deref()
+    }
+
+    fn codebook_size(&self) -> usize {
+        usize::from(self.settings.codebook_size).clamp(1, VQ_CODEBOOK_SIZE)
+    }
+
+    /// A 2x2 block of the frame. Rows past the bottom repeat the last row.
+    fn vector(&self, frame: &[u8], height: usize, x: usize, y: usize) -> Vector {
+        let pixel = |x: usize, y: usize| frame[y.min(height - 1) * self.width + x];
+        [
+            pixel(x, y),
+            pixel(x + 1, y),
+            pixel(x, y + 1),
+            pixel(x + 1, y + 1),
+        ]
+    }
+
+    fn quadrants(&self, frame: &[u8], height: usize, x: usize, y: usize) -> [Vector; 4] {
+        [(0, 0), (2, 0), (0, 2), (2, 2)].map(|(dx, dy)| self.vector(frame, height, x + dx, y + dy))
+    }
+
+    /// Refines the codebook with k-means.
+    /// Keyframes start from the most common vectors, delta frames from the previous codebook.
+    fn train(&mut self, vectors: &HashMap<Vector, u32>, keyframe: bool) {
+        let codebook_size = self.codebook_size();
+
+        // Most common first, so small frames get an exact codebook
+        let mut vectors = vectors
+            .iter()
+            .map(|(&vector, &weight)| (vector, expand(vector), weight))
+            .collect::<Vec<_>>();
+        vectors.sort_unstable_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+
+        let codebook = &mut self.codebook[..codebook_size];
+
+        if vectors.len() <= codebook_size {
+            // Keep entries that are still needed to send fewer updates
+            let mut unkept = vectors
+                .iter()
+                .map(|(vector, _, _)| *vector)
+                .collect::<HashSet<_>>();
+            let free = codebook
+                .iter()
+                .map(|entry| !unkept.remove(entry))
+                .collect::<Vec<_>>();
+            let mut missing = vectors
+                .iter()
+                .map(|(vector, _, _)| *vector)
+                .filter(|vector| unkept.contains(vector));
+
+            for (entry, free) in codebook.iter_mut().zip(free) {
+                if free && let Some(vector) = missing.next() {
+                    *entry = vector;
+                }
+            }
+
+            return;
+        }
+
+        if keyframe {
+            for (entry, (vector, _, _)) in codebook.iter_mut().zip(&vectors) {
+                *entry = *vector;
+            }
+        }
+
+        for iteration in 0..self.settings.iterations {
+            let expanded_codebook = codebook.iter().copied().map(expand).collect::<Vec<_>>();
+            let mut sums = vec![[0i64; 12]; codebook_size];
+            let mut weights = vec![0i64; codebook_size];
+            let mut errors = Vec::with_capacity(vectors.len());
+
+            for (vector, expanded, weight) in &vectors {
+                let (index, error) = nearest(&expanded_codebook, expanded);
+                let index = usize::from(index);
+
+                for (sum, channel) in sums[index].iter_mut().zip(expanded) {
+                    *sum += i64::from(*channel) * i64::from(*weight);
+                }
+
+                weights[index] += i64::from(*weight);
+                errors.push((i64::from(error) * i64::from(*weight), *vector));
+            }
+
+            // Entries nothing maps to are replaced with the worst represented vectors
+            errors.sort_unstable_by(|a, b| b.cmp(a));
+            let mut worst = errors.into_iter().filter(|(error, _)| *error > 0);
+
+            for (entry, (sum, weight)) in codebook.iter_mut().zip(sums.into_iter().zip(weights)) {
+                if weight == 0 {
+                    if let Some((_, vector)) = worst.next() {
+                        *entry = vector;
+                    }
+
+                    continue;
+                }
+
+                let mut centroid = [0; 4];
+
+                for (pixel, channels) in centroid.iter_mut().zip(sum.chunks_exact(3)) {
+                    let channels = [0, 1, 2].map(|channel| (channels[channel] / weight) as i32);
+                    *pixel = compress_pixel(channels);
+                }
+
+                *entry = centroid;
+            }
+
+            trace!("VQ codebook iteration {iteration}");
+        }
+    }
+
+    /// Chooses how to encode each macroblock, writing what the player will display to `reconstruction`.
+    fn encode_macroblocks(
+        &self,
+        frame: &[u8],
+        height: usize,
+        reference_frame: Option<&[u8]>,
+        reconstruction: &mut [u8],
+    ) -> Vec<Macroblock> {
+        let codebook = self.codebook[..self.codebook_size()]
+            .iter()
+            .copied()
+            .map(expand)
+            .collect::<Vec<_>>();
+        let mut cache = NearestCache::new(&codebook);
+
+        let mut macroblocks = Vec::new();
+
+        for y in (0..height).step_by(VQ_BLOCK_SIZE) {
+            for x in (0..self.width).step_by(VQ_BLOCK_SIZE) {
+                let quadrants = self.quadrants(frame, height, x, y).map(expand);
+
+                let skip_error = reference_frame.map(|reference_frame| {
+                    self.quadrants(reference_frame, height, x, y)
+                        .map(expand)
+                        .iter()
+                        .zip(&quadrants)
+                        .map(|(reference, quadrant)| distance(reference, quadrant))
+                        .sum::<i32>()
+                });
+
+                if skip_error == Some(0) {
+                    macroblocks.push(Macroblock::Skip);
+                    continue;
+                }
+
+                // V4
+                let mut v4 = [0; 4];
+                let mut v4_error = 0;
+
+                for (index, quadrant) in v4.iter_mut().zip(&quadrants) {
+                    let (entry, error) = cache.nearest(quadrant);
+                    *index = entry;
+                    v4_error += error;
+                }
+
+                // V1 is nearest to the average of each quadrant
+                let mut average = [0; 12];
+
+                for (quadrant_index, quadrant) in quadrants.iter().enumerate() {
+                    for channel in 0..3 {
+                        average[quadrant_index * 3 + channel] = quadrant
+                            .chunks_exact(3)
+                            .map(|pixel| pixel[channel])
+                            .sum::<i32>()
+                            / 4;
+                    }
+                }
+
+                let (v1, _) = cache.nearest(&average);
+                let v1_entry = &codebook[usize::from(v1)];
+                let v1_error = quadrants
+                    .iter()
+                    .enumerate()
+                    .map(|(quadrant_index, quadrant)| {
+                        let scaled = std::array::from_fn(|i| v1_entry[quadrant_index * 3 + i % 3]);
+                        distance(quadrant, &scaled)
+                    })
+                    .sum::<i32>();
+
+                let macroblock = match skip_error {
+                    Some(skip_error) if skip_error <= v1_error.min(v4_error) => Macroblock::Skip,
+                    _ if v1_error <= v4_error => Macroblock::V1(v1),
+                    _ => Macroblock::V4(v4),
+                };
+
+                let rows = VQ_BLOCK_SIZE.min(height - y);
+
+                for row in 0..rows {
+                    for column in 0..VQ_BLOCK_SIZE {
+                        let pixel = match macroblock {
+                            Macroblock::Skip => continue,
+                            Macroblock::V1(entry) => {
+                                self.codebook[usize::from(entry)][(row / 2) * 2 + column / 2]
+                            }
+                            Macroblock::V4(entries) => {
+                                let quadrant = (row / 2) * 2 + column / 2;
+                                self.codebook[usize::from(entries[quadrant])]
+                                    [(row % 2) * 2 + column % 2]
+                            }
+                        };
+
+                        reconstruction[(y + row) * self.width + x + column] = pixel;
+                    }
+                }
+
+                macroblocks.push(macroblock);
+            }
+        }
+
+        macroblocks
+    }
+
+    fn write_macroblocks(macroblocks: &[Macroblock], output: &mut Vec<u8>) {
+        // Trailing skips are implied by the end of the image
+        let end = macroblocks
+            .iter()
+            .rposition(|macroblock| *macroblock != Macroblock::Skip)
+            .map_or(0, |position| position + 1);
+        let mut macroblocks = macroblocks[..end].iter().peekable();
+
+        while let Some(&first) = macroblocks.next() {
+            let tag_index = output.len();
+            output.push(first.tag());
+            let mut run = 1;
+
+            let mut write = |macroblock: Macroblock| match macroblock {
+                Macroblock::Skip => (),
+                Macroblock::V1(entry) => output.push(entry),
+                Macroblock::V4(entries) => output.extend_from_slice(&entries),
+            };
+
+            write(first);
+
+            while run < VQ_MAX_RUN
+                && let Some(&&macroblock) = macroblocks.peek()
+                && macroblock.tag() == first.tag()
+            {
+                write(macroblock);
+                macroblocks.next();
+                run += 1;
+            }
+
+            output[tag_index] |= (run - 1) as u8;
+        }
+    }
+}
+
+impl FrameEncoder for VqEncoder {
+    fn encode(&mut self, frame: &[u8], output_buffer: &mut [u8]) -> anyhow::Result<usize> {
+        anyhow::ensure!(
+            self.width.is_multiple_of(VQ_BLOCK_SIZE),
+            "Frame width isn't a whole number of macroblocks; {} % {VQ_BLOCK_SIZE} != 0",
+            self.width
+        );
+        anyhow::ensure!(
+            frame.len().is_multiple_of(self.width),
+            "Frame isn't a whole number of rows; {} % {} != 0",
+            frame.len(),
+            self.width
+        );
+
+        let height = frame.len() / self.width;
+        let reference_frame = self.reconstruction.take();
+
+        if let Some(reference_frame) = &reference_frame {
+            anyhow::ensure!(
+                reference_frame.len() == frame.len(),
+                "Reference frame size doesn't match; {} != {}",
+                reference_frame.len(),
+                frame.len()
+            );
+        }
+
+        let previous_codebook = match reference_frame {
+            Some(_) => self.codebook,
+            // Keyframes start with an empty codebook
+            None => [Vector::default(); VQ_CODEBOOK_SIZE],
+        };
+        self.codebook = previous_codebook;
+
+        let mut vectors = HashMap::<Vector, u32>::new();
+
+        for y in (0..height).step_by(VQ_BLOCK_SIZE) {
+            for x in (0..self.width).step_by(VQ_BLOCK_SIZE) {
+                let quadrants = self.quadrants(frame, height, x, y);
+
+                let unchanged = reference_frame.as_ref().is_some_and(|reference_frame| {
+                    self.quadrants(reference_frame, height, x, y) == quadrants
+                });
+
+                if unchanged {
+                    continue;
+                }
+
+                for quadrant in quadrants {
+                    *vectors.entry(quadrant).or_default() += 1;
+                }
+            }
+        }
+
+        self.train(&vectors, reference_frame.is_none());
+
+        let mut reconstruction = reference_frame
+            .clone()
+            .unwrap_or_else(|| vec![0; frame.len()]);
+        let macroblocks = self.encode_macroblocks(
+            frame,
+            height,
+            reference_frame.as_deref(),
+            &mut reconstruction,
+        );
+
+        // Codebook updates
+        let mut output = vec![0; VQ_CODEBOOK_SIZE / 8];
+        let mut updates = 0;
+
+        for (index, (entry, previous_entry)) in
+            self.codebook.iter().zip(&previous_codebook).enumerate()
+        {
+            if entry != previous_entry {
+                output[index / 8] |= 0x80 >> (index % 8);
+                output.extend_from_slice(entry);
+                updates += 1;
+            }
+        }
+
+        Self::write_macroblocks(&macroblocks, &mut output);
+
+        self.reconstruction = Some(reconstruction);
+
+        trace!(
+            "VQ frame: {} bytes, {updates} codebook updates",
+            output.len()
+        );
+
+        output_buffer
+            .get_mut(..output.len())
+            .with_context(|| format!("VQ frame overflowed; {} bytes", output.len()))?
+            .copy_from_slice(&output);
+
+        Ok(output.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LCD_WIDTH, encode::tests::moving_square_frames};
+
+    /// Frames with few unique 2x2 blocks get an exact codebook.
+    #[test]
+    fn few_blocks_are_lossless() {
+        let frames = moving_square_frames(2);
+        let mut encoder = VqEncoder::new(LCD_WIDTH.into(), VqSettings::default());
+        let mut output_buffer = vec![0; frames[0].len()];
+
+        for frame in &frames {
+            encoder.encode(frame, &mut output_buffer).unwrap();
+            assert_eq!(encoder.reconstruction(), Some(frame.as_slice()));
+        }
+    }
+
+    /// Delta frames only send what changed.
+    #[test]
+    fn unchanged_frame_has_no_updates() {
+        let frame = moving_square_frames(1).remove(0);
+        let mut encoder = VqEncoder::new(LCD_WIDTH.into(), VqSettings::default());
+        let mut output_buffer = vec![0; frame.len()];
+
+        let keyframe = encoder.encode(&frame, &mut output_buffer).unwrap();
+        let delta = encoder.encode(&frame, &mut output_buffer).unwrap();
+
+        assert!(delta < keyframe);
+        // No codebook entries, just the update bitmap and skips
+        assert!(
+            output_buffer[..VQ_CODEBOOK_SIZE / 8]
+                .iter()
+                .all(|&byte| byte == 0)
+        );
+    }
+
+    /// Encoding again from a checkpoint gives the same frame as the first time.
+    #[test]
+    fn restore_checkpoint() {
+        let frames = moving_square_frames(3);
+        let mut encoder = VqEncoder::new(LCD_WIDTH.into(), VqSettings::default());
+        let mut output_buffer = vec![0; frames[0].len()];
+        let mut retry_buffer = vec![0; frames[0].len()];
+
+        encoder.encode(&frames[0], &mut output_buffer).unwrap();
+        encoder.checkpoint();
+        let bytes = encoder.encode(&frames[1], &mut output_buffer).unwrap();
+        let reconstruction = encoder.reconstruction().unwrap().to_vec();
+
+        // A different frame changes the codebook
+        encoder.encode(&frames[2], &mut retry_buffer).unwrap();
+        encoder.restore();
+        let retry_bytes = encoder.encode(&frames[1], &mut retry_buffer).unwrap();
+
+        assert_eq!(output_buffer[..bytes], retry_buffer[..retry_bytes]);
+        assert_eq!(encoder.reconstruction(), Some(reconstruction.as_slice()));
+    }
+}
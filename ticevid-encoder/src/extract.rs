@@ -0,0 +1,378 @@
+use std::{
+    io::Cursor,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::Context;
+use image::{ImageFormat, RgbImage};
+use log::{debug, info};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncSeek, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, Command},
+};
+
+use crate::{
+    LCD_HEIGHT, LCD_WIDTH,
+    decode::PictureDecoder,
+    encode::FrameType,
+    reader::{ContainerReader, Title},
+    serialize::color_space_to_rgb,
+};
+
+/// Maps the calculator's pixels to RGB.
+///
+/// Titles without a palette use the default xlibc palette, which matches the encoder's color space.
+pub struct Palette([[u8; 3]; 256]);
+
+/// Expands a 1555 color to 8-bit RGB.
+fn rgb1555_to_rgb(color: u16) -> [u8; 3] {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0b1_1111) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [channel(10), channel(5), channel(0)]
+}
+
+impl Palette {
+    pub fn new(color_palette: Option<&[u16]>) -> Self {
+        let mut colors = std::array::from_fn(|pixel| color_space_to_rgb(pixel as u8));
+
+        for (color, &palette_color) in colors.iter_mut().zip(color_palette.unwrap_or_default()) {
+            *color = rgb1555_to_rgb(palette_color);
+        }
+
+        Self(colors)
+    }
+
+    pub fn rgb(&self, screen: &[u8]) -> RgbImage {
+        let rgb = screen
+            .iter()
+            .flat_map(|&pixel| self.0[usize::from(pixel)])
+            .collect();
+        RgbImage::from_raw(LCD_WIDTH.into(), LCD_HEIGHT.into(), rgb)
+            .expect("Screens are always the size of the LCD")
+    }
+}
+
+/// Places a decoded frame the way the player does, centered on a screen cleared to `0`.
+fn composite_screen(frame: &[u8]) -> Vec<u8> {
+    let screen_pixels = usize::from(LCD_WIDTH) * usize::from(LCD_HEIGHT);
+    let pixel_offset = (screen_pixels - frame.len()) / 2;
+
+    let mut screen = vec![0; screen_pixels];
+    screen[pixel_offset..pixel_offset + frame.len()].copy_from_slice(frame);
+    screen
+}
+
+/// Which frames of a title to extract.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameSelection {
+    pub start: Option<u32>,
+    /// Exclusive.
+    pub end: Option<u32>,
+    /// Extracts a whole chapter instead of `start` to `end`.
+    pub chapter: Option<usize>,
+}
+
+impl FrameSelection {
+    fn range(self, title: &Title) -> anyhow::Result<Range<u32>> {
+        let range = if let Some(chapter_index) = self.chapter {
+            let chapter = title.chapters.get(chapter_index).with_context(|| {
+                format!(
+                    "Chapter {chapter_index} doesn't exist; the title has {} chapters",
+                    title.chapters.len()
+                )
+            })?;
+            // Chapters run until the next one starts
+            let end = title
+                .chapters
+                .get(chapter_index + 1)
+                .map_or(title.frame_count, |next| next.start_frame);
+
+            chapter.start_frame..end
+        } else {
+            self.start.unwrap_or(0)..self.end.unwrap_or(title.frame_count)
+        };
+
+        anyhow::ensure!(
+            range.start < range.end && range.end <= title.frame_count,
+            "Frame range {range:?} isn't within the title's {} frames",
+            title.frame_count
+        );
+
+        Ok(range)
+    }
+}
+
+/// Decodes the frames of a title in `range`, each as a whole screen of the player.
+///
+/// Decoding starts from the last keyframe at or before the range.
+pub async fn extract_frames(
+    reader: &mut ContainerReader<impl AsyncRead + AsyncSeek + Unpin>,
+    title_index: usize,
+    range: Range<u32>,
+    mut write_frame: impl AsyncFnMut(u32, Vec<u8>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let title = reader.header().titles[title_index].clone();
+    let table = reader.picture_chunk_table(title_index).await?;
+
+    let mut keyframe = range.start;
+
+    loop {
+        let chunk = reader.picture_chunk(table[keyframe as usize]).await?;
+
+        if chunk.frame_type == FrameType::Key {
+            break;
+        }
+
+        keyframe = keyframe
+            .checked_sub(1)
+            .context("No keyframe before the first frame")?;
+    }
+
+    debug!("Decoding from keyframe {keyframe}");
+
+    let mut decoder = PictureDecoder::new(usize::from(LCD_WIDTH) * usize::from(title.height));
+
+    for frame_index in keyframe..range.end {
+        let chunk = reader.picture_chunk(table[frame_index as usize]).await?;
+        let frame = decoder
+            .decode(chunk.frame_type, chunk.codec, &chunk.image)
+            .with_context(|| format!("Failed to decode frame {frame_index}"))?;
+
+        if range.contains(&frame_index) {
+            write_frame(frame_index, composite_screen(frame)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where extracted frames go.
+pub enum FrameWriter {
+    /// A PNG file per frame.
+    Png(PathBuf),
+    FFmpeg {
+        child: Child,
+        stdin: ChildStdin,
+    },
+}
+
+impl FrameWriter {
+    /// Paths without an extension are directories of PNG files, anything else is left to FFmpeg.
+    pub async fn new(out: &Path, fps: u8, width: u32, height: u32) -> anyhow::Result<Self> {
+        if out.extension().is_none() {
+            tokio::fs::create_dir_all(out)
+                .await
+                .with_context(|| format!("Failed to create directory: {}", out.display()))?;
+            return Ok(Self::Png(out.to_path_buf()));
+        }
+
+        anyhow::ensure!(fps != 0, "Title has no frame rate to encode a video with");
+
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .arg("-s")
+            .arg(format!("{width}x{height}"))
+            .arg("-r")
+            .arg(fps.to_string())
+            .args(["-i", "pipe:0"])
+            .arg(out)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .kill_on_drop(true);
+
+        debug!("FFmpeg Command: {command:?}");
+
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to start FFmpeg for: {}", out.display()))?;
+        let stdin = child.stdin.take().context("FFmpeg's input wasn't piped")?;
+
+        Ok(Self::FFmpeg { child, stdin })
+    }
+
+    pub async fn write(&mut self, frame_index: u32, image: &RgbImage) -> anyhow::Result<()> {
+        match self {
+            Self::Png(directory) => {
+                let mut png = Cursor::new(Vec::new());
+                image.write_to(&mut png, ImageFormat::Png)?;
+
+                let path = directory.join(format!("frame_{frame_index:06}.png"));
+                tokio::fs::write(&path, png.into_inner())
+                    .await
+                    .with_context(|| format!("Failed to write frame: {}", path.display()))
+            }
+            Self::FFmpeg { stdin, .. } => stdin
+                .write_all(image.as_raw())
+                .await
+                .context("Failed to send frame to FFmpeg"),
+        }
+    }
+
+    /// Waits for FFmpeg to finish writing, checking it didn't fail.
+    pub async fn finish(self) -> anyhow::Result<()> {
+        if let Self::FFmpeg { mut child, stdin } = self {
+            // Closing its input ends the video
+            drop(stdin);
+
+            let status = child.wait().await.context("Failed to wait for FFmpeg")?;
+            anyhow::ensure!(status.success(), "FFmpeg failed: {status}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens a container for decoding the selected frames of a title.
+pub async fn open_title(
+    path: &Path,
+    title_index: usize,
+    selection: FrameSelection,
+) -> anyhow::Result<(ContainerReader<BufReader<File>>, Title, Range<u32>)> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open container: {}", path.display()))?;
+
+    let reader = ContainerReader::new(BufReader::new(file))
+        .await
+        .with_context(|| format!("Invalid container: {}", path.display()))?;
+
+    let title = reader
+        .header()
+        .titles
+        .get(title_index)
+        .with_context(|| {
+            format!(
+                "Title {title_index} doesn't exist; the container has {} titles",
+                reader.header().titles.len()
+            )
+        })?
+        .clone();
+    let range = selection.range(&title)?;
+
+    Ok((reader, title, range))
+}
+
+pub async fn extract(
+    path: &Path,
+    out: &Path,
+    title_index: usize,
+    selection: FrameSelection,
+) -> anyhow::Result<()> {
+    let (mut reader, title, range) = open_title(path, title_index, selection).await?;
+
+    info!(
+        "Extracting frames {range:?} of title {title_index} to: {}",
+        out.display()
+    );
+
+    let palette = Palette::new(title.color_palette.as_deref());
+    let mut writer = FrameWriter::new(out, title.fps, LCD_WIDTH.into(), LCD_HEIGHT.into()).await?;
+
+    extract_frames(
+        &mut reader,
+        title_index,
+        range,
+        async |frame_index, screen| writer.write(frame_index, &palette.rgb(&screen)).await,
+    )
+    .await?;
+
+    writer.finish().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BLOCK_SIZE, HEADER_SIZE,
+        reader::{Chapter, tests::sample_container},
+    };
+
+    #[tokio::test]
+    async fn extract_chapter_from_keyframe() {
+        let mut container = sample_container();
+        // Make the chapter start a delta frame drawing one white pixel
+        let chunk = usize::from(HEADER_SIZE + BLOCK_SIZE * 2);
+        container[chunk..chunk + 6].copy_from_slice(&[2, 0, FrameType::Delta as u8, 0, 0xFF, 0xFF]);
+
+        let mut reader = ContainerReader::new(Cursor::new(container)).await.unwrap();
+        let title = reader.header().titles[0].clone();
+        let selection = FrameSelection {
+            chapter: Some(0),
+            ..FrameSelection::default()
+        };
+        let range = selection.range(&title).unwrap();
+        assert_eq!(range, 1..2);
+
+        let mut frames = Vec::new();
+        extract_frames(&mut reader, 0, range, async |frame_index, screen| {
+            frames.push((frame_index, screen));
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        let [(frame_index, screen)] = frames.try_into().unwrap();
+        assert_eq!(frame_index, 1);
+
+        // Centered between 60 rows of black bars
+        let rgb = Palette::new(None).rgb(&screen);
+        assert_eq!(rgb.get_pixel(0, 60).0, [255, 255, 255]);
+        assert_eq!(rgb.get_pixel(1, 60).0, [0, 0, 0]);
+        assert_eq!(rgb.get_pixel(0, 59).0, [0, 0, 0]);
+        assert_eq!(screen.iter().filter(|&&pixel| pixel != 0).count(), 1);
+    }
+
+    #[test]
+    fn frame_selection_range() {
+        let title = Title {
+            frame_count: 100,
+            chapters: vec![
+                Chapter {
+                    start_frame: 0,
+                    name: None,
+                },
+                Chapter {
+                    start_frame: 40,
+                    name: None,
+                },
+            ],
+            ..Title::default()
+        };
+
+        let range = |start, end, chapter| {
+            FrameSelection {
+                start,
+                end,
+                chapter,
+            }
+            .range(&title)
+        };
+
+        assert_eq!(range(None, None, Some(0)).unwrap(), 0..40);
+        assert_eq!(range(None, None, Some(1)).unwrap(), 40..100);
+        assert_eq!(range(Some(10), None, None).unwrap(), 10..100);
+        assert_eq!(
+            range(None, Some(101), None).unwrap_err().to_string(),
+            "Frame range 0..101 isn't within the title's 100 frames"
+        );
+        assert_eq!(
+            range(None, None, Some(2)).unwrap_err().to_string(),
+            "Chapter 2 doesn't exist; the title has 2 chapters"
+        );
+    }
+
+    #[test]
+    fn palette_colors() {
+        let palette = Palette::new(Some(&[0b0111_1100_0000_0000]));
+        assert_eq!(palette.0[0], [255, 0, 0]);
+        assert_eq!(palette.0[0xFF], [255, 255, 255]);
+        assert_eq!(Palette::new(None).0[0], [0, 0, 0]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Reads fontlibc font packs and draws text with them the way fontlibc does.
+
+use anyhow::Context;
+
+const FONT_PACK_MAGIC: &[u8; 8] = b"FONTPACK";
+const FONT_PACK_FONT_LIST: usize = 12;
+const FONT_HEADER_SIZE: usize = 18;
+
+fn u24(bytes: &[u8], offset: usize, field: &str) -> anyhow::Result<usize> {
+    let [low, middle, high] = *bytes
+        .get(offset..)
+        .and_then(<[u8]>::first_chunk::<3>)
+        .with_context(|| format!("`{field}` runs past the end of the font pack"))?;
+    Ok(u32::from_le_bytes([low, middle, high, 0]) as usize)
+}
+
+/// A single font from a font pack.
+#[derive(Debug, Clone)]
+pub struct Font {
+    height: u8,
+    first_glyph: u8,
+    space_above: u8,
+    space_below: u8,
+    /// Width of each glyph from `first_glyph`.
+    widths: Vec<u8>,
+    /// Rows of each glyph, padded to whole bytes with the leftmost pixel in the highest bit.
+    bitmaps: Vec<Vec<u8>>,
+}
+
+impl Font {
+    /// Reads a font by index from a font pack.
+    pub fn from_pack(pack: &[u8], font_index: u8) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            pack.starts_with(FONT_PACK_MAGIC),
+            "Font pack doesn't start with `FONTPACK`"
+        );
+
+        let font_count = *pack
+            .get(FONT_PACK_MAGIC.len() + 3)
+            .context("Font pack ends before its font count")?;
+        anyhow::ensure!(
+            font_index < font_count,
+            "Font {font_index} doesn't exist; the font pack has {font_count} fonts"
+        );
+
+        let font = u24(
+            pack,
+            FONT_PACK_FONT_LIST + usize::from(font_index) * 3,
+            "font_list",
+        )?;
+        Self::parse(
+            pack.get(font..)
+                .context("Font starts past the end of the font pack")?,
+        )
+        .with_context(|| format!("Invalid font {font_index}"))
+    }
+
+    fn parse(font: &[u8]) -> anyhow::Result<Self> {
+        let header = font
+            .first_chunk::<FONT_HEADER_SIZE>()
+            .context("Font is smaller than its header")?;
+        let [version, height, total_glyphs, first_glyph, ..] = *header;
+        anyhow::ensure!(version == 0, "Unsupported font version {version}");
+
+        // Zero means every glyph
+        let glyph_count = if total_glyphs == 0 {
+            256
+        } else {
+            usize::from(total_glyphs)
+        };
+        let widths = u24(font, 4, "widths_table")?;
+        let bitmaps = u24(font, 7, "bitmaps")?;
+
+        let widths = font
+            .get(widths..widths + glyph_count)
+            .context("Widths table runs past the end of the font")?
+            .to_vec();
+
+        let bitmaps = widths
+            .iter()
+            .enumerate()
+            .map(|(glyph, &width)| {
+                let table = bitmaps + glyph * 2;
+                let offset = font
+                    .get(table..table + 2)
+                    .context("Bitmap table runs past the end of the font")?;
+                let offset = usize::from(u16::from_le_bytes([offset[0], offset[1]]));
+                let size = usize::from(width).div_ceil(8) * usize::from(height);
+
+                font.get(offset..offset + size)
+                    .map(<[u8]>::to_vec)
+                    .with_context(|| {
+                        format!("Bitmap of glyph {glyph} runs past the end of the font")
+                    })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            height,
+            first_glyph,
+            space_above: header[11],
+            space_below: header[12],
+            widths,
+            bitmaps,
+        })
+    }
+
+    /// Glyphs missing from the font aren't drawn.
+    fn glyph(&self, code: u8) -> Option<usize> {
+        let glyph = usize::from(code.checked_sub(self.first_glyph)?);
+        (glyph < self.widths.len()).then_some(glyph)
+    }
+
+    /// Height of a line of text, including the blank space above and below glyphs.
+    pub fn line_height(&self) -> usize {
+        usize::from(self.space_above) + usize::from(self.height) + usize::from(self.space_below)
+    }
+
+    pub fn text_width(&self, text: &[u8]) -> usize {
+        text.iter()
+            .filter_map(|&code| self.glyph(code))
+            .map(|glyph| usize::from(self.widths[glyph]))
+            .sum()
+    }
+
+    /// Draws a line of text with its top left at `x` and `y`, clipped to the canvas.
+    ///
+    /// A `background` fills each glyph's whole line height, otherwise it's transparent.
+    pub fn draw(
+        &self,
+        canvas: &mut [u8],
+        canvas_width: usize,
+        (x, y): (isize, isize),
+        text: &[u8],
+        foreground: u8,
+        background: Option<u8>,
+    ) {
+        let canvas_height = canvas.len() / canvas_width;
+        let mut put = |pixel_x: isize, pixel_y: isize, color: u8| {
+            if let (Ok(pixel_x), Ok(pixel_y)) = (usize::try_from(pixel_x), usize::try_from(pixel_y))
+                && pixel_x < canvas_width
+                && pixel_y < canvas_height
+            {
+                canvas[pixel_y * canvas_width + pixel_x] = color;
+            }
+        };
+
+        let glyph_top = y + self.space_above as isize;
+        let mut glyph_x = x;
+
+        for glyph in text.iter().filter_map(|&code| self.glyph(code)) {
+            let width = usize::from(self.widths[glyph]);
+            let row_size = width.div_ceil(8);
+
+            for column in 0..width {
+                let pixel_x = glyph_x + column as isize;
+
+                if let Some(background) = background {
+                    for row in 0..self.line_height() {
+                        put(pixel_x, y + row as isize, background);
+                    }
+                }
+
+                for (row, bits) in self.bitmaps[glyph].chunks(row_size).enumerate() {
+                    if bits[column / 8] & (0x80 >> (column % 8)) != 0 {
+                        put(pixel_x, glyph_top + row as isize, foreground);
+                    }
+                }
+            }
+
+            glyph_x += width as isize;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A font pack with one 2 pixel tall font of `A`, 3 pixels wide, and a blank `B`.
+    pub(crate) fn sample_font_pack() -> Vec<u8> {
+        let mut pack = FONT_PACK_MAGIC.to_vec();
+        // Metadata, font count, then the font list
+        pack.extend_from_slice(&[0, 0, 0, 1, 15, 0, 0]);
+
+        #[rustfmt::skip]
+        let font = [
+            // Version, height, total glyphs, first glyph
+            0, 2, 2, b'A',
+            // Widths table, bitmaps
+            18, 0, 0, 20, 0, 0,
+            // Italic space adjust, space above, space below, weight, style, cap height, x height, baseline
+            0, 1, 0, 0, 0, 2, 2, 2,
+            // Widths
+            3, 1,
+            // Bitmap offsets
+            24, 0, 26, 0,
+            // Bitmaps
+            0b1010_0000, 0b0100_0000, 0, 0,
+        ];
+        pack.extend_from_slice(&font);
+        pack
+    }
+
+    #[test]
+    fn draw_text() {
+        let font = Font::from_pack(&sample_font_pack(), 0).unwrap();
+        assert_eq!(font.line_height(), 3);
+        assert_eq!(font.text_width(b"ABA?"), 7);
+
+        let mut canvas = vec![9; 8 * 3];
+        font.draw(&mut canvas, 8, (0, 0), b"AB?A", 1, Some(0));
+
+        #[rustfmt::skip]
+        assert_eq!(
+            canvas,
+            [
+                0, 0, 0, 0, 0, 0, 0, 9,
+                1, 0, 1, 0, 1, 0, 1, 9,
+                0, 1, 0, 0, 0, 1, 0, 9,
+            ]
+        );
+
+        let mut canvas = vec![9; 4 * 3];
+        font.draw(&mut canvas, 4, (-1, 0), b"A", 1, None);
+        assert_eq!(canvas, [9, 9, 9, 9, 9, 1, 9, 9, 1, 9, 9, 9]);
+    }
+
+    #[test]
+    fn missing_font() {
+        let error = Font::from_pack(&sample_font_pack(), 1).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Font 1 doesn't exist; the font pack has 1 fonts"
+        );
+    }
+}
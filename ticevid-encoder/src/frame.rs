@@ -1,94 +1,291 @@
-use std::path::{Path, PathBuf};
+use std::{path::Path, process::Stdio, time::Duration};
 
 use anyhow::Context;
-use log::{debug, info};
-use rust_ffmpeg::{FFmpegBuilder, PixelFormat, VideoFilter};
+use log::debug;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    process::{Child, ChildStdout, Command},
+};
 
-use crate::{FRAME_FORMAT_EXTENSION, LCD_WIDTH, definition::title::TitleDefinition};
+use crate::{LCD_WIDTH, definition::title::TitleDefinition, serialize::rgb_to_color_space};
+
+/// Bytes per pixel of FFmpeg's `rgb24` format.
+const RGB_PIXEL_SIZE: usize = 3;
 
 impl TitleDefinition {
-    pub fn frames_folder(&self, output_directory: &Path) -> anyhow::Result<PathBuf> {
-        let video_name = self
-            .video
-            .file_stem()
-            .with_context(|| format!("Failed to get source file name: {}", self.video.display()))?
-            .to_os_string();
-
-        let mut frames_folder = output_directory.join(video_name);
-        frames_folder
-            .as_mut_os_string()
-            .push(format!("-{}-frames", self.name));
-
-        Ok(frames_folder)
+    /// Starts FFmpeg decoding the title's video, scaled to the title's size.
+    pub fn spawn_frames(
+        &self,
+        title_directory: &Path,
+        threads: usize,
+    ) -> anyhow::Result<FFmpegFrames> {
+        self.spawn_frame_range(
+            title_directory,
+            threads,
+            self.start.map(Duration::from),
+            self.durration.map(Duration::from),
+        )
     }
 
-    /// Returns the number of frames generated
-    pub async fn create_frames(
+    /// Like [`Self::spawn_frames`], but from `start` in the video for up to `duration`.
+    pub fn spawn_frame_range(
         &self,
         title_directory: &Path,
-        frames_directory: &Path,
-    ) -> anyhow::Result<u32> {
-        if tokio::fs::try_exists(&frames_directory)
-            .await
-            .unwrap_or_default()
-        {
-            tokio::fs::remove_dir_all(&frames_directory).await?;
-        }
-        tokio::fs::create_dir_all(&frames_directory)
-            .await
-            .with_context(|| {
-                format!(
-                    "Failed to create frame directory: {}",
-                    frames_directory.display()
-                )
-            })?;
-
+        threads: usize,
+        start: Option<Duration>,
+        duration: Option<Duration>,
+    ) -> anyhow::Result<FFmpegFrames> {
         let video_path = title_directory.join(&self.video);
 
-        let mut input = rust_ffmpeg::Input::new(video_path)
-            .option("probesize", "100M")
-            .option("analyzeduration", "100M");
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-nostdin"])
+            .arg("-threads")
+            .arg(threads.to_string())
+            .args(["-probesize", "100M", "-analyzeduration", "100M"]);
+
+        if let Some(start) = start {
+            command.arg("-ss").arg(ffmpeg_duration(start));
+        }
 
-        if let Some(start) = self.start {
-            input = input.seek(start.into());
+        if let Some(duration) = duration {
+            command.arg("-t").arg(ffmpeg_duration(duration));
         }
 
+        command
+            .arg("-i")
+            .arg(&video_path)
+            .args(["-an", "-sn"])
+            .arg("-vf")
+            .arg(video_filter(self.height))
+            .args(["-pix_fmt", "rgb24", "-f", "rawvideo"])
+            .arg("-r")
+            .arg(self.fps.to_string())
+            .arg("pipe:1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true);
+
+        debug!("FFmpeg Command: {command:?}");
+
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to start FFmpeg for: {}", video_path.display()))?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("FFmpeg's output wasn't piped")?;
+
+        Ok(FFmpegFrames {
+            child,
+            frames: RawFrameReader::new(BufReader::new(stdout), self.height),
+        })
+    }
+
+    /// How long the title is, asking FFprobe for the video's length when it has no durration.
+    pub async fn duration(&self, title_directory: &Path) -> anyhow::Result<Duration> {
         if let Some(duration) = self.durration {
-            input = input.duration(duration.into());
+            return Ok(duration.into());
         }
 
-        let builder = FFmpegBuilder::new()?
-            .input(input)
-            .output(
-                rust_ffmpeg::Output::new(
-                    frames_directory.join(format!("%d.{FRAME_FORMAT_EXTENSION}")),
-                )
-                .no_audio()
-                .no_subtitles()
-                .option("pix_fmt", PixelFormat::rgb24().to_string())
-                .option("r", self.fps.to_string()),
-            )
-            .video_filter(VideoFilter::scale_aspect(LCD_WIDTH.into()))
-            .overwrite()
-            .on_progress(|progress| {
-                info!(
-                    "Gnerating Frames: completed {} frames",
-                    progress.frame.unwrap_or_default()
+        let video_path = title_directory.join(&self.video);
+        let output = Command::new("ffprobe")
+            .args(["-v", "error", "-show_entries", "format=duration"])
+            .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+            .arg(&video_path)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .with_context(|| format!("Failed to start FFprobe for: {}", video_path.display()))?;
+        anyhow::ensure!(
+            output.status.success(),
+            "FFprobe failed for {}: {}",
+            video_path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+
+        let seconds = String::from_utf8_lossy(&output.stdout);
+        let video_duration = seconds
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .with_context(|| format!("FFprobe gave an invalid duration: {}", seconds.trim()))?;
+
+        Ok(video_duration.saturating_sub(self.start.map(Duration::from).unwrap_or_default()))
+    }
+}
+
+/// Scales the video to fit the title's size without stretching it, padding the rest with black.
+fn video_filter(height: u8) -> String {
+    format!(
+        "scale={LCD_WIDTH}:{height}:force_original_aspect_ratio=decrease,\
+         pad={LCD_WIDTH}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1"
+    )
+}
+
+fn ffmpeg_duration(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+/// Frames streamed from a running FFmpeg process.
+pub struct FFmpegFrames {
+    child: Child,
+    frames: RawFrameReader<BufReader<ChildStdout>>,
+}
+
+impl FFmpegFrames {
+    /// Returns `None` once FFmpeg is out of frames.
+    pub async fn next_frame(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        self.frames.next_frame().await
+    }
+
+    /// See [`RawFrameReader::source_frame`].
+    pub fn source_frame(&self) -> &[u8] {
+        self.frames.source_frame()
+    }
+
+    /// Waits for FFmpeg to exit, checking it didn't fail.
+    pub async fn finish(mut self) -> anyhow::Result<()> {
+        let status = self
+            .child
+            .wait()
+            .await
+            .context("Failed to wait for FFmpeg")?;
+        anyhow::ensure!(status.success(), "FFmpeg failed: {status}");
+        Ok(())
+    }
+}
+
+/// Reads `rgb24` rawvideo frames, converting them to the calculator's color space.
+pub struct RawFrameReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> RawFrameReader<R> {
+    pub fn new(reader: R, height: u8) -> Self {
+        Self {
+            reader,
+            buffer: vec![0; usize::from(LCD_WIDTH) * usize::from(height) * RGB_PIXEL_SIZE],
+        }
+    }
+
+    /// Returns `None` at the end of the stream.
+    pub async fn next_frame(&mut self) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut filled = 0;
+
+        while filled < self.buffer.len() {
+            let read = self
+                .reader
+                .read(&mut self.buffer[filled..])
+                .await
+                .context("Failed to read frame from FFmpeg")?;
+
+            if read == 0 {
+                anyhow::ensure!(
+                    filled == 0,
+                    "FFmpeg's output ended partway through a frame; {filled}/{} bytes",
+                    self.buffer.len()
                 );
-            });
+                return Ok(None);
+            }
+
+            filled += read;
+        }
+
+        Ok(Some(rgb_to_color_space(&self.buffer)))
+    }
+
+    /// The `rgb24` pixels of the last frame read, before converting them.
+    pub fn source_frame(&self) -> &[u8] {
+        &self.buffer
+    }
+}
 
-        debug!("FFmpeg Command: {}", builder.command()?);
-        info!("Generating image sequence with FFmpeg.");
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::io::AsyncWriteExt;
 
-        builder.spawn().await?.wait().await?;
+    use super::*;
 
-        let frames_folder = frames_directory.to_path_buf();
-        let frames = tokio::runtime::Handle::current()
-            .spawn_blocking(move || std::fs::read_dir(frames_folder).map(std::fs::ReadDir::count))
-            .await??;
+    const HEIGHT: u8 = 2;
+    const FRAME_SIZE: usize = LCD_WIDTH as usize * HEIGHT as usize * RGB_PIXEL_SIZE;
+
+    #[test]
+    fn filter_keeps_aspect_ratio() {
+        assert_eq!(
+            video_filter(180),
+            "scale=320:180:force_original_aspect_ratio=decrease,pad=320:180:(ow-iw)/2:(oh-ih)/2,setsar=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_frames_until_eof() {
+        let stream = [vec![0; FRAME_SIZE], vec![255; FRAME_SIZE]].concat();
+        let mut frames = RawFrameReader::new(stream.as_slice(), HEIGHT);
+
+        let first = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(first, rgb_to_color_space(&[0; FRAME_SIZE]));
+        let second = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(second, rgb_to_color_space(&[255; FRAME_SIZE]));
+        assert_eq!(frames.source_frame(), [255; FRAME_SIZE]);
+
+        assert!(frames.next_frame().await.unwrap().is_none());
+        assert!(frames.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn short_final_frame() {
+        let stream = vec![0; FRAME_SIZE + 10];
+        let mut frames = RawFrameReader::new(stream.as_slice(), HEIGHT);
+
+        assert!(frames.next_frame().await.unwrap().is_some());
+        let error = frames.next_frame().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("FFmpeg's output ended partway through a frame; 10/{FRAME_SIZE} bytes")
+        );
+    }
+
+    /// Stands in for FFmpeg with a shell that outputs `stream` then exits with `status`.
+    pub(crate) fn fake_ffmpeg(stream: Vec<u8>, height: u8, status: u8) -> FFmpegFrames {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(format!("cat; exit {status}"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        tokio::spawn(async move {
+            stdin.write_all(&stream).await.unwrap();
+        });
+
+        FFmpegFrames {
+            child,
+            frames: RawFrameReader::new(BufReader::new(stdout), height),
+        }
+    }
+
+    #[tokio::test]
+    async fn ffmpeg_succeeded() {
+        let mut frames = fake_ffmpeg(vec![0; FRAME_SIZE], HEIGHT, 0);
+        assert!(frames.next_frame().await.unwrap().is_some());
+        assert!(frames.next_frame().await.unwrap().is_none());
+        frames.finish().await.unwrap();
+    }
 
-        debug!("Output {frames} frames");
+    #[tokio::test]
+    async fn ffmpeg_failed() {
+        let mut frames = fake_ffmpeg(vec![0; FRAME_SIZE], HEIGHT, 1);
+        assert!(frames.next_frame().await.unwrap().is_some());
+        assert!(frames.next_frame().await.unwrap().is_none());
 
-        u32::try_from(frames).with_context(|| format!("Frames exceeded maximum amount: {frames}"))
+        let error = frames.finish().await.unwrap_err();
+        assert_eq!(error.to_string(), "FFmpeg failed: exit status: 1");
     }
 }
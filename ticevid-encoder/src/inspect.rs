@@ -0,0 +1,297 @@
+use std::{
+    fmt::{self, Display},
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncSeek, BufReader},
+};
+
+use crate::{
+    BLOCK_SIZE,
+    reader::{ContainerReader, PictureChunkInfo, Title},
+};
+
+#[derive(Debug, Serialize)]
+pub struct ContainerReport {
+    pub version: String,
+    pub header_size: u16,
+    /// Size of the whole container in bytes.
+    pub file_size: u64,
+    pub font_pack: bool,
+    pub ui_font_index: u8,
+    pub titles: Vec<TitleReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleReport {
+    pub name: Option<String>,
+    pub fps: u8,
+    pub height: u8,
+    pub frame_count: u32,
+    /// `None` when the title has no frame rate.
+    pub duration_seconds: Option<f64>,
+    /// How many colors the palette has, or `None` for the default palette.
+    pub palette_colors: Option<usize>,
+    pub icon: bool,
+    pub caption_tracks: Vec<CaptionTrackReport>,
+    pub chapters: Vec<ChapterReport>,
+    pub picture_chunks: PictureChunkStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaptionTrackReport {
+    pub name: String,
+    pub font_index: u8,
+    pub chunk_start: u32,
+    pub chunk_count: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChapterReport {
+    pub name: Option<String>,
+    pub start_frame: u32,
+    pub start_seconds: Option<f64>,
+}
+
+/// Blocks the player reads each frame, from the picture chunk table.
+#[derive(Debug, Serialize)]
+pub struct PictureChunkStats {
+    pub min_blocks: u16,
+    pub average_blocks: f64,
+    pub max_blocks: u16,
+    pub total_blocks: u64,
+    pub total_bytes: u64,
+}
+
+fn seconds(frames: u32, fps: u8) -> Option<f64> {
+    (fps != 0).then(|| f64::from(frames) / f64::from(fps))
+}
+
+impl PictureChunkStats {
+    fn new(table: &[PictureChunkInfo]) -> Self {
+        let blocks = || table.iter().map(|info| info.block_count);
+        let total_blocks = blocks().map(u64::from).sum::<u64>();
+
+        Self {
+            min_blocks: blocks().min().unwrap_or_default(),
+            average_blocks: total_blocks as f64 / table.len().max(1) as f64,
+            max_blocks: blocks().max().unwrap_or_default(),
+            total_blocks,
+            total_bytes: total_blocks * u64::from(BLOCK_SIZE),
+        }
+    }
+}
+
+impl TitleReport {
+    fn new(title: &Title, table: &[PictureChunkInfo]) -> Self {
+        Self {
+            name: title.name.clone(),
+            fps: title.fps,
+            height: title.height,
+            frame_count: title.frame_count,
+            duration_seconds: seconds(title.frame_count, title.fps),
+            palette_colors: title.color_palette.as_ref().map(Vec::len),
+            icon: title.icon.is_some(),
+            caption_tracks: title
+                .caption_tracks
+                .iter()
+                .map(|track| CaptionTrackReport {
+                    name: track.name.clone(),
+                    font_index: track.font_index,
+                    chunk_start: track.chunk_start,
+                    chunk_count: track.chunk_count,
+                })
+                .collect(),
+            chapters: title
+                .chapters
+                .iter()
+                .map(|chapter| ChapterReport {
+                    name: chapter.name.clone(),
+                    start_frame: chapter.start_frame,
+                    start_seconds: seconds(chapter.start_frame, title.fps),
+                })
+                .collect(),
+            picture_chunks: PictureChunkStats::new(table),
+        }
+    }
+}
+
+impl ContainerReport {
+    pub async fn read(
+        reader: &mut ContainerReader<impl AsyncRead + AsyncSeek + Unpin>,
+        file_size: u64,
+    ) -> anyhow::Result<Self> {
+        let header = reader.header().clone();
+        let mut titles = Vec::with_capacity(header.titles.len());
+
+        for (title_index, title) in header.titles.iter().enumerate() {
+            let table = reader.picture_chunk_table(title_index).await?;
+            titles.push(TitleReport::new(title, &table));
+        }
+
+        let (major, minor, patch) = header.version;
+
+        Ok(Self {
+            version: format!("{major}.{minor}.{patch}"),
+            header_size: header.header_size,
+            file_size,
+            font_pack: header.font_pack.is_some(),
+            ui_font_index: header.ui_font_index,
+            titles,
+        })
+    }
+}
+
+/// Formats seconds as `minutes:seconds`.
+struct Timestamp(Option<f64>);
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(seconds) => write!(f, "{}:{:06.3}", (seconds / 60.0).floor(), seconds % 60.0),
+            None => write!(f, "-:--.---"),
+        }
+    }
+}
+
+/// Displays an optional name, quoted when present.
+struct Name<'a>(Option<&'a str>);
+
+impl Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(name) => write!(f, "\"{name}\""),
+            None => write!(f, "(unnamed)"),
+        }
+    }
+}
+
+impl Display for ContainerReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Format version: {}", self.version)?;
+        writeln!(f, "Header size: {} bytes", self.header_size)?;
+        writeln!(f, "File size: {} bytes", self.file_size)?;
+
+        if self.font_pack {
+            writeln!(f, "Font pack: UI font {}", self.ui_font_index)?;
+        } else {
+            writeln!(f, "Font pack: none")?;
+        }
+
+        for (title_index, title) in self.titles.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, "Title {title_index}: {}", Name(title.name.as_deref()))?;
+            title.fmt(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for TitleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Height: {} px", self.height)?;
+        writeln!(f, "  FPS: {}", self.fps)?;
+        writeln!(
+            f,
+            "  Frames: {} ({})",
+            self.frame_count,
+            Timestamp(self.duration_seconds)
+        )?;
+
+        match self.palette_colors {
+            Some(colors) => writeln!(f, "  Palette: {colors} colors")?,
+            None => writeln!(f, "  Palette: default")?,
+        }
+
+        writeln!(f, "  Icon: {}", if self.icon { "present" } else { "none" })?;
+
+        writeln!(f, "  Caption tracks: {}", self.caption_tracks.len())?;
+        for (track_index, track) in self.caption_tracks.iter().enumerate() {
+            writeln!(
+                f,
+                "    {track_index}: \"{}\", font {}, {} chunks from block {}",
+                track.name, track.font_index, track.chunk_count, track.chunk_start
+            )?;
+        }
+
+        writeln!(f, "  Chapters: {}", self.chapters.len())?;
+        for (chapter_index, chapter) in self.chapters.iter().enumerate() {
+            writeln!(
+                f,
+                "    {chapter_index}: {} at frame {} ({})",
+                Name(chapter.name.as_deref()),
+                chapter.start_frame,
+                Timestamp(chapter.start_seconds)
+            )?;
+        }
+
+        let chunks = &self.picture_chunks;
+        writeln!(
+            f,
+            "  Blocks per frame: {} min, {:.2} average, {} max",
+            chunks.min_blocks, chunks.average_blocks, chunks.max_blocks
+        )?;
+        writeln!(
+            f,
+            "  Picture chunks: {} blocks, {} bytes",
+            chunks.total_blocks, chunks.total_bytes
+        )
+    }
+}
+
+pub async fn inspect(path: &Path, json: bool) -> anyhow::Result<()> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open container: {}", path.display()))?;
+    let file_size = file.metadata().await?.len();
+
+    let mut reader = ContainerReader::new(BufReader::new(file))
+        .await
+        .with_context(|| format!("Invalid container: {}", path.display()))?;
+    let report = ContainerReport::read(&mut reader, file_size).await?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::tests::sample_container;
+
+    #[tokio::test]
+    async fn inspect_sample_container() {
+        let container = sample_container();
+        let file_size = container.len() as u64;
+        let mut reader = ContainerReader::new(Cursor::new(container)).await.unwrap();
+        let report = ContainerReport::read(&mut reader, file_size).await.unwrap();
+
+        let title = &report.titles[0];
+        assert_eq!(title.duration_seconds, Some(2.0 / 30.0));
+        assert_eq!(title.chapters[0].start_frame, 1);
+        assert_eq!(title.picture_chunks.total_bytes, 1024);
+
+        let text = report.to_string();
+        assert!(text.contains("Title 0: \"Demo\""), "{text}");
+        assert!(
+            text.contains("0: \"Intro\" at frame 1 (0:00.033)"),
+            "{text}"
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["titles"][0]["picture_chunks"]["max_blocks"], 1);
+        assert_eq!(json["version"], "0.4.0");
+    }
+}
@@ -0,0 +1,421 @@
+//! Finds where each sector of a container landed, reading it back the way the player does.
+
+use std::{
+    fmt::{self, Display},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::info;
+use serde::Serialize;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncSeek, BufReader},
+};
+
+use crate::{
+    BLOCK_SIZE, HEADER_SIZE,
+    definition::container::ContainerDefinition,
+    encode::{Codec, EncodedFrame, FrameType, PICTURE_CHUNK_HEADER_SIZE},
+    get_container_directory,
+    reader::{ContainerHeader, ContainerReader},
+    serialize::{EncodedTitle, PICTURE_CHUNK_TABLE_ENTRY_SIZE, SectorId, serialize_header},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayoutSector {
+    pub sector: SectorId,
+    pub offset: u64,
+    /// Bytes used, not counting padding.
+    pub size: u64,
+    /// Bytes before the next sector, or to the end of the container for the last sector.
+    pub padding: u64,
+}
+
+impl LayoutSector {
+    fn new(sector: SectorId, offset: u64, size: u64) -> Self {
+        Self {
+            sector,
+            offset,
+            size,
+            padding: 0,
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContainerLayout {
+    pub sectors: Vec<LayoutSector>,
+}
+
+/// The sectors serseg lays out: what the header was read from, its fill,
+/// then each title's picture chunk table padded to a whole block.
+fn header_sectors(header: &ContainerHeader) -> Vec<LayoutSector> {
+    let mut sectors = header
+        .sectors
+        .iter()
+        .map(|sector| LayoutSector::new(sector.sector, sector.offset as u64, sector.size as u64))
+        .collect::<Vec<_>>();
+
+    let header_size = u64::from(header.header_size);
+    sectors.push(LayoutSector::new(
+        SectorId::HeaderEnd,
+        header_size,
+        u64::from(HEADER_SIZE) - header_size,
+    ));
+    sectors.push(LayoutSector::new(SectorId::Chunks, HEADER_SIZE.into(), 0));
+
+    for (title_index, title) in (0..).zip(&header.titles) {
+        let offset = title.picture_chunk_table_position();
+        let size = u64::from(title.frame_count) * PICTURE_CHUNK_TABLE_ENTRY_SIZE as u64;
+        let end = offset + size;
+
+        sectors.push(LayoutSector::new(
+            SectorId::PictureChunkTable { title_index },
+            offset,
+            size,
+        ));
+        sectors.push(LayoutSector::new(
+            SectorId::PictureChunkTablePadding { title_index },
+            end,
+            end.next_multiple_of(BLOCK_SIZE.into()) - end,
+        ));
+    }
+
+    sectors
+}
+
+/// Each title's picture chunks and caption chunks, found through the tables pointing to them.
+///
+/// Returns the end of the last block any of them take up too.
+async fn chunk_sectors<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut ContainerReader<R>,
+    header: &ContainerHeader,
+) -> anyhow::Result<(Vec<LayoutSector>, u64)> {
+    let block_size = u64::from(BLOCK_SIZE);
+    let mut sectors = Vec::new();
+    let mut end = 0;
+
+    for (title_index, title) in (0..).zip(&header.titles) {
+        let table = reader.picture_chunk_table(title_index.into()).await?;
+        let mut size = 0;
+
+        for &info in &table {
+            let image_size = reader.picture_chunk_image_size(info).await?;
+            size += (PICTURE_CHUNK_HEADER_SIZE as u64) + u64::from(image_size);
+            end = end.max(info.position() + info.size() as u64);
+        }
+
+        if let Some(first) = table.first() {
+            sectors.push(LayoutSector::new(
+                SectorId::PictureChunks { title_index },
+                first.position(),
+                size,
+            ));
+        }
+
+        for (track_index, track) in (0..).zip(&title.caption_tracks) {
+            let chunks = reader
+                .caption_chunks(title_index.into(), track_index.into())
+                .await?;
+            let blocks = u64::from(track.chunk_block_count)
+                + chunks
+                    .iter()
+                    .map(|chunk| u64::from(chunk.next_block_count))
+                    .sum::<u64>();
+            let offset = u64::from(track.chunk_start) * block_size;
+
+            // Caption chunks don't say how much of their blocks they use
+            sectors.push(LayoutSector::new(
+                SectorId::CaptionChunks {
+                    title_index,
+                    track_index,
+                },
+                offset,
+                blocks * block_size,
+            ));
+            end = end.max(offset + blocks * block_size);
+        }
+    }
+
+    Ok((sectors, end))
+}
+
+/// Sorts sectors by offset, with padding being the gap to the next one, and from the last one to `end`.
+fn pad_sectors(mut sectors: Vec<LayoutSector>, end: u64) -> anyhow::Result<Vec<LayoutSector>> {
+    // Sectors that end where another starts come first, so empty ones sort after what they mark the end of
+    sectors.sort_by_key(|sector| (sector.offset, sector.size != 0));
+
+    let next_offsets = sectors
+        .iter()
+        .skip(1)
+        .map(|sector| sector.offset)
+        .collect::<Vec<_>>();
+
+    for (sector, next_offset) in sectors.iter_mut().zip(next_offsets) {
+        let end = sector.end();
+        sector.padding = next_offset.checked_sub(end).with_context(|| {
+            format!(
+                "{:?} overlaps the next sector; it ends at byte {end} after {next_offset}",
+                sector.sector
+            )
+        })?;
+    }
+
+    if let Some(last) = sectors.last_mut() {
+        last.padding = end.saturating_sub(last.end());
+    }
+
+    Ok(sectors)
+}
+
+/// The end of the last block the sectors take up.
+fn block_end(sectors: &[LayoutSector]) -> u64 {
+    sectors
+        .iter()
+        .map(LayoutSector::end)
+        .max()
+        .unwrap_or(0)
+        .next_multiple_of(BLOCK_SIZE.into())
+}
+
+/// Finds the sectors of a header, along with the picture chunk tables it points to.
+pub fn header_layout(header: &ContainerHeader) -> anyhow::Result<Vec<LayoutSector>> {
+    let sectors = header_sectors(header);
+    let end = block_end(&sectors);
+    pad_sectors(sectors, end)
+}
+
+/// Finds every sector of a container, including the chunks its tables point to.
+pub async fn container_layout<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut ContainerReader<R>,
+) -> anyhow::Result<Vec<LayoutSector>> {
+    let header = reader.header().clone();
+    let mut sectors = header_sectors(&header);
+    let (chunk_sectors, chunks_end) = chunk_sectors(reader, &header).await?;
+
+    let end = block_end(&sectors).max(chunks_end);
+    sectors.extend(chunk_sectors);
+    pad_sectors(sectors, end)
+}
+
+impl Display for ContainerLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>10} {:>8} {:>8}  Sector", "Offset", "Size", "Padding")?;
+
+        for sector in &self.sectors {
+            writeln!(
+                f,
+                "{:#010x} {:>8} {:>8}  {:?}",
+                sector.offset, sector.size, sector.padding, sector.sector
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Serializes the header and tables of a container definition, without encoding any frames.
+///
+/// Each title's frame count comes from its duration, or the length of its video.
+async fn dry_run(container: &Path) -> anyhow::Result<Vec<u8>> {
+    let title_directory = get_container_directory(container)?;
+    let definition = ContainerDefinition::load(container).await?;
+    let mut titles = Vec::with_capacity(definition.titles.len());
+
+    for title in definition.titles {
+        let duration = title.duration(title_directory).await?;
+        let frame_count = (duration.as_secs_f64() * f64::from(title.fps)).round() as usize;
+        info!("Laying out \"{}\" with {frame_count} frames.", title.name);
+
+        // Picture chunks don't change where anything in the header lands
+        let frame = EncodedFrame {
+            size: 0,
+            frame_type: FrameType::Key,
+            codec: Codec::Raw,
+        };
+
+        titles.push(EncodedTitle {
+            frames: vec![frame; frame_count],
+            image_offsets: vec![0; frame_count],
+            title,
+            encode_time: Duration::ZERO,
+            quality: Vec::new(),
+        });
+    }
+
+    serialize_header(&titles).await
+}
+
+/// Prints the layout of a container definition's header as it would be encoded, or of every sector of a container.
+pub async fn layout(path: &Path, json: bool) -> anyhow::Result<()> {
+    let sectors = if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+    {
+        let header = dry_run(path).await?;
+        header_layout(&ContainerHeader::parse(&header)?)?
+    } else {
+        let file = File::open(path)
+            .await
+            .with_context(|| format!("Failed to open container: {}", path.display()))?;
+        let mut reader = ContainerReader::new(BufReader::new(file)).await?;
+        container_layout(&mut reader).await?
+    };
+
+    let layout = ContainerLayout { sectors };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&layout)?);
+    } else {
+        print!("{layout}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::tests::sample_container;
+
+    fn sector_tuples(layout: &[LayoutSector]) -> Vec<(SectorId, u64, u64, u64)> {
+        layout
+            .iter()
+            .map(|sector| (sector.sector, sector.offset, sector.size, sector.padding))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sample_container_layout() {
+        let mut reader = ContainerReader::new(Cursor::new(sample_container()))
+            .await
+            .unwrap();
+        let layout = container_layout(&mut reader).await.unwrap();
+
+        let title_index = 0;
+        let chapter_index = 0;
+        assert_eq!(
+            sector_tuples(&layout),
+            [
+                (SectorId::Header, 0, 14, 2),
+                (SectorId::TitleTable, 16, 3, 0),
+                (SectorId::Title { title_index }, 19, 29, 0),
+                (SectorId::TitleName { title_index }, 48, 5, 0),
+                (SectorId::ChapterTable { title_index }, 53, 3, 0),
+                (
+                    SectorId::Chapter {
+                        title_index,
+                        chapter_index
+                    },
+                    56,
+                    6,
+                    0
+                ),
+                (
+                    SectorId::ChapterName {
+                        title_index,
+                        chapter_index
+                    },
+                    62,
+                    6,
+                    8192 - 68
+                ),
+                (SectorId::HeaderEnd, 8192, 0, 0),
+                (SectorId::Chunks, 8192, 0, 0),
+                (SectorId::PictureChunkTable { title_index }, 8192, 10, 0),
+                (
+                    SectorId::PictureChunkTablePadding { title_index },
+                    8202,
+                    502,
+                    0
+                ),
+                // Images of 2 and 1 bytes, in a block each
+                (SectorId::PictureChunks { title_index }, 8704, 11, 1024 - 11),
+            ]
+        );
+    }
+
+    /// serseg lays sectors out back to back, filling the rest of the header and each table's last block.
+    #[tokio::test]
+    async fn encoded_header_has_no_gaps() {
+        let titles = [("First", 110), ("Second", 3)].map(|(name, frame_count)| {
+            let title = toml::from_str(&format!(
+                r#"
+                name = "{name}"
+                video = "video.mp4"
+                fps = 30
+                height = 100
+
+                [[chapters]]
+                name = "Start"
+                "#
+            ))
+            .unwrap();
+            let frame = EncodedFrame {
+                size: 600,
+                frame_type: FrameType::Key,
+                codec: Codec::Raw,
+            };
+
+            EncodedTitle {
+                frames: vec![frame; frame_count],
+                image_offsets: vec![0; frame_count],
+                title,
+                encode_time: Duration::ZERO,
+                quality: Vec::new(),
+            }
+        });
+
+        let header = ContainerHeader::parse(&serialize_header(&titles).await.unwrap()).unwrap();
+        let layout = header_layout(&header).unwrap();
+
+        assert!(
+            layout.iter().all(|sector| sector.padding == 0),
+            "{layout:#?}"
+        );
+        let header_end = layout
+            .iter()
+            .find(|sector| sector.sector == SectorId::HeaderEnd)
+            .unwrap();
+        assert_eq!(header_end.offset, u64::from(header.header_size));
+        assert_eq!(header_end.end(), u64::from(HEADER_SIZE));
+
+        // 110 entries take up 550 bytes, then 3 take up 15
+        let title_tables = layout
+            .iter()
+            .filter(|sector| {
+                matches!(
+                    sector.sector,
+                    SectorId::PictureChunkTable { .. } | SectorId::PictureChunkTablePadding { .. }
+                )
+            })
+            .map(|sector| (sector.offset, sector.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            title_tables,
+            [(8192, 550), (8742, 474), (9216, 15), (9231, 497)]
+        );
+    }
+
+    #[test]
+    fn overlapping_sectors() {
+        let mut container = sample_container();
+        // Point the title's name into the title table
+        container[19] = 17;
+
+        let header = ContainerHeader::parse(&container[..HEADER_SIZE.into()]).unwrap();
+        let error = header_layout(&header).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "TitleTable overlaps the next sector; it ends at byte 19 after 17"
+        );
+    }
+}
@@ -6,23 +6,50 @@
 #![allow(clippy::cast_precision_loss)]
 
 use std::{
+    collections::BTreeMap,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use anyhow::Context;
-use clap::Parser;
-use futures_util::{StreamExt, TryStreamExt, stream};
-use image::ImageFormat;
-use log::{debug, info, warn};
+use clap::{Parser, Subcommand};
+use log::{info, warn};
+use tokio::sync::Mutex;
 use u24::u24;
 
-use crate::definition::{container::ContainerDefinition, title::TitleDefinition};
+use crate::{
+    definition::{container::ContainerDefinition, title::TitleDefinition},
+    encode::{FrameType, PictureEncoder, frame_timestamp, registry::CodecRegistry},
+    pipeline::{EncodedImage, FramePipeline},
+    reader::ContainerReader,
+    report::BuildReport,
+    scheduler::{Scheduler, spawn_in_order},
+    serialize::EncodedTitle,
+    spool::{ImageSpool, SpoolSegment},
+};
 
+pub mod decode;
 pub mod definition;
 pub mod encode;
+pub mod extract;
+pub mod font;
 pub mod frame;
+pub mod inspect;
+pub mod layout;
+pub mod metrics;
+pub mod pipeline;
+pub mod plan;
+#[cfg(test)]
+mod player_harness;
+pub mod preview;
+pub mod reader;
+pub mod report;
+pub mod scheduler;
 pub mod serialize;
+pub mod simulate;
+pub mod spool;
+pub mod verify;
 
 pub const LCD_WIDTH: u16 = 320;
 pub const LCD_HEIGHT: u16 = 240;
@@ -31,15 +58,38 @@ pub const BLOCKS_PER_HEADER: u8 = 16;
 pub const HEADER_SIZE: u16 = BLOCK_SIZE * BLOCKS_PER_HEADER as u16;
 pub const BLOCKS_PER_CHUNK: u8 = 16;
 pub const CHUNK_SIZE: u16 = BLOCK_SIZE * BLOCKS_PER_CHUNK as u16;
+/// The calculator's CPU speed.
+pub const EZ80_CLOCK_HZ: u32 = 48_000_000;
 pub const SCHEMA_VERSION: u24 = u24::checked_from_u32(0).unwrap();
 
-pub const FRAME_FORMAT: ImageFormat = ImageFormat::Qoi;
-pub const FRAME_FORMAT_EXTENSION: &str = "qoi";
-
-pub const MAX_FILES_OPEN: u64 = 1024 * 1024;
-
 #[derive(Debug, Parser)]
 pub struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Encodes a container definition into a video binary.
+    Encode(EncodeArgs),
+    /// Estimates a container's size from samples of each title, without encoding all of it.
+    Plan(PlanArgs),
+    /// Prints what's in a video binary.
+    Inspect(InspectArgs),
+    /// Prints where each sector of a video binary lands, or would land for a container definition.
+    Layout(LayoutArgs),
+    /// Checks a video binary against everything the player relies on.
+    Verify(VerifyArgs),
+    /// Decodes a title's frames from a video binary to PNG files or a video.
+    Extract(ExtractArgs),
+    /// Renders a title with its captions the way the player shows it.
+    Preview(PreviewArgs),
+    /// Times playing a title from a simulated USB drive, finding frames that would be late.
+    Simulate(SimulateArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct EncodeArgs {
     /// A toml file defining a title container.
     container: PathBuf,
     /// The output file of the collection.
@@ -47,6 +97,134 @@ pub struct Args {
     /// The max amount of threads used for jobs. Defaults to the number of logical CPU cores.
     #[clap(short = 'j')]
     threads: Option<usize>,
+    /// Keeps encoded frames in memory instead of spooling them next to the output.
+    #[clap(long)]
+    in_memory: bool,
+    /// Also writes the build report as HTML, next to the JSON one.
+    #[clap(long)]
+    html_report: bool,
+    /// Measures the PSNR and SSIM of every frame against the source for the build report.
+    /// Always on for titles with a quality floor.
+    #[clap(long)]
+    quality: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PlanArgs {
+    /// A toml file defining a title container.
+    container: PathBuf,
+    /// The most bytes the container may take up, like `1GB` or `512MiB`.
+    #[clap(long, value_parser = plan::parse_size)]
+    max_size: Option<u64>,
+    /// How many stretches of each title to encode.
+    #[clap(long, default_value_t = plan::DEFAULT_SAMPLE_WINDOWS)]
+    samples: usize,
+    /// The max amount of threads used for jobs. Defaults to the number of logical CPU cores.
+    #[clap(short = 'j')]
+    threads: Option<usize>,
+    /// Prints JSON instead of text.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct InspectArgs {
+    /// A video binary made by the encoder.
+    container: PathBuf,
+    /// Prints JSON instead of text.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct LayoutArgs {
+    /// A video binary, or a toml file defining a title container to lay out without encoding any frames.
+    container: PathBuf,
+    /// Prints JSON instead of text.
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    /// A video binary to check before writing it to a drive.
+    container: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExtractArgs {
+    /// A video binary made by the encoder.
+    container: PathBuf,
+    /// A directory for PNG files, or a video file for FFmpeg to encode, like an MP4 or GIF.
+    out: PathBuf,
+    /// The index of the title to extract.
+    #[clap(long, default_value_t = 0)]
+    title: usize,
+    /// The first frame to extract.
+    #[clap(long, conflicts_with = "chapter")]
+    start: Option<u32>,
+    /// The frame to stop extracting at, exclusive. Defaults to the end of the title.
+    #[clap(long, conflicts_with = "chapter")]
+    end: Option<u32>,
+    /// Extracts a single chapter by index.
+    #[clap(long)]
+    chapter: Option<usize>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PreviewArgs {
+    /// A video binary made by the encoder.
+    container: PathBuf,
+    /// A video file for FFmpeg to encode, or a directory for PNG files.
+    out: PathBuf,
+    /// The index of the title to preview.
+    #[clap(long, default_value_t = 0)]
+    title: usize,
+    /// The first frame to preview.
+    #[clap(long, conflicts_with = "chapter")]
+    start: Option<u32>,
+    /// The frame to stop previewing at, exclusive. Defaults to the end of the title.
+    #[clap(long, conflicts_with = "chapter")]
+    end: Option<u32>,
+    /// Previews a single chapter by index.
+    #[clap(long)]
+    chapter: Option<usize>,
+    /// The index of the caption track to show. Defaults to the first.
+    #[clap(long)]
+    captions: Option<usize>,
+    /// A fontlibc font pack to draw captions with instead of the container's.
+    #[clap(long)]
+    font_pack: Option<PathBuf>,
+    /// How many times larger than the calculator's screen the output is.
+    #[clap(long, default_value_t = 3)]
+    scale: u32,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SimulateArgs {
+    /// A video binary made by the encoder.
+    container: PathBuf,
+    /// The index of the title to play.
+    #[clap(long, default_value_t = 0)]
+    title: usize,
+    /// Microseconds the drive takes to start each read. Defaults to one USB frame.
+    #[clap(long, default_value_t = 1_000.0)]
+    command_latency_us: f64,
+    /// Microseconds the drive takes per block read. Defaults to around full speed USB.
+    #[clap(long, default_value_t = 450.0)]
+    block_latency_us: f64,
+    /// Multiplies the estimated decode time of every frame.
+    #[clap(long, default_value_t = 1.0)]
+    decode_scale: f64,
+    /// Cycles spent drawing each frame after decoding it.
+    #[clap(long, default_value_t = simulate::DEFAULT_DRAW_CYCLES)]
+    draw_cycles: u64,
+    /// The calculator's CPU clock in hertz.
+    #[clap(long, default_value_t = EZ80_CLOCK_HZ)]
+    clock_hz: u32,
+    /// Prints JSON instead of text.
+    #[clap(long)]
+    json: bool,
 }
 
 fn get_container_directory(container: &Path) -> anyhow::Result<&Path> {
@@ -55,93 +233,156 @@ fn get_container_directory(container: &Path) -> anyhow::Result<&Path> {
         .with_context(|| format!("Failed to get container folder: {}", container.display()))
 }
 
-fn get_output_directory(output: &Path) -> anyhow::Result<&Path> {
-    output.parent().with_context(|| {
-        format!(
-            "Failed to get output file's parent directory: {}",
-            output.display()
-        )
-    })
+/// Where encoded frames are kept until the container is written.
+fn get_spool_path(output: &Path) -> PathBuf {
+    let mut spool_path = output.to_path_buf();
+    spool_path.as_mut_os_string().push(".spool");
+    spool_path
 }
 
 async fn encode_title(
     title: TitleDefinition,
-    title_directory: &Path,
-    output_directory: &Path,
-    threads: usize,
-) -> anyhow::Result<(Vec<usize>, PathBuf, TitleDefinition)> {
-    let frames_folder = Arc::new(title.frames_folder(output_directory)?);
-
-    let frame_count = title.create_frames(title_directory, &frames_folder).await?;
-    let frame_count_digits = (frame_count.checked_ilog10().unwrap_or_default() + 1) as usize;
+    title_directory: PathBuf,
+    ffmpeg_threads: usize,
+    registry: Arc<CodecRegistry>,
+    spool: Arc<Mutex<ImageSpool>>,
+    scheduler: Scheduler,
+    measure_quality: bool,
+) -> anyhow::Result<EncodedTitle> {
+    let measure_quality = measure_quality || title.quality_floor.is_some();
+
+    // Fail before spending time extracting frames
+    let picture_encoder = PictureEncoder::from_title(&title, &registry)?;
 
     let encoding_start = tokio::time::Instant::now();
 
-    let mut frame_stream = stream::iter(1..=frame_count)
-        .map(|frame_index| {
-            let frames_folder = Arc::clone(&frames_folder);
-            tokio::spawn(async move {
-                Box::pin(serialize::serialize_frame(
-                    &frames_folder,
-                    frame_index,
-                    frame_count_digits,
-                ))
-                .await
-            })
-        })
-        .buffered(threads);
+    info!("Encoding \"{}\" from FFmpeg.", title.name);
+    let mut ffmpeg_frames = title.spawn_frames(&title_directory, ffmpeg_threads)?;
 
     let mut sum = 0.0;
     let mut frames = 0u32;
+    let mut keyframes = 0u32;
+    let mut codec_frames = BTreeMap::new();
+
+    let mut spool = SpoolSegment::new(spool);
+    let mut encoded_frames = Vec::new();
+    let mut quality = Vec::new();
+
+    let mut pipeline = FramePipeline::new(picture_encoder, scheduler).with_quality(measure_quality);
+
+    while let Some(EncodedImage {
+        frame: encoded_frame,
+        image,
+        quality: frame_quality,
+    }) = pipeline.next(&mut ffmpeg_frames).await?
+    {
+        spool
+            .append(&image)
+            .await
+            .with_context(|| format!("Failed to spool frame {frames}"))?;
+        frames += 1;
 
-    let mut frame_sizes = Vec::new();
+        sum += encoded_frame.size as f32;
+        quality.extend(frame_quality);
 
-    while let Some(join) = frame_stream.next().await {
-        let bytes = join??;
-        sum += bytes as f32;
-        frames += 1;
+        if encoded_frame.frame_type == FrameType::Key {
+            keyframes += 1;
+        }
 
-        frame_sizes.push(bytes);
+        *codec_frames.entry(encoded_frame.codec).or_insert(0u32) += 1;
 
-        if frames.is_multiple_of(title.fps.into()) || frames == frame_count {
-            info!(
-                "Encoding frames: {:>frame_count_digits$}/{} {:>6.2}%",
-                frames,
-                frame_count,
-                (frames as f32 / frame_count as f32) * 100.0
-            );
+        encoded_frames.push(encoded_frame);
+
+        if frames.is_multiple_of(title.fps.into()) {
+            info!("Encoding \"{}\": completed {frames} frames", title.name);
         }
     }
 
-    let time = encoding_start.elapsed().as_secs_f32() * 1_000.0;
-    info!("Encoding took {time:.2} MS.");
+    ffmpeg_frames.finish().await?;
+    let image_offsets = spool.finish().await?;
+
+    let encode_time = encoding_start.elapsed();
+    info!(
+        "Encoding \"{}\" took {:.2} MS.",
+        title.name,
+        encode_time.as_secs_f32() * 1_000.0
+    );
     info!("Average size {:.0} bytes.", sum / frames as f32);
+    info!("Keyframes {keyframes}/{frames}.");
 
-    Ok((frame_sizes, frames_folder.to_path_buf(), title))
-}
+    for (codec, codec_frames) in codec_frames {
+        info!(
+            "{codec:?} chosen for {codec_frames}/{frames} frames {:.2}%.",
+            (codec_frames as f32 / frames as f32) * 100.0
+        );
+    }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    if !quality.is_empty() {
+        let average = |score: fn(&metrics::FrameQuality) -> f64| {
+            quality.iter().map(score).sum::<f64>() / quality.len() as f64
+        };
+        info!(
+            "Average PSNR {:.2} dB, SSIM {:.4}.",
+            average(|quality| quality.psnr),
+            average(|quality| quality.ssim)
+        );
+    }
 
-    let args = Args::try_parse()?;
+    if let Some(quality_floor) = &title.quality_floor {
+        let start = title.start.map(Duration::from).unwrap_or_default();
 
-    if let Err(error) = rlimit::increase_nofile_limit(MAX_FILES_OPEN) {
-        warn!("Failed to increase max files open to {MAX_FILES_OPEN}:\n{error}");
-    } else {
-        debug!("Increased max files open to {MAX_FILES_OPEN}.");
+        for scene in quality_floor.settings().scenes_below(&quality) {
+            warn!(
+                "\"{}\" is below the quality floor for frames {scene:?} from {}.",
+                title.name,
+                frame_timestamp(start, title.fps, scene.start)
+            );
+        }
     }
 
-    let threads = args.threads.unwrap_or_else(num_cpus::get);
-
-    let container_directory = get_container_directory(&args.container)?;
-    let output_directory = get_output_directory(&args.out)?;
+    Ok(EncodedTitle {
+        frames: encoded_frames,
+        image_offsets,
+        title,
+        encode_time,
+        quality,
+    })
+}
 
-    let container = ContainerDefinition::load(&args.container).await?;
+/// Encodes every title into the spool, then writes the container from it.
+async fn write_container(
+    args: &EncodeArgs,
+    container: ContainerDefinition,
+    container_directory: &Path,
+    spool: ImageSpool,
+    threads: usize,
+) -> anyhow::Result<Vec<EncodedTitle>> {
+    let registry = Arc::new(CodecRegistry::default());
+    let scheduler = Scheduler::new(threads);
+    let spool = Arc::new(Mutex::new(spool));
+
+    // Titles are encoded at the same time, splitting FFmpeg's threads between them
+    let titles_at_once = container.titles.len().clamp(1, threads.max(1));
+    let ffmpeg_threads = threads.div_ceil(titles_at_once);
+
+    let encoded_titles = spawn_in_order(container.titles, titles_at_once, |title| {
+        encode_title(
+            title,
+            container_directory.to_path_buf(),
+            ffmpeg_threads,
+            Arc::clone(&registry),
+            Arc::clone(&spool),
+            scheduler.clone(),
+            args.quality,
+        )
+    })
+    .await?;
 
-    let encoded_titles = stream::iter(container.titles)
-        .then(|title| encode_title(title, container_directory, output_directory, threads))
-        .try_collect()
+    let mut spool = Arc::try_unwrap(spool)
+        .ok()
+        .context("Spool still in use after encoding")?
+        .into_inner()
+        .into_reader()
         .await?;
 
     let output_buffer = tokio::io::BufWriter::with_capacity(
@@ -151,9 +392,117 @@ async fn main() -> anyhow::Result<()> {
             .with_context(|| format!("Failed to open output: {}", args.out.display()))?,
     );
 
-    serialize::serialize_container(encoded_titles, output_buffer).await?;
+    serialize::serialize_container(&encoded_titles, &mut spool, output_buffer).await?;
+    spool.remove().await?;
 
-    Ok(())
+    Ok(encoded_titles)
+}
+
+async fn encode(args: EncodeArgs) -> anyhow::Result<()> {
+    let threads = args.threads.unwrap_or_else(num_cpus::get);
+
+    let container_directory = get_container_directory(&args.container)?;
+    let spool_path = get_spool_path(&args.out);
+
+    let container = ContainerDefinition::load(&args.container).await?;
+
+    let spool = if args.in_memory {
+        ImageSpool::memory()
+    } else {
+        ImageSpool::create(spool_path.clone()).await?
+    };
+
+    let encoded_titles =
+        match write_container(&args, container, container_directory, spool, threads).await {
+            Ok(encoded_titles) => encoded_titles,
+            Err(error) => {
+                // Titles still encoding when another fails may hold the spool open, so it's removed by path
+                if !args.in_memory
+                    && let Err(remove_error) = tokio::fs::remove_file(&spool_path).await
+                    && remove_error.kind() != std::io::ErrorKind::NotFound
+                {
+                    warn!(
+                        "Failed to remove spool: {}: {remove_error}",
+                        spool_path.display()
+                    );
+                }
+                return Err(error);
+            }
+        };
+
+    // The header's layout is only known once serseg has laid it out
+    let output = tokio::fs::File::open(&args.out)
+        .await
+        .with_context(|| format!("Failed to open output: {}", args.out.display()))?;
+    let mut reader = ContainerReader::new(tokio::io::BufReader::new(output))
+        .await
+        .context("Failed to read back the container's header")?;
+    let layout = layout::container_layout(&mut reader)
+        .await
+        .context("Failed to lay out the container")?;
+
+    let report = BuildReport::new(&encoded_titles, layout);
+    report::write_reports(&report, &args.out, args.html_report).await
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    match Args::try_parse()?.command {
+        Command::Encode(args) => encode(args).await,
+        Command::Plan(args) => {
+            let options = plan::PlanOptions {
+                max_size: args.max_size,
+                sample_windows: args.samples,
+                threads: args.threads.unwrap_or_else(num_cpus::get),
+            };
+            plan::plan(&args.container, options, args.json).await
+        }
+        Command::Inspect(args) => inspect::inspect(&args.container, args.json).await,
+        Command::Layout(args) => layout::layout(&args.container, args.json).await,
+        Command::Verify(args) => verify::verify(&args.container).await,
+        Command::Extract(args) => {
+            let selection = extract::FrameSelection {
+                start: args.start,
+                end: args.end,
+                chapter: args.chapter,
+            };
+            extract::extract(&args.container, &args.out, args.title, selection).await
+        }
+        Command::Preview(args) => {
+            let selection = extract::FrameSelection {
+                start: args.start,
+                end: args.end,
+                chapter: args.chapter,
+            };
+            let options = preview::PreviewOptions {
+                caption_track: args.captions,
+                font_pack: args.font_pack,
+                scale: args.scale,
+            };
+            preview::preview(&args.container, &args.out, args.title, selection, options).await
+        }
+        Command::Simulate(args) => {
+            let device_model = simulate::DeviceModel {
+                command_latency_us: args.command_latency_us,
+                block_latency_us: args.block_latency_us,
+            };
+            let decode_model = simulate::DecodeModel {
+                clock_hz: args.clock_hz,
+                cost_scale: args.decode_scale,
+                draw_cycles: args.draw_cycles,
+            };
+            simulate::simulate_file(
+                &args.container,
+                args.title,
+                device_model,
+                decode_model,
+                args.json,
+            )
+            .await
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,188 @@
+//! Objective quality of frames as the player shows them, measured against the source video.
+
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::serialize::color_space_to_rgb;
+
+/// PSNR of identical frames, which would otherwise be infinite.
+pub const MAX_PSNR: f64 = 100.0;
+/// Width and height of the windows SSIM is averaged over.
+const SSIM_WINDOW: usize = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FrameQuality {
+    /// Peak signal to noise ratio over every RGB channel, in decibels.
+    pub psnr: f64,
+    /// Structural similarity of the luma, from `-1` to `1`.
+    pub ssim: f64,
+}
+
+/// Frames below either score are flagged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QualityFloor {
+    pub psnr: Option<f64>,
+    pub ssim: Option<f64>,
+}
+
+impl QualityFloor {
+    pub fn is_below(&self, quality: FrameQuality) -> bool {
+        self.psnr.is_some_and(|psnr| quality.psnr < psnr)
+            || self.ssim.is_some_and(|ssim| quality.ssim < ssim)
+    }
+
+    /// Runs of consecutive frames below the floor.
+    pub fn scenes_below(&self, quality: &[FrameQuality]) -> Vec<Range<u32>> {
+        let mut scenes = Vec::<Range<u32>>::new();
+
+        for (frame_index, &frame_quality) in (0..).zip(quality) {
+            if !self.is_below(frame_quality) {
+                continue;
+            }
+
+            match scenes.last_mut() {
+                Some(scene) if scene.end == frame_index => scene.end += 1,
+                _ => scenes.push(frame_index..frame_index + 1),
+            }
+        }
+
+        scenes
+    }
+}
+
+/// BT.601 luma.
+fn luma([red, green, blue]: [u8; 3]) -> f64 {
+    0.299 * f64::from(red) + 0.587 * f64::from(green) + 0.114 * f64::from(blue)
+}
+
+fn psnr(source: &[[u8; 3]], displayed: &[[u8; 3]]) -> f64 {
+    let squared_error = source
+        .iter()
+        .flatten()
+        .zip(displayed.iter().flatten())
+        .map(|(&a, &b)| {
+            let difference = f64::from(a) - f64::from(b);
+            difference * difference
+        })
+        .sum::<f64>();
+    let mean_squared_error = squared_error / (source.len() * 3).max(1) as f64;
+
+    if mean_squared_error == 0.0 {
+        return MAX_PSNR;
+    }
+
+    (10.0 * (255.0 * 255.0 / mean_squared_error).log10()).min(MAX_PSNR)
+}
+
+/// Mean SSIM over windows of the frame, with smaller windows at the right and bottom edges.
+fn ssim(source: &[f64], displayed: &[f64], width: usize) -> f64 {
+    let height = source.len() / width;
+    let mut total = 0.0;
+    let mut windows = 0;
+
+    for top in (0..height).step_by(SSIM_WINDOW) {
+        for left in (0..width).step_by(SSIM_WINDOW) {
+            let pixels = (top..(top + SSIM_WINDOW).min(height))
+                .flat_map(|y| (left..(left + SSIM_WINDOW).min(width)).map(move |x| y * width + x))
+                .map(|pixel| (source[pixel], displayed[pixel]))
+                .collect::<Vec<_>>();
+            let count = pixels.len() as f64;
+
+            let mean_a = pixels.iter().map(|&(a, _)| a).sum::<f64>() / count;
+            let mean_b = pixels.iter().map(|&(_, b)| b).sum::<f64>() / count;
+            let (mut variance_a, mut variance_b, mut covariance) = (0.0, 0.0, 0.0);
+
+            for &(a, b) in &pixels {
+                variance_a += (a - mean_a) * (a - mean_a);
+                variance_b += (b - mean_b) * (b - mean_b);
+                covariance += (a - mean_a) * (b - mean_b);
+            }
+
+            variance_a /= count;
+            variance_b /= count;
+            covariance /= count;
+
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1)
+                    * (variance_a + variance_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+
+    total / f64::from(windows.max(1))
+}
+
+/// Compares a source frame in `rgb24` with the frame the player shows in its default palette.
+pub fn frame_quality(source_rgb: &[u8], displayed: &[u8], width: usize) -> FrameQuality {
+    let (source, _) = source_rgb.as_chunks::<3>();
+    let displayed = displayed
+        .iter()
+        .map(|&pixel| color_space_to_rgb(pixel))
+        .collect::<Vec<_>>();
+
+    let source_luma = source.iter().map(|&pixel| luma(pixel)).collect::<Vec<_>>();
+    let displayed_luma = displayed
+        .iter()
+        .map(|&pixel| luma(pixel))
+        .collect::<Vec<_>>();
+
+    FrameQuality {
+        psnr: psnr(source, &displayed),
+        ssim: ssim(&source_luma, &displayed_luma, width),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_frames() {
+        // White and black are exact in the default palette
+        let source = [[255; 3], [0; 3]].repeat(32).concat();
+        let displayed = [0xFF, 0].repeat(32);
+
+        let quality = frame_quality(&source, &displayed, 8);
+        assert_eq!(quality.psnr, MAX_PSNR);
+        assert!((quality.ssim - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quantized_frames() {
+        // Mid gray isn't a color of the default palette
+        let source = [[128, 128, 128]; 64].concat();
+        let displayed = crate::serialize::rgb_to_color_space(&source);
+
+        let quality = frame_quality(&source, &displayed, 8);
+        let [red, green, blue] = color_space_to_rgb(displayed[0]);
+        let squared_error = [red, green, blue]
+            .map(|channel| (128.0 - f64::from(channel)).powi(2))
+            .iter()
+            .sum::<f64>()
+            / 3.0;
+        assert!((quality.psnr - 10.0 * (255.0f64 * 255.0 / squared_error).log10()).abs() < 1e-9);
+        assert!(quality.ssim < 1.0);
+    }
+
+    #[test]
+    fn scenes_below_floor() {
+        let floor = QualityFloor {
+            psnr: Some(30.0),
+            ssim: Some(0.9),
+        };
+        let quality = [
+            (40.0, 0.95),
+            (20.0, 0.95),
+            (40.0, 0.5),
+            (40.0, 0.95),
+            (10.0, 0.1),
+        ]
+        .map(|(psnr, ssim)| FrameQuality { psnr, ssim });
+
+        assert_eq!(floor.scenes_below(&quality), [1..3, 4..5]);
+        assert!(QualityFloor::default().scenes_below(&quality).is_empty());
+    }
+}
@@ -0,0 +1,407 @@
+//! Encodes a title's frames from FFmpeg on the scheduler's threads.
+
+use std::collections::VecDeque;
+
+use anyhow::Context;
+use futures_util::{FutureExt, StreamExt, future::BoxFuture, stream::FuturesOrdered};
+use log::debug;
+
+use crate::{
+    LCD_HEIGHT, LCD_WIDTH,
+    encode::{EncodedFrame, PictureEncoder},
+    frame::FFmpegFrames,
+    metrics::{self, FrameQuality},
+    scheduler::Scheduler,
+};
+
+/// Frames encoding at once for each of the scheduler's threads, so threads aren't left waiting on FFmpeg.
+const JOBS_PER_THREAD: usize = 2;
+
+/// A frame encoded by a [`FramePipeline`].
+#[derive(Debug)]
+pub struct EncodedImage {
+    pub frame: EncodedFrame,
+    pub image: Vec<u8>,
+    /// How close what the player displays is to FFmpeg's frame, if measured.
+    pub quality: Option<FrameQuality>,
+}
+
+/// A frame encoded on the scheduler's threads.
+struct FrameJob {
+    encoded_image: EncodedImage,
+    /// The encoder split off for the frame, if it was encoded at the same time as others.
+    fork: Option<PictureEncoder>,
+}
+
+/// Encodes frames in order, encoding frames that don't depend on each other at the same time.
+///
+/// Frames can only be split off with [`PictureEncoder::fork`]; codecs that keep state,
+/// motion compensation, near lossless and rate control all build on the last frame as encoded,
+/// so their frames are encoded one at a time.
+///
+/// Split off frames are encoded as if every frame before them displays unchanged.
+/// When one is requantized or falls back to a lossy codec instead,
+/// every frame after it is encoded again from what the player actually displays.
+pub struct FramePipeline {
+    /// Only `None` while encoding a frame one at a time.
+    picture_encoder: Option<PictureEncoder>,
+    scheduler: Scheduler,
+    measure_quality: bool,
+    /// Frames waiting for rate control to see far enough ahead of them, with FFmpeg's pixels if measuring quality.
+    waiting: VecDeque<(Vec<u8>, Option<Vec<u8>>)>,
+    jobs: FuturesOrdered<BoxFuture<'static, anyhow::Result<FrameJob>>>,
+    /// The frame of each job, to start it again from if a frame before it didn't display unchanged.
+    in_flight: VecDeque<(Vec<u8>, Option<Vec<u8>>)>,
+    ffmpeg_finished: bool,
+}
+
+impl FramePipeline {
+    pub fn new(picture_encoder: PictureEncoder, scheduler: Scheduler) -> Self {
+        Self {
+            picture_encoder: Some(picture_encoder),
+            scheduler,
+            measure_quality: false,
+            waiting: VecDeque::new(),
+            jobs: FuturesOrdered::new(),
+            in_flight: VecDeque::new(),
+            ffmpeg_finished: false,
+        }
+    }
+
+    /// Measures the PSNR and SSIM of every frame against FFmpeg's.
+    #[must_use]
+    pub fn with_quality(mut self, measure_quality: bool) -> Self {
+        self.measure_quality = measure_quality;
+        self
+    }
+
+    /// Returns `None` once FFmpeg is out of frames and every frame has been returned.
+    pub async fn next(
+        &mut self,
+        ffmpeg_frames: &mut FFmpegFrames,
+    ) -> anyhow::Result<Option<EncodedImage>> {
+        let max_jobs = self.scheduler.threads() * JOBS_PER_THREAD;
+
+        while !self.ffmpeg_finished && self.jobs.len() < max_jobs {
+            let Some(frame) = ffmpeg_frames.next_frame().await? else {
+                self.ffmpeg_finished = true;
+
+                while let Some((frame, source_frame)) = self.waiting.pop_front() {
+                    self.start(frame, source_frame).await?;
+                }
+
+                break;
+            };
+
+            let source_frame = self
+                .measure_quality
+                .then(|| ffmpeg_frames.source_frame().to_vec());
+            self.look_ahead(frame, source_frame).await?;
+        }
+
+        let Some(FrameJob {
+            encoded_image,
+            fork,
+        }) = self.jobs.next().await.transpose()?
+        else {
+            return Ok(None);
+        };
+
+        let (frame, _) = self
+            .in_flight
+            .pop_front()
+            .context("Frame was encoded without being started")?;
+
+        if let Some(fork) = fork
+            && fork.previous_frame() != Some(frame.as_slice())
+        {
+            debug!(
+                "Frame {} doesn't display unchanged, encoding the frames after it again",
+                fork.frame_index() - 1
+            );
+
+            // Jobs already running finish on their own, with nothing waiting on them
+            self.jobs = FuturesOrdered::new();
+            self.picture_encoder = Some(fork);
+
+            for (frame, source_frame) in std::mem::take(&mut self.in_flight) {
+                self.start(frame, source_frame).await?;
+            }
+        }
+
+        Ok(Some(encoded_image))
+    }
+
+    fn picture_encoder(&mut self) -> anyhow::Result<PictureEncoder> {
+        self.picture_encoder
+            .take()
+            .context("Encoder was lost to a frame that didn't finish")
+    }
+
+    /// Starts encoding the oldest waiting frame once rate control has seen far enough ahead of it.
+    async fn look_ahead(
+        &mut self,
+        frame: Vec<u8>,
+        source_frame: Option<Vec<u8>>,
+    ) -> anyhow::Result<()> {
+        let mut picture_encoder = self.picture_encoder()?;
+        let (picture_encoder, frame) = self
+            .scheduler
+            .run(move || {
+                picture_encoder.look_ahead(&frame);
+                (picture_encoder, frame)
+            })
+            .await?;
+
+        let look_ahead = picture_encoder.look_ahead_frames();
+        self.picture_encoder = Some(picture_encoder);
+        self.waiting.push_back((frame, source_frame));
+
+        if self.waiting.len() > look_ahead
+            && let Some((frame, source_frame)) = self.waiting.pop_front()
+        {
+            self.start(frame, source_frame).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn start(&mut self, frame: Vec<u8>, source_frame: Option<Vec<u8>>) -> anyhow::Result<()> {
+        let mut picture_encoder = self.picture_encoder()?;
+        self.in_flight
+            .push_back((frame.clone(), source_frame.clone()));
+
+        if let Some(mut fork) = picture_encoder.fork(&frame) {
+            self.picture_encoder = Some(picture_encoder);
+
+            let scheduler = self.scheduler.clone();
+            let job = tokio::spawn(async move {
+                scheduler
+                    .run(move || {
+                        let encoded_image = encode_image(&mut fork, frame, source_frame)?;
+                        anyhow::Ok(FrameJob {
+                            encoded_image,
+                            fork: Some(fork),
+                        })
+                    })
+                    .await?
+            });
+            self.jobs.push_back(async move { job.await? }.boxed());
+        } else {
+            let (picture_encoder, encoded) = self
+                .scheduler
+                .run(move || {
+                    let encoded = encode_image(&mut picture_encoder, frame, source_frame);
+                    (picture_encoder, encoded)
+                })
+                .await?;
+
+            self.picture_encoder = Some(picture_encoder);
+            let job = encoded.map(|encoded_image| FrameJob {
+                encoded_image,
+                fork: None,
+            });
+            self.jobs.push_back(std::future::ready(job).boxed());
+        }
+
+        Ok(())
+    }
+}
+
+fn encode_image(
+    picture_encoder: &mut PictureEncoder,
+    frame: Vec<u8>,
+    source_frame: Option<Vec<u8>>,
+) -> anyhow::Result<EncodedImage> {
+    let frame_index = picture_encoder.frame_index();
+    let frame_len = frame.len();
+
+    let mut image = vec![0; usize::from(LCD_WIDTH) * usize::from(LCD_HEIGHT)];
+    let encoded_frame = picture_encoder.encode(frame, &mut image)?;
+    image.truncate(encoded_frame.size);
+
+    debug!(
+        "Compressed {:?} {:?} frame {frame_index}: {frame_len} bytes => {} bytes, {:>6.2}%",
+        encoded_frame.frame_type,
+        encoded_frame.codec,
+        encoded_frame.size,
+        (encoded_frame.size as f32 / frame_len as f32) * 100.0,
+    );
+
+    let quality = source_frame
+        .map(|source_frame| {
+            let displayed_frame = picture_encoder
+                .previous_frame()
+                .context("Encoder didn't keep the frame it encoded")?;
+            anyhow::Ok(metrics::frame_quality(
+                &source_frame,
+                displayed_frame,
+                LCD_WIDTH.into(),
+            ))
+        })
+        .transpose()?;
+
+    Ok(EncodedImage {
+        frame: encoded_frame,
+        image,
+        quality,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::{
+            Codec, max_image_size,
+            registry::{CodecOptions, CodecRegistry},
+            tests::lcg,
+        },
+        frame::tests::fake_ffmpeg,
+        serialize::rgb_to_color_space,
+    };
+
+    const HEIGHT: u8 = 16;
+
+    /// A gray bar moving over black, as FFmpeg's `rgb24` pixels.
+    fn rgb_frames(count: usize) -> Vec<Vec<u8>> {
+        let width = usize::from(LCD_WIDTH);
+
+        (0..count)
+            .map(|frame_index| {
+                (0..width * usize::from(HEIGHT))
+                    .flat_map(|pixel| {
+                        let x = pixel % width;
+                        let value = if (frame_index * 9..frame_index * 9 + 20).contains(&x) {
+                            0x80
+                        } else {
+                            0
+                        };
+                        [value; 3]
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The moving bar, with every third frame covered in noise that only fits one block once requantized.
+    fn noisy_rgb_frames(count: usize) -> Vec<Vec<u8>> {
+        rgb_frames(count)
+            .into_iter()
+            .zip(lcg(0x5EED))
+            .enumerate()
+            .map(|(frame_index, (frame, seed))| {
+                if frame_index % 3 != 1 {
+                    return frame;
+                }
+
+                // Red and green of 0 or 1, which requantizing merges
+                lcg(seed)
+                    .take(frame.len() / 3)
+                    .flat_map(|sample| [(sample & 1) as u8 * 32, (sample & 2) as u8 * 16, 0])
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn picture_encoder(codec: &str) -> PictureEncoder {
+        let encoder = CodecRegistry::default()
+            .create(codec, &CodecOptions::default())
+            .unwrap();
+        PictureEncoder::new(4, 0.5, [6]).with_encoder(encoder)
+    }
+
+    /// Encodes the frames through a pipeline, comparing them to encoding them one at a time.
+    ///
+    /// Returns the encoded frames and how many frames the player doesn't display unchanged.
+    async fn assert_matches_serial(
+        frames: &[Vec<u8>],
+        picture_encoder: impl Fn() -> PictureEncoder,
+        threads: usize,
+    ) -> (Vec<EncodedImage>, usize) {
+        let mut ffmpeg_frames = fake_ffmpeg(frames.concat(), HEIGHT, 0);
+        let mut pipeline =
+            FramePipeline::new(picture_encoder(), Scheduler::new(threads)).with_quality(true);
+
+        let mut encoded_images = Vec::new();
+        while let Some(encoded_image) = pipeline.next(&mut ffmpeg_frames).await.unwrap() {
+            encoded_images.push(encoded_image);
+        }
+        ffmpeg_frames.finish().await.unwrap();
+
+        let mut serial_encoder = picture_encoder();
+        let mut changed_frames = 0;
+        assert_eq!(encoded_images.len(), frames.len());
+
+        for (frame_index, (frame, encoded_image)) in frames.iter().zip(&encoded_images).enumerate()
+        {
+            let color_space_frame = rgb_to_color_space(frame);
+            let expected = encode_image(
+                &mut serial_encoder,
+                color_space_frame.clone(),
+                Some(frame.clone()),
+            )
+            .unwrap();
+
+            if serial_encoder.previous_frame() != Some(color_space_frame.as_slice()) {
+                changed_frames += 1;
+            }
+
+            assert_eq!(
+                (encoded_image.frame.frame_type, encoded_image.frame.codec),
+                (expected.frame.frame_type, expected.frame.codec),
+                "frame {frame_index}"
+            );
+            assert_eq!(encoded_image.image, expected.image, "frame {frame_index}");
+            assert_eq!(
+                encoded_image.quality, expected.quality,
+                "frame {frame_index}"
+            );
+        }
+
+        (encoded_images, changed_frames)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn forked_frames_match_serial() {
+        let (encoded_images, _) =
+            assert_matches_serial(&rgb_frames(12), || picture_encoder("qoi"), 4).await;
+        assert!(
+            encoded_images
+                .iter()
+                .all(|encoded_image| encoded_image.frame.codec == Codec::Qoi)
+        );
+    }
+
+    /// Frames after one that's requantized to fit are encoded again from what the player displays.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn requantized_forks_match_serial() {
+        let (encoded_images, changed_frames) = assert_matches_serial(
+            &noisy_rgb_frames(12),
+            || picture_encoder("qoi").with_max_blocks(1),
+            4,
+        )
+        .await;
+
+        assert_eq!(changed_frames, 4);
+        assert!(
+            encoded_images
+                .iter()
+                .all(|encoded_image| encoded_image.image.len() <= max_image_size(1))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn stateful_frames_match_serial() {
+        assert_matches_serial(&rgb_frames(12), || picture_encoder("vq"), 4).await;
+    }
+
+    #[tokio::test]
+    async fn ffmpeg_failing_is_an_error() {
+        let mut ffmpeg_frames = fake_ffmpeg(Vec::new(), HEIGHT, 1);
+        let mut pipeline = FramePipeline::new(picture_encoder("qoi"), Scheduler::new(1));
+
+        assert!(pipeline.next(&mut ffmpeg_frames).await.unwrap().is_none());
+        assert!(ffmpeg_frames.finish().await.is_err());
+    }
+}
@@ -0,0 +1,490 @@
+//! Estimates how large a container will be from samples of each title, before encoding all of it.
+
+use std::{
+    fmt::{self, Display},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    BLOCK_SIZE, HEADER_SIZE,
+    definition::{container::ContainerDefinition, title::TitleDefinition},
+    encode::{
+        EncodedFrame, FrameType, PictureEncoder, motion::MOTION_BLOCK_SIZE, registry::CodecRegistry,
+    },
+    get_container_directory,
+    pipeline::FramePipeline,
+    report::BlockPercentiles,
+    scheduler::{Scheduler, spawn_in_order},
+    serialize::{PICTURE_CHUNK_TABLE_ENTRY_SIZE, picture_chunk_blocks},
+};
+
+/// Stretches of each title encoded to estimate the rest.
+pub const DEFAULT_SAMPLE_WINDOWS: usize = 8;
+/// Long enough for delta frames to settle after the keyframe each window starts with.
+const SAMPLE_WINDOW_SECONDS: u64 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlanOptions {
+    pub max_size: Option<u64>,
+    pub sample_windows: usize,
+    pub threads: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContainerPlan {
+    pub titles: Vec<TitlePlan>,
+    pub estimated_size: u64,
+    pub max_size: Option<u64>,
+    /// Changes that would each make the container fit on their own.
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitlePlan {
+    pub name: String,
+    pub fps: u8,
+    pub height: u8,
+    pub frame_count: u64,
+    pub sampled_frames: usize,
+    /// The fraction of frames expected to be keyframes, from the samples and the keyframe interval.
+    pub keyframe_fraction: f64,
+    pub average_blocks: f64,
+    /// From the sampled frames.
+    pub blocks_per_frame: BlockPercentiles,
+    /// The picture chunks and their table.
+    pub estimated_size: u64,
+}
+
+/// A setting of a title, scaled down so everything fits.
+#[derive(Debug, Serialize)]
+#[serde(tag = "setting", rename_all = "snake_case")]
+pub enum Suggestion {
+    Fps {
+        title_index: usize,
+        fps: u8,
+    },
+    Height {
+        title_index: usize,
+        height: u8,
+    },
+    RateControl {
+        title_index: usize,
+        blocks_per_frame: f64,
+    },
+}
+
+/// Parses a size in bytes with an optional decimal (`K`, `M`, `G`) or binary (`KiB`, `MiB`, `GiB`) unit.
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|character: char| !character.is_ascii_digit() && character != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        unit => anyhow::bail!("Unknown size unit: {unit}"),
+    };
+    let number = number
+        .parse::<f64>()
+        .with_context(|| format!("Invalid size: {size}"))?;
+
+    Ok((number * scale as f64) as u64)
+}
+
+/// Encodes a stretch of a title from `start` with a fresh encoder, the same way `encode` does.
+async fn encode_window(
+    title: Arc<TitleDefinition>,
+    title_directory: Arc<Path>,
+    registry: Arc<CodecRegistry>,
+    scheduler: Scheduler,
+    start: Duration,
+    duration: Duration,
+) -> anyhow::Result<Vec<EncodedFrame>> {
+    let picture_encoder = PictureEncoder::from_title(&title, &registry)?;
+    let mut ffmpeg_frames =
+        title.spawn_frame_range(&title_directory, 1, Some(start), Some(duration))?;
+    let mut pipeline = FramePipeline::new(picture_encoder, scheduler);
+    let mut encoded_frames = Vec::new();
+
+    while let Some(encoded_image) = pipeline.next(&mut ffmpeg_frames).await.with_context(|| {
+        format!(
+            "Failed to encode a sample of \"{}\" from {:.3}s",
+            title.name,
+            start.as_secs_f64()
+        )
+    })? {
+        encoded_frames.push(encoded_image.frame);
+    }
+
+    ffmpeg_frames.finish().await?;
+
+    Ok(encoded_frames)
+}
+
+impl TitlePlan {
+    /// `samples` are the frames of each window, each starting with a keyframe.
+    fn new(title: &TitleDefinition, frame_count: u64, samples: &[Vec<EncodedFrame>]) -> Self {
+        let frames = samples.iter().flatten();
+        let average = |frame_type: FrameType| {
+            let blocks = frames
+                .clone()
+                .filter(|frame| frame.frame_type == frame_type)
+                .map(picture_chunk_blocks)
+                .collect::<Vec<_>>();
+            (!blocks.is_empty()).then(|| blocks.iter().sum::<usize>() as f64 / blocks.len() as f64)
+        };
+
+        let key_blocks = average(FrameType::Key).unwrap_or_default();
+        let delta_blocks = average(FrameType::Delta).unwrap_or(key_blocks);
+
+        // The first keyframe of each window is forced, so only later ones show how often scenes cut
+        let later_frames = samples
+            .iter()
+            .map(|window| window.len().saturating_sub(1))
+            .sum::<usize>();
+        let scene_cuts = samples
+            .iter()
+            .flat_map(|window| window.iter().skip(1))
+            .filter(|frame| frame.frame_type == FrameType::Key)
+            .count();
+        let observed_keyframes = scene_cuts as f64 / later_frames.max(1) as f64;
+        let keyframe_fraction = (observed_keyframes
+            .max(1.0 / f64::from(title.keyframe_interval().max(1)))
+            + title.chapters.len() as f64 / frame_count.max(1) as f64)
+            .min(1.0);
+
+        let average_blocks =
+            keyframe_fraction * key_blocks + (1.0 - keyframe_fraction) * delta_blocks;
+        let chunk_blocks = (average_blocks * frame_count as f64).ceil() as u64;
+        let table_blocks =
+            (frame_count * PICTURE_CHUNK_TABLE_ENTRY_SIZE as u64).div_ceil(BLOCK_SIZE.into());
+
+        Self {
+            name: title.name.clone(),
+            fps: title.fps,
+            height: title.height,
+            frame_count,
+            sampled_frames: frames.clone().count(),
+            keyframe_fraction,
+            average_blocks,
+            blocks_per_frame: BlockPercentiles::new(frames.map(picture_chunk_blocks).collect()),
+            estimated_size: (chunk_blocks + table_blocks) * u64::from(BLOCK_SIZE),
+        }
+    }
+}
+
+impl ContainerPlan {
+    fn new(titles: Vec<TitlePlan>, max_size: Option<u64>) -> anyhow::Result<Self> {
+        let estimated_size =
+            u64::from(HEADER_SIZE) + titles.iter().map(|title| title.estimated_size).sum::<u64>();
+
+        let mut plan = Self {
+            titles,
+            estimated_size,
+            max_size,
+            suggestions: Vec::new(),
+        };
+
+        if let Some(max_size) = max_size
+            && !plan.fits()
+        {
+            plan.suggestions = plan.suggest(max_size)?;
+        }
+
+        Ok(plan)
+    }
+
+    pub fn fits(&self) -> bool {
+        self.max_size
+            .is_none_or(|max_size| self.estimated_size <= max_size)
+    }
+
+    /// Scales every title down by the same amount, assuming size follows frame count and pixels.
+    fn suggest(&self, max_size: u64) -> anyhow::Result<Vec<Suggestion>> {
+        let picture_size = self.estimated_size - u64::from(HEADER_SIZE);
+        let budget = max_size
+            .checked_sub(u64::from(HEADER_SIZE))
+            .filter(|&budget| budget > 0)
+            .context("Max size doesn't fit the container's header")?;
+        let scale = budget as f64 / picture_size as f64;
+
+        let mut suggestions = Vec::new();
+
+        for (title_index, title) in self.titles.iter().enumerate() {
+            let fps = (f64::from(title.fps) * scale).floor() as u8;
+            if fps != 0 {
+                suggestions.push(Suggestion::Fps { title_index, fps });
+            }
+
+            let block_rows = (f64::from(title.height) * scale / MOTION_BLOCK_SIZE as f64).floor();
+            let height = (block_rows * MOTION_BLOCK_SIZE as f64) as u8;
+            if height != 0 {
+                suggestions.push(Suggestion::Height {
+                    title_index,
+                    height,
+                });
+            }
+
+            // Rate control can't go under a block per frame
+            let blocks_per_frame = (title.average_blocks * scale * 100.0).floor() / 100.0;
+            if blocks_per_frame >= 1.0 {
+                suggestions.push(Suggestion::RateControl {
+                    title_index,
+                    blocks_per_frame,
+                });
+            }
+        }
+
+        Ok(suggestions)
+    }
+}
+
+/// Displays bytes along with MiB.
+struct Size(u64);
+
+impl Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes ({:.1} MiB)",
+            self.0,
+            self.0 as f64 / f64::from(1 << 20)
+        )
+    }
+}
+
+impl Display for ContainerPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (title_index, title) in self.titles.iter().enumerate() {
+            writeln!(
+                f,
+                "Title {title_index}: \"{}\", {} frames at {} fps, {} px tall",
+                title.name, title.frame_count, title.fps, title.height
+            )?;
+            writeln!(
+                f,
+                "  Sampled {} frames, expecting {:.2}% keyframes",
+                title.sampled_frames,
+                title.keyframe_fraction * 100.0
+            )?;
+            writeln!(
+                f,
+                "  Blocks per frame: average {:.2}, p50 {}, p90 {}, p99 {}, max {}",
+                title.average_blocks,
+                title.blocks_per_frame.p50,
+                title.blocks_per_frame.p90,
+                title.blocks_per_frame.p99,
+                title.blocks_per_frame.max
+            )?;
+            writeln!(f, "  Estimated size: {}", Size(title.estimated_size))?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Estimated container size: {}", Size(self.estimated_size))?;
+
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+
+        if self.fits() {
+            return writeln!(
+                f,
+                "Fits in {} with {:.1}% to spare",
+                Size(max_size),
+                (max_size - self.estimated_size) as f64 * 100.0 / max_size as f64
+            );
+        }
+
+        writeln!(
+            f,
+            "Over {} by {:.1}%",
+            Size(max_size),
+            (self.estimated_size - max_size) as f64 * 100.0 / max_size as f64
+        )?;
+
+        if self.suggestions.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(f, "Any of these for every title should fit:")?;
+
+        for suggestion in &self.suggestions {
+            match suggestion {
+                Suggestion::Fps { title_index, fps } => {
+                    writeln!(f, "  Title {title_index}: fps = {fps}")?;
+                }
+                Suggestion::Height {
+                    title_index,
+                    height,
+                } => writeln!(f, "  Title {title_index}: height = {height}")?,
+                Suggestion::RateControl {
+                    title_index,
+                    blocks_per_frame,
+                } => writeln!(
+                    f,
+                    "  Title {title_index}: rate_control = {{ blocks_per_frame = {blocks_per_frame} }}"
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn plan(container: &Path, options: PlanOptions, json: bool) -> anyhow::Result<()> {
+    let title_directory = Arc::<Path>::from(get_container_directory(container)?);
+    let definition = ContainerDefinition::load(container).await?;
+    let registry = Arc::new(CodecRegistry::default());
+    let scheduler = Scheduler::new(options.threads);
+
+    let mut titles = Vec::with_capacity(definition.titles.len());
+
+    for title in definition.titles {
+        let title = Arc::new(title);
+        // Fail before spending time decoding samples
+        PictureEncoder::from_title(&title, &registry)?;
+
+        let duration = title.duration(&title_directory).await?;
+        let frame_count = (duration.as_secs_f64() * f64::from(title.fps)).round() as u64;
+        let window = duration.min(Duration::from_secs(SAMPLE_WINDOW_SECONDS));
+        let window_count = options.sample_windows.max(1);
+
+        info!(
+            "Sampling {window_count} windows of {:.1}s from \"{}\".",
+            window.as_secs_f64(),
+            title.name
+        );
+
+        let title_start = title.start.map(Duration::from).unwrap_or_default();
+        let samples = spawn_in_order(0..window_count, options.threads, |window_index| {
+            // Centered in even slices of the title
+            let offset =
+                (duration - window).mul_f64((window_index as f64 + 0.5) / window_count as f64);
+
+            encode_window(
+                Arc::clone(&title),
+                Arc::clone(&title_directory),
+                Arc::clone(&registry),
+                scheduler.clone(),
+                title_start + offset,
+                window,
+            )
+        })
+        .await?;
+
+        anyhow::ensure!(
+            samples.iter().any(|window| !window.is_empty()),
+            "FFmpeg gave no frames to sample from \"{}\"",
+            title.name
+        );
+
+        titles.push(TitlePlan::new(&title, frame_count, &samples));
+    }
+
+    let plan = ContainerPlan::new(titles, options.max_size)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+    } else {
+        print!("{plan}");
+    }
+
+    anyhow::ensure!(
+        plan.fits(),
+        "Container is estimated to be over the max size; {} > {} bytes",
+        plan.estimated_size,
+        options.max_size.unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::Codec;
+
+    fn frame(size: usize, frame_type: FrameType) -> EncodedFrame {
+        EncodedFrame {
+            size,
+            frame_type,
+            codec: Codec::Qoi,
+        }
+    }
+
+    #[test]
+    fn size_units() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("1GB").unwrap(), 1_000_000_000);
+        assert_eq!(parse_size("1.5 MiB").unwrap(), 3 << 19);
+        assert_eq!(
+            parse_size("1 TB").unwrap_err().to_string(),
+            "Unknown size unit: tb"
+        );
+    }
+
+    #[test]
+    fn estimate_and_suggest() {
+        let title = toml::from_str(
+            r#"
+            video = "video.mp4"
+            fps = 20
+            height = 200
+            keyframe_interval = 100
+            "#,
+        )
+        .unwrap();
+
+        // Keyframes take 8 blocks, delta frames 2, with a scene cut in the second window
+        let window = |cut| {
+            let mut window = vec![frame(8 * 512 - 4, FrameType::Key)];
+            window.extend((1..10).map(|frame_index| {
+                if cut && frame_index == 5 {
+                    frame(8 * 512 - 4, FrameType::Key)
+                } else {
+                    frame(2 * 512 - 4, FrameType::Delta)
+                }
+            }));
+            window
+        };
+
+        let title = TitlePlan::new(&title, 1000, &[window(false), window(true)]);
+        assert_eq!(title.sampled_frames, 20);
+        // One scene cut in 18 later frames
+        assert!((title.keyframe_fraction - 1.0 / 18.0).abs() < 1e-9);
+        assert!((title.average_blocks - (2.0 + 6.0 / 18.0)).abs() < 1e-9);
+        assert_eq!(title.blocks_per_frame.max, 8);
+        // 2334 chunk blocks and 10 table blocks
+        assert_eq!(title.estimated_size, (2334 + 10) * 512);
+
+        let estimated_size = u64::from(HEADER_SIZE) + title.estimated_size;
+        let plan = ContainerPlan::new(vec![title], Some(estimated_size / 2)).unwrap();
+        assert!(!plan.fits());
+
+        let suggestions = plan
+            .suggestions
+            .iter()
+            .map(|suggestion| serde_json::to_value(suggestion).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(suggestions[0]["fps"], 9);
+        assert_eq!(suggestions[1]["height"], 96);
+        assert_eq!(suggestions[2]["blocks_per_frame"], 1.15);
+        assert!(plan.to_string().contains("Title 0: fps = 9"));
+
+        let plan = ContainerPlan::new(plan.titles, Some(estimated_size)).unwrap();
+        assert!(plan.fits() && plan.suggestions.is_empty());
+    }
+}
@@ -0,0 +1,877 @@
+//! Differential tests against the player's C decoders.
+//!
+//! The picture decoders in `src/` are compiled for the host with the eZ80 types and error hooks stubbed out.
+//! Every frame the encoder produces is decoded by it and compared pixel for pixel,
+//! along with the encoder's own port of them in [`crate::decode`].
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use image::{DynamicImage, RgbImage, imageops::FilterType};
+
+use crate::{
+    BLOCK_SIZE, BLOCKS_PER_CHUNK, LCD_HEIGHT, LCD_WIDTH,
+    decode::PictureDecoder,
+    encode::{
+        Codec, EncodedFrame, FrameType, NearLosslessSettings, PICTURE_CHUNK_HEADER_SIZE,
+        PictureEncoder, QoiSettings, max_image_size,
+        motion::{MOTION_MAX_SEARCH_RANGE, MotionSettings},
+        rate::RateSettings,
+        registry::{CodecOptions, CodecRegistry, CodecSelection, TitleEncoder},
+        tests::{
+            grain_frames, grainy_frames, lcg, moving_square_frames, qoi_encode, vertical_frame,
+        },
+        vq::{VQ_CODEBOOK_SIZE, VqSettings},
+    },
+    serialize::rgb_to_color_space,
+};
+
+const PLAYER_SOURCE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../src");
+const HARNESS_SOURCE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/player");
+const SAMPLE_FRAME: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/icon.png");
+
+/// A picture chunk's image and how it was encoded.
+type EncodedImage = (EncodedFrame, Vec<u8>);
+
+/// A compiled harness in its own temporary directory, which is deleted on drop.
+struct HarnessBinary {
+    directory: PathBuf,
+}
+
+impl HarnessBinary {
+    fn compile() -> Self {
+        static COMPILED: AtomicUsize = AtomicUsize::new(0);
+
+        let player_source = Path::new(PLAYER_SOURCE);
+        let harness_source = Path::new(HARNESS_SOURCE);
+        let directory = std::env::temp_dir().join(format!(
+            "ticevid-player-harness-{}-{}",
+            std::process::id(),
+            COMPILED.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&directory).expect("Failed to create harness directory");
+        // Cleans up even if compiling fails
+        let binary = Self { directory };
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+
+        let status = Command::new(&compiler)
+            .arg("-std=c2x")
+            .arg("-Wall")
+            .arg("-Wextra")
+            // The decoder measures progress by casting pointers to `uint24_t`
+            .arg("-Wno-pointer-to-int-cast")
+            .arg("-include")
+            .arg(harness_source.join("ez80.h"))
+            .arg("-I")
+            .arg(player_source)
+            .arg("-o")
+            .arg(binary.path())
+            .arg(harness_source.join("player_harness.c"))
+            .arg(player_source.join("qoi.c"))
+            .arg(player_source.join("lzss.c"))
+            .arg(player_source.join("motion.c"))
+            .arg(player_source.join("vq.c"))
+            .status()
+            .unwrap_or_else(|error| panic!("Failed to run C compiler `{compiler}`: {error}"));
+
+        assert!(status.success(), "Failed to compile the player's decoders");
+
+        binary
+    }
+
+    fn path(&self) -> PathBuf {
+        self.directory
+            .join(format!("player_harness{}", std::env::consts::EXE_SUFFIX))
+    }
+}
+
+impl Drop for HarnessBinary {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.directory);
+    }
+}
+
+/// Compiles the harness, sharing it between tests running at the same time.
+fn harness_binary() -> Arc<HarnessBinary> {
+    static BINARY: Mutex<Weak<HarnessBinary>> = Mutex::new(Weak::new());
+
+    let mut shared = BINARY.lock().unwrap();
+
+    if let Some(binary) = shared.upgrade() {
+        return binary;
+    }
+
+    let binary = Arc::new(HarnessBinary::compile());
+    *shared = Arc::downgrade(&binary);
+    binary
+}
+
+/// Decodes each image in order with a single decoder instance, like the player does.
+fn player_decode(images: &[EncodedImage], pixels: usize) -> Vec<Vec<u8>> {
+    let mut input = Vec::new();
+
+    for (encoded_frame, image) in images {
+        let length = u32::try_from(image.len()).unwrap();
+        input.push(encoded_frame.codec as u8);
+        input.push(encoded_frame.frame_type as u8);
+        input.extend_from_slice(&length.to_le_bytes());
+        input.extend_from_slice(image);
+    }
+
+    let binary = harness_binary();
+    let mut child = Command::new(binary.path())
+        .arg(pixels.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to start player harness");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+
+    assert!(output.status.success(), "Player failed to decode frame");
+    assert_eq!(output.stdout.len(), images.len() * pixels);
+
+    output.stdout.chunks(pixels).map(<[u8]>::to_vec).collect()
+}
+
+fn check_image_size(bytes: usize) {
+    assert!(
+        bytes <= u16::MAX.into(),
+        "Frame won't fit in a picture chunk: {bytes} bytes"
+    );
+}
+
+fn assert_player_decodes(frames: &[Vec<u8>], images: &[EncodedImage]) {
+    let pixels = frames[0].len();
+    let decoded = player_decode(images, pixels);
+    let mut picture_decoder = PictureDecoder::new(pixels);
+
+    for (frame_index, ((frame, decoded), (encoded_frame, image))) in
+        frames.iter().zip(decoded).zip(images).enumerate()
+    {
+        if let Some(pixel) = frame.iter().zip(&decoded).position(|(a, b)| a != b) {
+            panic!(
+                "Frame {frame_index} differs at pixel {pixel}: expected {}, decoded {}",
+                frame[pixel], decoded[pixel]
+            );
+        }
+
+        let ported = picture_decoder
+            .decode(encoded_frame.frame_type, encoded_frame.codec, image)
+            .unwrap_or_else(|error| panic!("Frame {frame_index} failed to decode: {error:#}"));
+
+        if let Some(pixel) = ported.iter().zip(&decoded).position(|(a, b)| a != b) {
+            panic!(
+                "Frame {frame_index} differs from the player at pixel {pixel}: player {}, decoded {}",
+                decoded[pixel], ported[pixel]
+            );
+        }
+    }
+}
+
+/// Encodes every frame as a keyframe.
+fn assert_player_matches(frames: &[Vec<u8>]) {
+    let images = frames
+        .iter()
+        .map(|frame| {
+            let image = qoi_encode(frame);
+            check_image_size(image.len());
+            let encoded_frame = EncodedFrame {
+                size: image.len(),
+                frame_type: FrameType::Key,
+                codec: Codec::Qoi,
+            };
+            (encoded_frame, image)
+        })
+        .collect::<Vec<_>>();
+    assert_player_decodes(frames, &images);
+}
+
+/// Test frames are larger than the player's buffer, so aren't limited.
+fn picture_encoder(
+    keyframe_interval: u32,
+    scene_cut_threshold: f32,
+    forced_keyframes: impl IntoIterator<Item = u32>,
+) -> PictureEncoder {
+    PictureEncoder::new(keyframe_interval, scene_cut_threshold, forced_keyframes)
+        .with_max_blocks(u8::MAX)
+}
+
+/// Encodes frames the same way titles are.
+///
+/// Returns the encoded frames and the frames the encoder expects the player to display.
+fn encode_sequence(
+    frames: &[Vec<u8>],
+    mut picture_encoder: PictureEncoder,
+) -> (Vec<EncodedFrame>, Vec<EncodedImage>, Vec<Vec<u8>>) {
+    let mut encoded_frames = Vec::with_capacity(frames.len());
+    let mut images = Vec::with_capacity(frames.len());
+    let mut displayed_frames = Vec::with_capacity(frames.len());
+
+    let look_ahead = picture_encoder.look_ahead_frames();
+
+    for frame in frames.iter().take(look_ahead) {
+        picture_encoder.look_ahead(frame);
+    }
+
+    for (frame_index, frame) in frames.iter().enumerate() {
+        if let Some(upcoming) = frames.get(frame_index + look_ahead) {
+            picture_encoder.look_ahead(upcoming);
+        }
+
+        let mut output_buffer = vec![0; LCD_WIDTH as usize * LCD_HEIGHT as usize];
+        let encoded_frame = picture_encoder
+            .encode(frame.clone(), &mut output_buffer)
+            .unwrap();
+        output_buffer.truncate(encoded_frame.size);
+        check_image_size(encoded_frame.size);
+
+        encoded_frames.push(encoded_frame);
+        images.push((encoded_frame, output_buffer));
+        displayed_frames.push(picture_encoder.previous_frame().unwrap().to_vec());
+    }
+
+    (encoded_frames, images, displayed_frames)
+}
+
+/// Encodes frames the same way titles are, expecting them to be decoded losslessly.
+fn assert_player_matches_sequence(
+    frames: &[Vec<u8>],
+    picture_encoder: PictureEncoder,
+) -> Vec<EncodedFrame> {
+    let (encoded_frames, images, _) = encode_sequence(frames, picture_encoder);
+    assert_player_decodes(frames, &images);
+
+    encoded_frames
+}
+
+/// Encodes frames the same way titles are, expecting the player to match the encoder's reconstruction.
+fn assert_player_matches_lossy(
+    frames: &[Vec<u8>],
+    picture_encoder: PictureEncoder,
+) -> Vec<EncodedFrame> {
+    let (encoded_frames, images, displayed_frames) = encode_sequence(frames, picture_encoder);
+    assert_player_decodes(&displayed_frames, &images);
+
+    encoded_frames
+}
+
+fn count_keyframes(encoded_frames: &[EncodedFrame]) -> usize {
+    encoded_frames
+        .iter()
+        .filter(|frame| frame.frame_type == FrameType::Key)
+        .count()
+}
+
+fn count_codec(encoded_frames: &[EncodedFrame], codec: Codec) -> usize {
+    encoded_frames
+        .iter()
+        .filter(|frame| frame.codec == codec)
+        .count()
+}
+
+fn generated_frame(f: impl Fn(u32, u32) -> [u8; 3]) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(
+        LCD_WIDTH.into(),
+        LCD_HEIGHT.into(),
+        |x, y| image::Rgb(f(x, y)),
+    ))
+}
+
+fn generated_frames() -> Vec<DynamicImage> {
+    let palette = [[0, 0, 0], [255, 255, 255], [200, 40, 40], [40, 200, 40]];
+    let noise = lcg(0x5EED)
+        .take((LCD_WIDTH / 2) as usize * LCD_HEIGHT as usize)
+        .collect::<Vec<_>>();
+    // Two pixels wide to keep the frame within a picture chunk
+    let noise_frame = RgbImage::from_fn(LCD_WIDTH.into(), LCD_HEIGHT.into(), |x, y| {
+        let sample = noise[(y * u32::from(LCD_WIDTH / 2) + x / 2) as usize];
+        image::Rgb(palette[sample as usize % palette.len()])
+    });
+
+    vec![
+        generated_frame(|_, _| [0, 0, 0]),
+        generated_frame(|_, _| [255, 255, 255]),
+        generated_frame(|x, y| [(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8]),
+        generated_frame(|x, _| {
+            if (x / 8).is_multiple_of(2) {
+                [255; 3]
+            } else {
+                [0; 3]
+            }
+        }),
+        generated_frame(|x, y| {
+            if ((x / 4) + (y / 4)).is_multiple_of(2) {
+                [255, 0, 255]
+            } else {
+                [0, 64, 128]
+            }
+        }),
+        DynamicImage::ImageRgb8(noise_frame),
+    ]
+}
+
+fn sample_frame() -> DynamicImage {
+    let sample = image::open(SAMPLE_FRAME).expect("Failed to open sample frame");
+    DynamicImage::ImageRgb8(sample.to_rgb8()).resize_exact(
+        LCD_WIDTH.into(),
+        LCD_HEIGHT.into(),
+        FilterType::Nearest,
+    )
+}
+
+async fn quantize(frames: Vec<DynamicImage>) -> Vec<Vec<u8>> {
+    let mut quantized = Vec::with_capacity(frames.len());
+
+    for frame in frames {
+        quantized.push(rgb_to_color_space(frame.as_rgb8().unwrap().as_raw()));
+    }
+
+    quantized
+}
+
+#[test]
+fn player_qoi_palette_sweep() {
+    let frame = (0..=u8::MAX).cycle().take(4096).collect::<Vec<_>>();
+    assert_player_matches(&[frame]);
+}
+
+#[test]
+fn player_qoi_long_runs() {
+    let frame = [0, 1, 2, 3]
+        .into_iter()
+        .flat_map(|pixel| std::iter::repeat_n(pixel, 200))
+        .collect::<Vec<_>>();
+    assert_player_matches(&[frame]);
+}
+
+#[tokio::test]
+async fn player_qoi_generated_frames() {
+    for frame in quantize(generated_frames()).await {
+        assert_player_matches(&[frame]);
+    }
+}
+
+#[tokio::test]
+async fn player_qoi_sample_frame() {
+    let frames = quantize(vec![sample_frame()]).await;
+    assert_player_matches(&frames);
+}
+
+/// The player keeps its decoder state between frames, so stale state must never leak through.
+#[tokio::test]
+async fn player_qoi_frame_sequence() {
+    let mut frames = generated_frames();
+    frames.push(sample_frame());
+
+    let frames = quantize(frames).await;
+    assert_player_matches(&frames);
+}
+
+/// Small steps between pixels, both ways and wrapping around, are written as diffs.
+#[test]
+fn player_qoi_diff() {
+    let frame = lcg(0xD1F)
+        .take(4096)
+        .scan(0u8, |pixel, sample| {
+            *pixel = pixel.wrapping_add_signed((sample % 33) as i8 - 16);
+            Some(*pixel)
+        })
+        .collect::<Vec<_>>();
+    assert!(qoi_encode(&frame).len() < frame.len() * 5 / 4);
+    assert_player_matches(&[frame]);
+}
+
+/// The encoder starts every frame with an empty index table.
+#[test]
+fn player_qoi_index_reset() {
+    let frames = [vec![64, 128, 192, 5], vec![5, 0, 5, 0]];
+    assert_player_matches(&frames);
+}
+
+#[test]
+fn player_qoi_delta_frames() {
+    let frames = moving_square_frames(24);
+    let encoded_frames = assert_player_matches_sequence(&frames, picture_encoder(100, 0.5, []));
+    let keyframes = count_keyframes(&encoded_frames);
+    assert_eq!(keyframes, 1);
+}
+
+#[test]
+fn player_qoi_delta_unchanged() {
+    let frames = vec![moving_square_frames(1)[0].clone(); 4];
+    let encoded_frames = assert_player_matches_sequence(&frames, picture_encoder(100, 0.5, []));
+    let keyframes = count_keyframes(&encoded_frames);
+    assert_eq!(keyframes, 1);
+}
+
+#[test]
+fn player_qoi_keyframes() {
+    let frames = moving_square_frames(12);
+    let encoded_frames = assert_player_matches_sequence(&frames, picture_encoder(4, 0.5, [5]));
+    let keyframes = count_keyframes(&encoded_frames);
+    // Interval keyframes at 0, 4 and 9, plus the forced keyframe at 5
+    assert_eq!(keyframes, 4);
+}
+
+#[tokio::test]
+async fn player_qoi_scene_cuts() {
+    let frames = quantize(generated_frames()).await;
+    let encoded_frames = assert_player_matches_sequence(&frames, picture_encoder(100, 0.5, []));
+    let keyframes = count_keyframes(&encoded_frames);
+    assert!(keyframes > 1);
+}
+
+const MOTION_SETTINGS: MotionSettings = MotionSettings {
+    search_range: MOTION_MAX_SEARCH_RANGE,
+    max_decode_cycles: u32::MAX,
+};
+
+const VQ_SETTINGS: VqSettings = VqSettings {
+    codebook_size: VQ_CODEBOOK_SIZE as u16,
+    iterations: 2,
+};
+
+const CODEC_OPTIONS: CodecOptions = CodecOptions {
+    width: LCD_WIDTH as usize,
+    max_image_size: max_image_size(BLOCKS_PER_CHUNK),
+    qoi: QoiSettings {
+        optimal_parse: false,
+    },
+    motion: MOTION_SETTINGS,
+    vq: VQ_SETTINGS,
+};
+
+fn title_encoder(codec: &str, options: CodecOptions) -> Box<dyn TitleEncoder> {
+    CodecRegistry::default().create(codec, &options).unwrap()
+}
+
+/// Noise scrolling diagonally, which QOI can't skip.
+fn panning_frames(count: u32) -> Vec<Vec<u8>> {
+    let width = u32::from(LCD_WIDTH);
+    let height = u32::from(LCD_HEIGHT);
+    let texture = lcg(0xBEEF).take(64 * 64).collect::<Vec<_>>();
+
+    (0..count)
+        .map(|frame_index| {
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let texture_x = (x + frame_index * 3) / 2 % 64;
+                    let texture_y = (y + frame_index * 2) / 2 % 64;
+                    (texture[(texture_y * 64 + texture_x) as usize] % 4) as u8
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn player_motion_panning() {
+    let frames = panning_frames(8);
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, f32::MAX, []).with_encoder(title_encoder("motion", CODEC_OPTIONS)),
+    );
+    assert_eq!(count_keyframes(&encoded_frames), 1);
+    assert_eq!(count_codec(&encoded_frames, Codec::Motion), 7);
+}
+
+#[test]
+fn player_motion_moving_square() {
+    let frames = moving_square_frames(24);
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, 0.5, []).with_encoder(title_encoder("motion", CODEC_OPTIONS)),
+    );
+    assert_eq!(count_keyframes(&encoded_frames), 1);
+}
+
+/// Blocks that can't be matched fall back to intra QOI data.
+#[test]
+fn player_motion_intra_blocks() {
+    let mut frames = panning_frames(6);
+
+    // A square that's never been seen before
+    for (frame_index, frame) in frames.iter_mut().enumerate().skip(1) {
+        let square_y = frame_index * 24;
+
+        for row in frame.chunks_mut(LCD_WIDTH.into()).skip(square_y).take(32) {
+            row[frame_index * 40..frame_index * 40 + 32].fill(0xE0 + frame_index as u8);
+        }
+    }
+
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, f32::MAX, []).with_encoder(title_encoder("motion", CODEC_OPTIONS)),
+    );
+    assert_eq!(count_keyframes(&encoded_frames), 1);
+    assert_eq!(count_codec(&encoded_frames, Codec::Motion), 5);
+}
+
+/// Frames with few unique 2x2 blocks get an exact codebook.
+#[test]
+fn player_vq_lossless() {
+    let frames = moving_square_frames(12);
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, 0.5, []).with_encoder(title_encoder("vq", CODEC_OPTIONS)),
+    );
+    assert_eq!(count_keyframes(&encoded_frames), 1);
+    assert_eq!(count_codec(&encoded_frames, Codec::Vq), 12);
+}
+
+#[test]
+fn player_vq_keyframes() {
+    let frames = panning_frames(6);
+    let encoded_frames = assert_player_matches_lossy(
+        &frames,
+        // Fewer entries than unique blocks, so it's lossy
+        picture_encoder(3, f32::MAX, [4]).with_encoder(title_encoder(
+            "vq",
+            CodecOptions {
+                vq: VqSettings {
+                    codebook_size: 8,
+                    ..VQ_SETTINGS
+                },
+                ..CODEC_OPTIONS
+            },
+        )),
+    );
+    // Interval keyframes at 0 and 3, plus the forced keyframe at 4
+    assert_eq!(count_keyframes(&encoded_frames), 3);
+}
+
+#[tokio::test]
+async fn player_vq_generated_frames() {
+    let mut frames = quantize(generated_frames()).await;
+    frames.extend(quantize(vec![sample_frame()]).await);
+
+    let encoded_frames = assert_player_matches_lossy(
+        &frames,
+        picture_encoder(100, f32::MAX, []).with_encoder(title_encoder(
+            "vq",
+            CodecOptions {
+                vq: VqSettings {
+                    codebook_size: 64,
+                    ..VQ_SETTINGS
+                },
+                ..CODEC_OPTIONS
+            },
+        )),
+    );
+    assert_eq!(count_keyframes(&encoded_frames), 1);
+}
+
+/// Frames over the block limit are encoded again from the same codebook until they fit.
+#[test]
+fn player_vq_max_blocks() {
+    let frames = grainy_frames(4);
+    let max_blocks = 10;
+    let max_image_size =
+        usize::from(max_blocks) * usize::from(BLOCK_SIZE) - PICTURE_CHUNK_HEADER_SIZE;
+
+    let mut output_buffer = vec![0; frames[0].len()];
+    let (_, unlimited) = title_encoder("vq", CODEC_OPTIONS)
+        .encode(&frames[0], None, &mut output_buffer)
+        .unwrap();
+    assert!(unlimited > max_image_size);
+
+    let encoded_frames = assert_player_matches_lossy(
+        &frames,
+        PictureEncoder::new(100, f32::MAX, [])
+            .with_max_blocks(max_blocks)
+            .with_encoder(title_encoder("vq", CODEC_OPTIONS)),
+    );
+    assert!(
+        encoded_frames
+            .iter()
+            .all(|frame| frame.size <= max_image_size)
+    );
+}
+
+/// Short enough for uncompressed frames to fit in a picture chunk.
+fn short_frames(frames: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let pixels = LCD_WIDTH as usize * 200;
+
+    frames
+        .into_iter()
+        .map(|mut frame| {
+            frame.truncate(pixels);
+            frame
+        })
+        .collect()
+}
+
+#[test]
+fn player_raw_frames() {
+    let frames = short_frames(panning_frames(4));
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, 0.5, []).with_encoder(title_encoder("raw", CODEC_OPTIONS)),
+    );
+    assert_eq!(count_codec(&encoded_frames, Codec::Raw), 4);
+}
+
+#[tokio::test]
+async fn player_lzss_frames() {
+    let mut frames = moving_square_frames(4);
+    frames.extend(quantize(vec![sample_frame()]).await);
+
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, 0.5, []).with_encoder(title_encoder("lzss", CODEC_OPTIONS)),
+    );
+    assert_eq!(count_codec(&encoded_frames, Codec::Lzss), 5);
+}
+
+fn best_encoder(codecs: &[&str], selection: CodecSelection) -> Box<dyn TitleEncoder> {
+    CodecRegistry::default()
+        .create_best(codecs, selection, &CODEC_OPTIONS)
+        .unwrap()
+}
+
+/// Mixed content gets a different codec depending on the frame.
+#[tokio::test]
+async fn player_best_of_mixed() {
+    let mut frames = panning_frames(4);
+    frames.extend(moving_square_frames(4));
+    frames.extend(quantize(vec![sample_frame()]).await);
+
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, 0.5, []).with_encoder(best_encoder(
+            &["qoi", "lzss", "motion"],
+            CodecSelection::Size,
+        )),
+    );
+    assert!(count_codec(&encoded_frames, Codec::Motion) > 0);
+    assert!(count_codec(&encoded_frames, Codec::Qoi) > 0);
+}
+
+/// Raw images decode the fastest, but only fit the player's buffer for short frames.
+#[test]
+fn player_best_of_decode_time() {
+    let decode_time_encoder = || {
+        PictureEncoder::new(100, 0.5, [])
+            .with_encoder(best_encoder(&["qoi", "raw"], CodecSelection::DecodeTime))
+    };
+
+    let frames = moving_square_frames(3);
+    let first_rows = |rows: usize| {
+        frames
+            .iter()
+            .map(|frame| frame[..LCD_WIDTH as usize * rows].to_vec())
+            .collect::<Vec<_>>()
+    };
+
+    let encoded_frames = assert_player_matches_sequence(&first_rows(120), decode_time_encoder());
+    assert_eq!(count_codec(&encoded_frames, Codec::Qoi), 3);
+
+    let encoded_frames = assert_player_matches_sequence(&first_rows(25), decode_time_encoder());
+    assert_eq!(count_codec(&encoded_frames, Codec::Raw), 3);
+}
+
+/// Noise short enough to fit the player's buffer uncompressed.
+#[test]
+fn player_raw_fallback() {
+    let frames = grain_frames(3, 25);
+    let encoded_frames = assert_player_matches_sequence(&frames, PictureEncoder::new(100, 0.5, []));
+    assert_eq!(count_codec(&encoded_frames, Codec::Raw), 3);
+}
+
+/// Noise repeating too far apart for QOI to reuse, in frames too tall to store uncompressed.
+#[test]
+fn player_lzss_fallback() {
+    let frames = (0..3)
+        .map(|seed| {
+            let period = lcg(seed)
+                .take(500)
+                .map(|sample| sample as u8)
+                .collect::<Vec<_>>();
+            period.repeat(LCD_WIDTH as usize * 120 / period.len() + 1)[..LCD_WIDTH as usize * 120]
+                .to_vec()
+        })
+        .collect::<Vec<_>>();
+    let picture_encoder = PictureEncoder::new(100, 0.5, []);
+    let max_image_size = picture_encoder.max_image_size();
+    assert!(frames[0].len() > max_image_size);
+
+    let encoded_frames = assert_player_matches_sequence(&frames, picture_encoder);
+    assert_eq!(count_codec(&encoded_frames, Codec::Lzss), 3);
+    assert!(
+        encoded_frames
+            .iter()
+            .all(|frame| frame.size <= max_image_size)
+    );
+}
+
+/// Frames over the block limit are requantized until they fit.
+#[test]
+fn player_max_blocks() {
+    let frames = grainy_frames(1);
+    let mut picture_encoder = PictureEncoder::new(100, 0.5, []).with_max_blocks(6);
+    let max_image_size = picture_encoder.max_image_size();
+    assert!(qoi_encode(&frames[0]).len() > max_image_size);
+
+    let mut output_buffer = vec![0; frames[0].len()];
+    let encoded_frame = picture_encoder
+        .encode(frames[0].clone(), &mut output_buffer)
+        .unwrap();
+    assert!(encoded_frame.size <= max_image_size);
+
+    let displayed_frame = picture_encoder.previous_frame().unwrap().to_vec();
+    assert_ne!(displayed_frame, frames[0]);
+
+    output_buffer.truncate(encoded_frame.size);
+    assert_player_decodes(&[displayed_frame], &[(encoded_frame, output_buffer)]);
+}
+
+fn total_size(encoded_frames: &[EncodedFrame]) -> usize {
+    encoded_frames.iter().map(|frame| frame.size).sum()
+}
+
+#[test]
+fn player_near_lossless() {
+    let frames = grainy_frames(6);
+    let lossless = assert_player_matches_sequence(&frames, picture_encoder(100, 0.5, []));
+    let near_lossless = assert_player_matches_lossy(
+        &frames,
+        picture_encoder(100, 0.5, []).with_near_lossless(NearLosslessSettings {
+            tolerance: 1,
+            target_size: None,
+        }),
+    );
+
+    assert!(total_size(&near_lossless) < total_size(&lossless));
+}
+
+/// The adaptive tolerance only goes as high as needed to meet the target.
+#[test]
+fn player_near_lossless_adaptive() {
+    let frames = grainy_frames(4);
+    let target_size = 8000;
+    let encoded_frames = assert_player_matches_lossy(
+        &frames,
+        picture_encoder(100, 0.5, []).with_near_lossless(NearLosslessSettings {
+            tolerance: 4,
+            target_size: Some(target_size),
+        }),
+    );
+
+    assert!(encoded_frames.iter().all(|frame| frame.size <= target_size));
+
+    // Frames already under the target stay lossless
+    let frames = moving_square_frames(4);
+    let target_size = qoi_encode(&frames[0]).len();
+    assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, 0.5, []).with_near_lossless(NearLosslessSettings {
+            tolerance: 4,
+            target_size: Some(target_size),
+        }),
+    );
+}
+
+#[test]
+fn player_qoi_up() {
+    let mut frame = vertical_frame(24);
+
+    // Breaks in the columns, including runs that beat copying from above
+    frame[LCD_WIDTH as usize * 3 + 5] ^= 1;
+    frame[LCD_WIDTH as usize * 10..LCD_WIDTH as usize * 10 + 100].fill(9);
+
+    assert_player_matches(&[frame]);
+}
+
+#[test]
+fn player_qoi_up_delta() {
+    let mut frames = vec![vertical_frame(24); 3];
+    frames[1][LCD_WIDTH as usize * 8..LCD_WIDTH as usize * 16].rotate_left(3);
+    frames[2] = vertical_frame(24)
+        .into_iter()
+        .map(|pixel| pixel / 2)
+        .collect();
+
+    assert_player_matches_sequence(&frames, picture_encoder(100, f32::MAX, []));
+}
+
+const OPTIMAL_OPTIONS: CodecOptions = CodecOptions {
+    qoi: QoiSettings {
+        optimal_parse: true,
+    },
+    ..CODEC_OPTIONS
+};
+
+#[test]
+fn player_qoi_optimal_parse() {
+    let mut frames = moving_square_frames(4);
+    frames.extend(grainy_frames(2));
+    frames.push(vertical_frame(LCD_HEIGHT.into()));
+
+    let encoded_frames = assert_player_matches_sequence(
+        &frames,
+        picture_encoder(100, f32::MAX, []).with_encoder(title_encoder("qoi", OPTIMAL_OPTIONS)),
+    );
+    assert_eq!(count_keyframes(&encoded_frames), 1);
+
+    assert_player_matches_sequence(
+        &moving_square_frames(4),
+        picture_encoder(100, f32::MAX, []).with_encoder(title_encoder("motion", OPTIMAL_OPTIONS)),
+    );
+}
+
+/// How many blocks the player reads for each frame.
+fn blocks_read(encoded_frames: &[EncodedFrame]) -> Vec<usize> {
+    encoded_frames
+        .iter()
+        .map(|frame| (frame.size + PICTURE_CHUNK_HEADER_SIZE).div_ceil(BLOCK_SIZE.into()))
+        .collect()
+}
+
+#[test]
+fn player_rate_control() {
+    // Easy frames followed by noise
+    let mut frames = moving_square_frames(8);
+    frames.extend(grainy_frames(8));
+
+    let blocks_per_frame = 3;
+    let look_ahead = 4;
+    let encoded_frames = assert_player_matches_lossy(
+        &frames,
+        PictureEncoder::new(100, 0.5, []).with_rate_control(RateSettings {
+            bytes_per_frame: blocks_per_frame * usize::from(BLOCK_SIZE),
+            look_ahead,
+            max_tolerance: 4,
+        }),
+    );
+    let blocks = blocks_read(&encoded_frames);
+
+    // Savings from the easy frames are spent on the noise, so short stretches can go over
+    for window in blocks.windows(look_ahead + 1) {
+        let average = window.iter().sum::<usize>() as f32 / window.len() as f32;
+        assert!(average <= blocks_per_frame as f32 * 2.0, "{blocks:?}");
+    }
+
+    let average = blocks.iter().sum::<usize>() as f32 / blocks.len() as f32;
+    assert!(average <= blocks_per_frame as f32, "{blocks:?}");
+
+    // Noise is over budget without rate control
+    let (unlimited, ..) = encode_sequence(&frames, PictureEncoder::new(100, 0.5, []));
+    assert!(
+        blocks_read(&unlimited)
+            .iter()
+            .any(|&blocks| blocks > blocks_per_frame)
+    );
+}
@@ -0,0 +1,220 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use image::imageops::{self, FilterType};
+use log::info;
+
+use crate::{
+    LCD_HEIGHT, LCD_WIDTH,
+    extract::{FrameSelection, FrameWriter, Palette, extract_frames, open_title},
+    font::Font,
+    reader::{Caption, CaptionChunk},
+};
+
+/// Blank space between captions and the edge of the screen.
+const CAPTION_MARGIN: isize = 2;
+
+#[derive(Debug, Clone, Default)]
+pub struct PreviewOptions {
+    /// Defaults to the title's first caption track, if it has any.
+    pub caption_track: Option<usize>,
+    /// Used instead of the container's font pack.
+    pub font_pack: Option<PathBuf>,
+    /// How many times larger than the screen the output is.
+    pub scale: u32,
+}
+
+/// Draws the captions of a track over the screen.
+pub struct CaptionRenderer {
+    font: Font,
+    chunks: Vec<CaptionChunk>,
+    foreground: u8,
+    /// `None` when transparent.
+    background: Option<u8>,
+}
+
+impl CaptionRenderer {
+    pub fn new(
+        font: Font,
+        chunks: Vec<CaptionChunk>,
+        foreground: u8,
+        background: Option<u8>,
+    ) -> Self {
+        Self {
+            font,
+            chunks,
+            foreground,
+            background,
+        }
+    }
+
+    /// Draws every caption shown on a frame of the title.
+    pub fn draw(&self, screen: &mut [u8], frame_index: u32) {
+        for chunk in &self.chunks {
+            for caption in &chunk.captions {
+                if caption.is_shown(chunk, frame_index) {
+                    self.draw_caption(screen, caption);
+                }
+            }
+        }
+    }
+
+    /// Positions are a 3x3 grid over the screen, with each line aligned on its own.
+    fn draw_caption(&self, screen: &mut [u8], caption: &Caption) {
+        let screen_width = LCD_WIDTH as isize;
+        let screen_height = LCD_HEIGHT as isize;
+        let line_height = self.font.line_height() as isize;
+        let caption_height = line_height * caption.lines.len() as isize;
+
+        let top = match caption.position / 3 {
+            0 => CAPTION_MARGIN,
+            1 => (screen_height - caption_height) / 2,
+            _ => screen_height - caption_height - CAPTION_MARGIN,
+        };
+
+        for (line_index, line) in caption.lines.iter().enumerate() {
+            let line_width = self.font.text_width(line) as isize;
+            let left = match caption.position % 3 {
+                0 => CAPTION_MARGIN,
+                1 => (screen_width - line_width) / 2,
+                _ => screen_width - line_width - CAPTION_MARGIN,
+            };
+
+            self.font.draw(
+                screen,
+                LCD_WIDTH.into(),
+                (left, top + line_index as isize * line_height),
+                line,
+                self.foreground,
+                self.background,
+            );
+        }
+    }
+}
+
+pub async fn preview(
+    path: &Path,
+    out: &Path,
+    title_index: usize,
+    selection: FrameSelection,
+    options: PreviewOptions,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(options.scale != 0, "Scale must be at least 1");
+
+    let (mut reader, title, range) = open_title(path, title_index, selection).await?;
+
+    let track_index = options
+        .caption_track
+        .or((!title.caption_tracks.is_empty()).then_some(0));
+
+    let captions = match track_index {
+        Some(track_index) => {
+            let track = title.caption_tracks.get(track_index).with_context(|| {
+                format!(
+                    "Caption track {track_index} doesn't exist; the title has {} caption tracks",
+                    title.caption_tracks.len()
+                )
+            })?;
+
+            let font_pack = match &options.font_pack {
+                Some(font_pack) => tokio::fs::read(font_pack).await.with_context(|| {
+                    format!("Failed to read font pack: {}", font_pack.display())
+                })?,
+                None => reader
+                    .font_pack()
+                    .context(
+                        "Container has no font pack for its captions; pass one with --font-pack",
+                    )?
+                    .to_vec(),
+            };
+
+            let font = Font::from_pack(&font_pack, track.font_index)?;
+            let chunks = reader.caption_chunks(title_index, track_index).await?;
+
+            info!("Showing caption track {track_index}: \"{}\"", track.name);
+
+            Some(CaptionRenderer::new(
+                font,
+                chunks,
+                title.caption_foreground,
+                (!title.caption_transparent).then_some(title.caption_background),
+            ))
+        }
+        None => None,
+    };
+
+    info!(
+        "Previewing frames {range:?} of title {title_index} to: {}",
+        out.display()
+    );
+
+    let palette = Palette::new(title.color_palette.as_deref());
+    let width = u32::from(LCD_WIDTH) * options.scale;
+    let height = u32::from(LCD_HEIGHT) * options.scale;
+    let mut writer = FrameWriter::new(out, title.fps, width, height).await?;
+
+    extract_frames(
+        &mut reader,
+        title_index,
+        range,
+        async |frame_index, mut screen| {
+            if let Some(captions) = &captions {
+                captions.draw(&mut screen, frame_index);
+            }
+
+            let image = imageops::resize(&palette.rgb(&screen), width, height, FilterType::Nearest);
+            writer.write(frame_index, &image).await
+        },
+    )
+    .await?;
+
+    writer.finish().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::tests::sample_font_pack;
+
+    fn caption(position: u8, lines: &[&[u8]]) -> Caption {
+        Caption {
+            frame_start: 1,
+            frame_duration: 2,
+            position,
+            lines: lines.iter().map(|line| line.to_vec()).collect(),
+        }
+    }
+
+    #[test]
+    fn draw_captions() {
+        let font = Font::from_pack(&sample_font_pack(), 0).unwrap();
+        let chunk = CaptionChunk {
+            frame: 10,
+            frame_count: 5,
+            next_block_count: 0,
+            captions: vec![caption(0, &[b"A"]), caption(7, &[b"AA", b"A"])],
+        };
+        let renderer = CaptionRenderer::new(font, vec![chunk], 1, None);
+        let screen_width = usize::from(LCD_WIDTH);
+        let pixel = |screen: &[u8], x: usize, y: usize| screen[y * screen_width + x];
+
+        let mut screen = vec![0; screen_width * usize::from(LCD_HEIGHT)];
+        renderer.draw(&mut screen, 10);
+        assert!(screen.iter().all(|&pixel| pixel == 0));
+
+        renderer.draw(&mut screen, 11);
+
+        // Top left, below the blank space above glyphs
+        assert_eq!(pixel(&screen, 2, 3), 1);
+        assert_eq!(pixel(&screen, 3, 4), 1);
+
+        // Bottom center, two lines of 3 pixels above the margin
+        assert_eq!(pixel(&screen, 157, 233), 1);
+        assert_eq!(pixel(&screen, 158, 236), 1);
+        assert_eq!(pixel(&screen, 159, 237), 1);
+        assert_eq!(
+            screen.iter().filter(|&&pixel| pixel == 1).count(),
+            3 + 6 + 3
+        );
+    }
+}
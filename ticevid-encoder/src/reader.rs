@@ -0,0 +1,947 @@
+use std::io::SeekFrom;
+
+use anyhow::Context;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+use crate::{
+    BLOCK_SIZE, BLOCKS_PER_CHUNK, HEADER_SIZE, LCD_HEIGHT,
+    encode::{Codec, FrameType, PICTURE_CHUNK_HEADER_SIZE},
+    serialize::{PICTURE_CHUNK_TABLE_ENTRY_SIZE, SectorId, VERSION},
+};
+
+/// Bytes in a title's 16x16 icon.
+pub const ICON_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerHeader {
+    pub version: (u16, u8, u8),
+    /// The size of the header in bytes. Offsets never point past this.
+    pub header_size: u16,
+    pub titles: Vec<Title>,
+    /// Offset of the font pack from the start of the header.
+    pub font_pack: Option<usize>,
+    pub ui_font_index: u8,
+    /// Where each structure of the header was read from, in the order they were read.
+    pub sectors: Vec<HeaderSector>,
+}
+
+/// A structure read from the header, at an offset from its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderSector {
+    pub sector: SectorId,
+    pub offset: usize,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Title {
+    pub name: Option<String>,
+    /// 1555 colors. `None` for the default palette.
+    pub color_palette: Option<Vec<u16>>,
+    pub icon: Option<[u8; ICON_SIZE]>,
+    pub height: u8,
+    pub frame_count: u32,
+    pub fps: u8,
+    pub caption_tracks: Vec<CaptionTrack>,
+    pub caption_foreground: u8,
+    pub caption_background: u8,
+    pub caption_transparent: bool,
+    pub chapters: Vec<Chapter>,
+    /// The block index of the picture chunk table.
+    pub picture_chunk_table: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptionTrack {
+    pub name: String,
+    pub font_index: u8,
+    pub chunk_block_count: u8,
+    pub chunk_start: u32,
+    pub chunk_count: u32,
+}
+
+/// The captions shown over a run of frames, read in order from a caption track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptionChunk {
+    /// The first frame of the chunk.
+    pub frame: u32,
+    pub frame_count: u32,
+    /// Blocks the next chunk takes up, or `0` for the last chunk.
+    pub next_block_count: u8,
+    pub captions: Vec<Caption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caption {
+    /// Relative to the start of the chunk.
+    pub frame_start: u32,
+    pub frame_duration: u32,
+    /// Top left to bottom right, from `0` to `8`.
+    pub position: u8,
+    /// Text in the encoding of the track's font.
+    pub lines: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub start_frame: u32,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PictureChunkInfo {
+    pub block_count: u16,
+    pub block_index: u32,
+}
+
+impl PictureChunkInfo {
+    /// Parses an entry of a picture chunk table.
+    pub fn parse(entry: [u8; PICTURE_CHUNK_TABLE_ENTRY_SIZE]) -> Self {
+        let [count_low, count_high, index_low, index_middle, index_high] = entry;
+
+        Self {
+            block_count: u16::from_le_bytes([count_low, count_high]),
+            block_index: u32::from_le_bytes([index_low, index_middle, index_high, 0]),
+        }
+    }
+
+    /// Where the picture chunk starts in the container.
+    pub fn position(&self) -> u64 {
+        u64::from(self.block_index) * u64::from(BLOCK_SIZE)
+    }
+
+    /// How many bytes the player reads for the picture chunk.
+    pub fn size(&self) -> usize {
+        usize::from(self.block_count) * usize::from(BLOCK_SIZE)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PictureChunk {
+    pub frame_type: FrameType,
+    pub codec: Codec,
+    pub image: Vec<u8>,
+}
+
+/// Reads fields in order from the header, bounds checking every read and offset like the player.
+struct HeaderCursor<'a> {
+    header: &'a [u8],
+    position: usize,
+}
+
+impl<'a> HeaderCursor<'a> {
+    fn new(header: &'a [u8], position: usize) -> Self {
+        Self { header, position }
+    }
+
+    fn bytes<const N: usize>(&mut self, field: &str) -> anyhow::Result<[u8; N]> {
+        let start = self.position;
+        let bytes = self
+            .header
+            .get(start..)
+            .and_then(<[u8]>::first_chunk::<N>)
+            .with_context(|| {
+                format!(
+                    "`{field}` at byte {start} runs past the end of the header; {} > {}",
+                    start + N,
+                    self.header.len()
+                )
+            })?;
+        self.position += N;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self, field: &str) -> anyhow::Result<u8> {
+        self.bytes::<1>(field).map(|[value]| value)
+    }
+
+    fn u16(&mut self, field: &str) -> anyhow::Result<u16> {
+        self.bytes(field).map(u16::from_le_bytes)
+    }
+
+    fn u24(&mut self, field: &str) -> anyhow::Result<u32> {
+        let [low, middle, high] = self.bytes(field)?;
+        Ok(u32::from_le_bytes([low, middle, high, 0]))
+    }
+
+    /// Only the first bit is checked, with `0` being true.
+    fn bool(&mut self, field: &str) -> anyhow::Result<bool> {
+        self.u8(field).map(|value| value & 1 == 0)
+    }
+
+    /// A `?T`, which can't point past the end of the header.
+    fn nullable_offset(&mut self, field: &str) -> anyhow::Result<Option<usize>> {
+        let offset = self.u24(field)? as usize;
+
+        if offset == 0 {
+            return Ok(None);
+        }
+
+        anyhow::ensure!(
+            offset <= self.header.len(),
+            "`{field}` points outside the header; {offset} > {}",
+            self.header.len()
+        );
+
+        Ok(Some(offset))
+    }
+
+    /// A `&T`, which can't be null.
+    fn offset(&mut self, field: &str) -> anyhow::Result<usize> {
+        self.nullable_offset(field)?
+            .with_context(|| format!("`{field}` is null"))
+    }
+
+    fn at(&self, offset: usize) -> Self {
+        Self::new(self.header, offset)
+    }
+
+    /// A `str` as its raw bytes, without the null terminator.
+    fn text(&self, offset: usize, field: &str) -> anyhow::Result<&'a [u8]> {
+        let text = &self.header[offset..];
+        let length = text.iter().position(|&byte| byte == 0).with_context(|| {
+            format!("`{field}` at byte {offset} isn't null terminated before the end of the header")
+        })?;
+        Ok(&text[..length])
+    }
+
+    /// A `?str`, recording where it was read from as `sector`.
+    fn nullable_string(
+        &mut self,
+        field: &str,
+        sector: SectorId,
+        sectors: &mut Vec<HeaderSector>,
+    ) -> anyhow::Result<Option<String>> {
+        self.nullable_offset(field)?
+            .map(|offset| {
+                let text = self.text(offset, field)?;
+                sectors.push(HeaderSector {
+                    sector,
+                    offset,
+                    size: text.len() + 1,
+                });
+                Ok(String::from_utf8_lossy(text).into_owned())
+            })
+            .transpose()
+    }
+
+    /// Records the bytes from `start` up to the cursor as `sector`.
+    fn sector_from(&self, start: usize, sector: SectorId, sectors: &mut Vec<HeaderSector>) {
+        sectors.push(HeaderSector {
+            sector,
+            offset: start,
+            size: self.position - start,
+        });
+    }
+
+    /// The offsets of a `?[&T]`, which is only null when `count` is zero.
+    fn table(
+        &mut self,
+        count: u8,
+        field: &str,
+        sector: SectorId,
+        sectors: &mut Vec<HeaderSector>,
+    ) -> anyhow::Result<Vec<usize>> {
+        let Some(table) = self.nullable_offset(field)? else {
+            anyhow::ensure!(count == 0, "`{field}` is null with a count of {count}");
+            return Ok(Vec::new());
+        };
+
+        let mut cursor = self.at(table);
+        let offsets = (0..count)
+            .map(|index| cursor.offset(&format!("{field}[{index}]")))
+            .collect::<anyhow::Result<_>>()?;
+        cursor.sector_from(table, sector, sectors);
+        Ok(offsets)
+    }
+}
+
+impl ContainerHeader {
+    /// Parses the header from the start of a container, checking it the same way the player does.
+    pub fn parse(container: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = HeaderCursor::new(container, 0);
+
+        let version = (
+            cursor.u16("format_version_major")?,
+            cursor.u8("format_version_minor")?,
+            cursor.u8("format_version_patch")?,
+        );
+
+        // Patch versions are compatible
+        anyhow::ensure!(
+            (version.0, version.1) == (VERSION.0, VERSION.1),
+            "Unsupported format version {}.{}.{}; expected {}.{}.x",
+            version.0,
+            version.1,
+            version.2,
+            VERSION.0,
+            VERSION.1
+        );
+
+        let header_size = cursor.u16("header_size")?;
+
+        anyhow::ensure!(
+            header_size != 0 && header_size <= HEADER_SIZE,
+            "Header size out of range; {header_size} not in 1..={HEADER_SIZE}"
+        );
+
+        let header = container.get(..header_size.into()).with_context(|| {
+            format!(
+                "Container ends before the end of the header; {} < {header_size}",
+                container.len()
+            )
+        })?;
+
+        let mut cursor = HeaderCursor::new(header, cursor.position);
+
+        let title_count = cursor.u8("title_count")?;
+        anyhow::ensure!(title_count != 0, "Container has no titles");
+
+        let mut sectors = Vec::new();
+        let title_table_offset = cursor.offset("title_table")?;
+        let mut title_table = cursor.at(title_table_offset);
+
+        let titles = (0..title_count)
+            .map(|title_index| {
+                title_table
+                    .offset(&format!("title_table[{title_index}]"))
+                    .and_then(|title| {
+                        Title::parse(&mut cursor.at(title), title_index, &mut sectors)
+                    })
+                    .with_context(|| format!("Invalid title {title_index}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        title_table.sector_from(title_table_offset, SectorId::TitleTable, &mut sectors);
+
+        let font_pack = cursor.nullable_offset("font_pack")?;
+        let ui_font_index = cursor.u8("ui_font_index")?;
+        cursor.sector_from(0, SectorId::Header, &mut sectors);
+
+        // The font pack runs to the end of the header
+        if let Some(font_pack) = font_pack {
+            sectors.push(HeaderSector {
+                sector: SectorId::FontPack,
+                offset: font_pack,
+                size: header.len() - font_pack,
+            });
+        }
+
+        anyhow::ensure!(
+            font_pack.is_some() || ui_font_index == 0,
+            "UI font index is {ui_font_index} without a font pack"
+        );
+
+        Ok(Self {
+            version,
+            header_size,
+            titles,
+            font_pack,
+            ui_font_index,
+            sectors,
+        })
+    }
+}
+
+impl Title {
+    fn parse(
+        cursor: &mut HeaderCursor,
+        title_index: u8,
+        sectors: &mut Vec<HeaderSector>,
+    ) -> anyhow::Result<Self> {
+        let start = cursor.position;
+        let name = cursor.nullable_string("name", SectorId::TitleName { title_index }, sectors)?;
+
+        let color_palette_count = cursor.u8("color_palette_count")?;
+        let color_palette = match cursor.nullable_offset("color_palette")? {
+            Some(offset) => {
+                let mut palette = cursor.at(offset);
+                let colors = (0..color_palette_count)
+                    .map(|index| palette.u16(&format!("color_palette[{index}]")))
+                    .collect::<anyhow::Result<_>>()?;
+                palette.sector_from(offset, SectorId::ColorPalette { title_index }, sectors);
+                Some(colors)
+            }
+            None => {
+                anyhow::ensure!(
+                    color_palette_count == 0,
+                    "`color_palette` is null with a count of {color_palette_count}"
+                );
+                None
+            }
+        };
+
+        let icon = cursor
+            .nullable_offset("icon")?
+            .map(|offset| {
+                let mut icon = cursor.at(offset);
+                let pixels = icon.bytes("icon")?;
+                icon.sector_from(offset, SectorId::Icon { title_index }, sectors);
+                anyhow::Ok(pixels)
+            })
+            .transpose()?;
+
+        let height = cursor.u8("height")?;
+        anyhow::ensure!(
+            u16::from(height) <= LCD_HEIGHT,
+            "Height is larger than the screen; {height} > {LCD_HEIGHT}"
+        );
+
+        let frame_count = cursor.u24("frame_count")?;
+        anyhow::ensure!(frame_count != 0, "Title has no frames");
+
+        let fps = cursor.u8("fps")?;
+
+        let caption_track_count = cursor.u8("caption_track_count")?;
+        let caption_tracks = cursor
+            .table(
+                caption_track_count,
+                "caption_tracks",
+                SectorId::CaptionTrackTable { title_index },
+                sectors,
+            )?
+            .into_iter()
+            .zip(0..)
+            .map(|(offset, track_index)| {
+                CaptionTrack::parse(&mut cursor.at(offset), title_index, track_index, sectors)
+                    .with_context(|| format!("Invalid caption track {track_index}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let caption_foreground = cursor.u8("caption_foreground")?;
+        let caption_background = cursor.u8("caption_background")?;
+        let caption_transparent = cursor.bool("caption_transparent")?;
+
+        let chapter_count = cursor.u8("chapter_count")?;
+        let chapters = cursor
+            .table(
+                chapter_count,
+                "chapter_table",
+                SectorId::ChapterTable { title_index },
+                sectors,
+            )?
+            .into_iter()
+            .zip(0..)
+            .map(|(offset, chapter_index)| {
+                Chapter::parse(&mut cursor.at(offset), title_index, chapter_index, sectors)
+                    .with_context(|| format!("Invalid chapter {chapter_index}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let picture_chunk_table = cursor.u24("picture_chunk_table")?;
+        cursor.sector_from(start, SectorId::Title { title_index }, sectors);
+
+        Ok(Self {
+            name,
+            color_palette,
+            icon,
+            height,
+            frame_count,
+            fps,
+            caption_tracks,
+            caption_foreground,
+            caption_background,
+            caption_transparent,
+            chapters,
+            picture_chunk_table,
+        })
+    }
+
+    /// Where the picture chunk table starts in the container.
+    pub fn picture_chunk_table_position(&self) -> u64 {
+        u64::from(self.picture_chunk_table) * u64::from(BLOCK_SIZE)
+    }
+}
+
+impl CaptionTrack {
+    fn parse(
+        cursor: &mut HeaderCursor,
+        title_index: u8,
+        track_index: u8,
+        sectors: &mut Vec<HeaderSector>,
+    ) -> anyhow::Result<Self> {
+        let start = cursor.position;
+        let name = cursor
+            .nullable_string(
+                "name",
+                SectorId::CaptionTrackName {
+                    title_index,
+                    track_index,
+                },
+                sectors,
+            )?
+            .context("`name` is null")?;
+        let font_index = cursor.u8("font_index")?;
+        let chunk_block_count = cursor.u8("chunk_block_count")?;
+        let chunk_start = cursor.u24("chunk_start")?;
+        let chunk_count = cursor.u24("chunk_count")?;
+        cursor.sector_from(
+            start,
+            SectorId::CaptionTrack {
+                title_index,
+                track_index,
+            },
+            sectors,
+        );
+
+        anyhow::ensure!(chunk_block_count != 0, "First caption chunk has no blocks");
+        anyhow::ensure!(chunk_count != 0, "Caption track has no chunks");
+
+        Ok(Self {
+            name,
+            font_index,
+            chunk_block_count,
+            chunk_start,
+            chunk_count,
+        })
+    }
+}
+
+impl Chapter {
+    fn parse(
+        cursor: &mut HeaderCursor,
+        title_index: u8,
+        chapter_index: u8,
+        sectors: &mut Vec<HeaderSector>,
+    ) -> anyhow::Result<Self> {
+        let start = cursor.position;
+        let sector = SectorId::Chapter {
+            title_index,
+            chapter_index,
+        };
+        let name_sector = SectorId::ChapterName {
+            title_index,
+            chapter_index,
+        };
+
+        let chapter = Self {
+            start_frame: cursor.u24("start_frame")?,
+            name: cursor.nullable_string("name", name_sector, sectors)?,
+        };
+        cursor.sector_from(start, sector, sectors);
+        Ok(chapter)
+    }
+}
+
+impl CaptionChunk {
+    /// Parses a caption chunk, whose offsets are relative to the start of the chunk.
+    pub fn parse(chunk: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = HeaderCursor::new(chunk, 0);
+
+        let frame = cursor.u24("frame")?;
+        let frame_count = cursor.u24("frame_count")?;
+        let next_block_count = cursor.u8("chunk_block_count")?;
+        let captions = cursor.offset("captions")?;
+        let caption_count = cursor.u8("caption_count")?;
+
+        anyhow::ensure!(frame_count != 0, "Caption chunk has no frames");
+        anyhow::ensure!(caption_count != 0, "Caption chunk has no captions");
+
+        let mut captions = cursor.at(captions);
+        let captions = (0..caption_count)
+            .map(|caption_index| {
+                Caption::parse(&mut captions)
+                    .with_context(|| format!("Invalid caption {caption_index}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            frame,
+            frame_count,
+            next_block_count,
+            captions,
+        })
+    }
+}
+
+impl Caption {
+    fn parse(cursor: &mut HeaderCursor) -> anyhow::Result<Self> {
+        let frame_start = cursor.u24("frame_start")?;
+        let frame_duration = cursor.u24("frame_durration")?;
+        let position = cursor.u8("position")?;
+        let line_count = cursor.u8("line_count")?;
+
+        anyhow::ensure!(
+            position <= 8,
+            "Caption position out of range; {position} > 8"
+        );
+        anyhow::ensure!(line_count != 0, "Caption has no lines");
+
+        let lines = cursor.offset("lines")?;
+        let mut lines = cursor.at(lines);
+        let lines = (0..line_count)
+            .map(|line_index| {
+                let field = format!("lines[{line_index}]");
+                let line = lines.offset(&field)?;
+                lines.text(line, &field).map(<[u8]>::to_vec)
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            frame_start,
+            frame_duration,
+            position,
+            lines,
+        })
+    }
+
+    /// Whether the caption is shown on a frame of the title.
+    pub fn is_shown(&self, chunk: &CaptionChunk, frame_index: u32) -> bool {
+        let start = chunk.frame + self.frame_start;
+        (start..start + self.frame_duration).contains(&frame_index)
+    }
+}
+
+impl PictureChunk {
+    /// Parses a picture chunk read using `info`.
+    pub fn parse(chunk: &[u8], info: PictureChunkInfo) -> anyhow::Result<Self> {
+        let header = chunk
+            .first_chunk::<PICTURE_CHUNK_HEADER_SIZE>()
+            .context("Picture chunk is smaller than its header")?;
+        let [size_low, size_high, frame_type, codec] = *header;
+
+        let image_size = usize::from(u16::from_le_bytes([size_low, size_high]));
+        anyhow::ensure!(image_size != 0, "Picture chunk has an empty image");
+
+        let image = chunk[PICTURE_CHUNK_HEADER_SIZE..]
+            .get(..image_size)
+            .with_context(|| {
+                format!(
+                    "Image is larger than its {} blocks; {} > {}",
+                    info.block_count,
+                    PICTURE_CHUNK_HEADER_SIZE + image_size,
+                    chunk.len()
+                )
+            })?;
+
+        Ok(Self {
+            frame_type: FrameType::try_from(frame_type)?,
+            codec: Codec::try_from(codec)?,
+            image: image.to_vec(),
+        })
+    }
+}
+
+/// Reads the picture data of a container on demand, after parsing its header.
+pub struct ContainerReader<R> {
+    reader: R,
+    header: ContainerHeader,
+    /// The header's bytes up to its size, which the font pack is part of.
+    header_bytes: Vec<u8>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> ContainerReader<R> {
+    pub async fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut header = Vec::with_capacity(HEADER_SIZE.into());
+        reader.seek(SeekFrom::Start(0)).await?;
+        (&mut reader)
+            .take(HEADER_SIZE.into())
+            .read_to_end(&mut header)
+            .await
+            .context("Failed to read container header")?;
+
+        let mut header_bytes = header;
+        let header = ContainerHeader::parse(&header_bytes)?;
+        header_bytes.truncate(header.header_size.into());
+
+        Ok(Self {
+            reader,
+            header,
+            header_bytes,
+        })
+    }
+
+    pub fn header(&self) -> &ContainerHeader {
+        &self.header
+    }
+
+    /// The fontlibc font pack, running to the end of the header.
+    pub fn font_pack(&self) -> Option<&[u8]> {
+        self.header
+            .font_pack
+            .map(|offset| &self.header_bytes[offset..])
+    }
+
+    /// Reads every chunk of a caption track.
+    ///
+    /// Chunks follow each other, each one giving the size of the next.
+    pub async fn caption_chunks(
+        &mut self,
+        title_index: usize,
+        track_index: usize,
+    ) -> anyhow::Result<Vec<CaptionChunk>> {
+        let track = self
+            .header
+            .titles
+            .get(title_index)
+            .and_then(|title| title.caption_tracks.get(track_index))
+            .with_context(|| {
+                format!("Caption track {track_index} of title {title_index} doesn't exist")
+            })?;
+
+        let mut block_index = track.chunk_start;
+        let mut block_count = track.chunk_block_count;
+        let mut chunks = Vec::with_capacity(track.chunk_count as usize);
+
+        for chunk_index in 0..track.chunk_count {
+            anyhow::ensure!(
+                (1..=BLOCKS_PER_CHUNK).contains(&block_count),
+                "Caption chunk {chunk_index} doesn't fit the player's buffer; {block_count} blocks not in 1..={BLOCKS_PER_CHUNK}"
+            );
+
+            let mut chunk = vec![0; usize::from(block_count) * usize::from(BLOCK_SIZE)];
+            self.reader
+                .seek(SeekFrom::Start(
+                    u64::from(block_index) * u64::from(BLOCK_SIZE),
+                ))
+                .await?;
+            self.reader.read_exact(&mut chunk).await.with_context(|| {
+                format!("Container ends before caption chunk {chunk_index} at block {block_index}")
+            })?;
+
+            let chunk = CaptionChunk::parse(&chunk).with_context(|| {
+                format!("Invalid caption chunk {chunk_index} at block {block_index}")
+            })?;
+
+            block_index += u32::from(block_count);
+            block_count = chunk.next_block_count;
+            chunks.push(chunk);
+        }
+
+        Ok(chunks)
+    }
+
+    /// Reads the picture chunk table of a title, with one entry per frame.
+    pub async fn picture_chunk_table(
+        &mut self,
+        title_index: usize,
+    ) -> anyhow::Result<Vec<PictureChunkInfo>> {
+        let title = self
+            .header
+            .titles
+            .get(title_index)
+            .with_context(|| format!("Title {title_index} doesn't exist"))?;
+
+        let mut table = vec![0; title.frame_count as usize * PICTURE_CHUNK_TABLE_ENTRY_SIZE];
+        self.reader
+            .seek(SeekFrom::Start(title.picture_chunk_table_position()))
+            .await?;
+        self.reader.read_exact(&mut table).await.with_context(|| {
+            format!(
+                "Container ends before the picture chunk table of title {title_index} at block {}",
+                title.picture_chunk_table
+            )
+        })?;
+
+        let (entries, _) = table.as_chunks::<PICTURE_CHUNK_TABLE_ENTRY_SIZE>();
+
+        entries
+            .iter()
+            .enumerate()
+            .map(|(frame_index, &entry)| {
+                let info = PictureChunkInfo::parse(entry);
+
+                anyhow::ensure!(
+                    (1..=BLOCKS_PER_CHUNK.into()).contains(&info.block_count),
+                    "Picture chunk of frame {frame_index} in title {title_index} doesn't fit the player's buffer; {} blocks not in 1..={BLOCKS_PER_CHUNK}",
+                    info.block_count
+                );
+
+                Ok(info)
+            })
+            .collect()
+    }
+
+    /// Reads the picture chunk an entry of a picture chunk table points to.
+    pub async fn picture_chunk(&mut self, info: PictureChunkInfo) -> anyhow::Result<PictureChunk> {
+        let mut chunk = vec![0; info.size()];
+        self.reader.seek(SeekFrom::Start(info.position())).await?;
+        self.reader.read_exact(&mut chunk).await.with_context(|| {
+            format!(
+                "Container ends before the picture chunk at block {} of {} blocks",
+                info.block_index, info.block_count
+            )
+        })?;
+
+        PictureChunk::parse(&chunk, info)
+            .with_context(|| format!("Invalid picture chunk at block {}", info.block_index))
+    }
+
+    /// Reads the size of a picture chunk's image, without reading the image.
+    pub async fn picture_chunk_image_size(
+        &mut self,
+        info: PictureChunkInfo,
+    ) -> anyhow::Result<u16> {
+        let mut image_size = [0; 2];
+        self.reader.seek(SeekFrom::Start(info.position())).await?;
+        self.reader
+            .read_exact(&mut image_size)
+            .await
+            .with_context(|| {
+                format!(
+                    "Container ends before the picture chunk at block {}",
+                    info.block_index
+                )
+            })?;
+        Ok(u16::from_le_bytes(image_size))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A container like the encoder writes, with one title of two frames and a chapter.
+    pub(crate) fn sample_container() -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE.into()];
+        let fields: &[(usize, &[u8])] = &[
+            // Version, header size, title count, title table, font pack, UI font index
+            (0, &[0, 0, 4, 0, 0, 0x20, 1, 16, 0, 0, 0, 0, 0, 0]),
+            // Title table
+            (16, &[19, 0, 0]),
+            // Title
+            (
+                19,
+                &[
+                    48, 0, 0, 0, 0, 0, 0, 0, 0, 0, 120, 2, 0, 0, 30, 0, 0, 0, 0, 0xFF, 0, 1, 1, 53,
+                    0, 0, 16, 0, 0,
+                ],
+            ),
+            (48, b"Demo\0"),
+            // Chapter table
+            (53, &[56, 0, 0]),
+            // Chapter
+            (56, &[1, 0, 0, 62, 0, 0]),
+            (62, b"Intro\0"),
+        ];
+
+        for (offset, bytes) in fields {
+            header[*offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        // Picture chunk table
+        let mut table = vec![0; BLOCK_SIZE.into()];
+        table[..10].copy_from_slice(&[1, 0, 17, 0, 0, 1, 0, 18, 0, 0]);
+
+        let mut chunks = vec![0; usize::from(BLOCK_SIZE) * 2];
+        chunks[..6].copy_from_slice(&[2, 0, 0, 0, 0xC3, 0x80]);
+        chunks[512..517].copy_from_slice(&[1, 0, 0, 0, 0xC3]);
+
+        [header, table, chunks].concat()
+    }
+
+    #[tokio::test]
+    async fn read_sample_container() {
+        let mut reader = ContainerReader::new(Cursor::new(sample_container()))
+            .await
+            .unwrap();
+
+        let title = &reader.header().titles[0];
+        assert_eq!(title.name.as_deref(), Some("Demo"));
+        assert_eq!((title.height, title.frame_count, title.fps), (120, 2, 30));
+        assert!(!title.caption_transparent);
+        assert_eq!(
+            title.chapters,
+            vec![Chapter {
+                start_frame: 1,
+                name: Some("Intro".to_string())
+            }]
+        );
+
+        let table = reader.picture_chunk_table(0).await.unwrap();
+        assert_eq!(table.len(), 2);
+
+        let chunk = reader.picture_chunk(table[1]).await.unwrap();
+        assert_eq!(
+            chunk,
+            PictureChunk {
+                frame_type: FrameType::Key,
+                codec: Codec::Qoi,
+                image: vec![0xC3],
+            }
+        );
+    }
+
+    fn parse_error(edit: impl FnOnce(&mut [u8])) -> String {
+        let mut container = sample_container();
+        edit(&mut container);
+        format!(
+            "{:#}",
+            ContainerHeader::parse(&container[..HEADER_SIZE.into()]).unwrap_err()
+        )
+    }
+
+    #[test]
+    fn offsets_past_header_size() {
+        let error = parse_error(|container| container[59..62].copy_from_slice(&[0, 0x30, 0]));
+        assert_eq!(
+            error,
+            "Invalid title 0: Invalid chapter 0: `name` points outside the header; 12288 > 8192"
+        );
+
+        // Shrink the header to end partway through the chapter's name
+        let error = parse_error(|container| container[4..6].copy_from_slice(&[64, 0]));
+        assert_eq!(
+            error,
+            "Invalid title 0: Invalid chapter 0: `name` at byte 62 isn't null terminated before the end of the header"
+        );
+
+        let error = parse_error(|container| container[4..6].copy_from_slice(&[60, 0]));
+        assert_eq!(
+            error,
+            "Invalid title 0: Invalid chapter 0: `name` at byte 59 runs past the end of the header; 62 > 60"
+        );
+    }
+
+    #[test]
+    fn null_table_with_count() {
+        let error = parse_error(|container| container[19 + 23..19 + 26].fill(0));
+        assert_eq!(
+            error,
+            "Invalid title 0: `chapter_table` is null with a count of 1"
+        );
+    }
+
+    #[test]
+    fn parse_caption_chunk() {
+        let mut chunk = vec![0; BLOCK_SIZE.into()];
+        #[rustfmt::skip]
+        let fields: &[u8] = &[
+            // Frame, frame count, next chunk's block count, captions, caption count
+            10, 0, 0, 5, 0, 0, 0, 11, 0, 0, 1,
+            // Caption
+            1, 0, 0, 2, 0, 0, 7, 1, 22, 0, 0,
+            // Lines
+            25, 0, 0,
+        ];
+        chunk[..fields.len()].copy_from_slice(fields);
+        chunk[25..28].copy_from_slice(b"Hi\0");
+
+        let chunk = CaptionChunk::parse(&chunk).unwrap();
+        let caption = &chunk.captions[0];
+        assert_eq!(
+            (caption.position, &caption.lines),
+            (7, &vec![b"Hi".to_vec()])
+        );
+        assert_eq!(
+            (10..14)
+                .map(|frame| caption.is_shown(&chunk, frame))
+                .collect::<Vec<_>>(),
+            [false, true, true, false]
+        );
+    }
+
+    #[tokio::test]
+    async fn picture_chunk_too_large() {
+        let mut container = sample_container();
+        container[usize::from(HEADER_SIZE) + 5] = 17;
+
+        let mut reader = ContainerReader::new(Cursor::new(container)).await.unwrap();
+        let error = reader.picture_chunk_table(0).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Picture chunk of frame 1 in title 0 doesn't fit the player's buffer; 17 blocks not in 1..=16"
+        );
+    }
+}
@@ -0,0 +1,619 @@
+//! Reports on an encode, written next to the output for tracking numbers across releases.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    BLOCK_SIZE, LCD_WIDTH,
+    encode::{Codec, FrameType, PICTURE_CHUNK_HEADER_SIZE, frame_timestamp},
+    layout::LayoutSector,
+    metrics::FrameQuality,
+    serialize::{EncodedTitle, picture_chunk_blocks},
+};
+
+/// Bytes covered by each bar of the frame size histogram.
+const SIZE_HISTOGRAM_BUCKET: usize = 1024;
+/// How many of the largest frames are reported per title.
+const WORST_FRAMES: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct BuildReport {
+    pub file_size: u64,
+    pub titles: Vec<TitleBuildReport>,
+    pub layout: Vec<LayoutSector>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TitleBuildReport {
+    pub name: String,
+    pub fps: u8,
+    pub height: u8,
+    pub frame_count: usize,
+    pub keyframes: usize,
+    pub encode_seconds: f64,
+    pub caption_tracks: usize,
+    pub chapters: usize,
+    /// Frames each codec was chosen for.
+    pub codec_frames: BTreeMap<Codec, usize>,
+    /// Bytes of the frames before compression.
+    pub raw_bytes: u64,
+    /// Bytes of the frames' images.
+    pub image_bytes: u64,
+    /// Bytes of the picture chunks, including padding to whole blocks.
+    pub chunk_bytes: u64,
+    pub padding_bytes: u64,
+    /// Raw bytes per image byte.
+    pub compression_ratio: f64,
+    pub frame_size_histogram: Vec<SizeBucket>,
+    pub blocks_per_frame: BlockPercentiles,
+    /// The largest frames, largest first.
+    pub worst_frames: Vec<FrameSummary>,
+    /// `None` unless quality was measured while encoding.
+    pub quality: Option<QualityReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QualityReport {
+    pub average_psnr: f64,
+    pub min_psnr: f64,
+    pub average_ssim: f64,
+    pub min_ssim: f64,
+    /// Runs of frames below the title's quality floor.
+    pub scenes_below_floor: Vec<SceneSummary>,
+    /// Every frame's quality, in order.
+    pub frames: Vec<FrameQuality>,
+}
+
+/// Frames from `start_frame` up to but not including `end_frame`.
+#[derive(Debug, Serialize)]
+pub struct SceneSummary {
+    pub start_frame: u32,
+    pub end_frame: u32,
+    /// The first frame's time in the source video.
+    pub timestamp: String,
+    pub min_psnr: f64,
+    pub min_ssim: f64,
+}
+
+/// Frames with an image size from `min_bytes` up to but not including `max_bytes`.
+#[derive(Debug, Serialize)]
+pub struct SizeBucket {
+    pub min_bytes: usize,
+    pub max_bytes: usize,
+    pub frames: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BlockPercentiles {
+    pub p50: usize,
+    pub p90: usize,
+    pub p99: usize,
+    pub max: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FrameSummary {
+    pub frame_index: u32,
+    /// The frame's time in the source video.
+    pub timestamp: String,
+    pub size: usize,
+    pub blocks: usize,
+    pub frame_type: FrameType,
+    pub codec: Codec,
+}
+
+impl BlockPercentiles {
+    /// Nearest rank percentiles.
+    pub fn new(mut blocks: Vec<usize>) -> Self {
+        blocks.sort_unstable();
+
+        let percentile = |percent: usize| {
+            let rank = (blocks.len() * percent).div_ceil(100).max(1);
+            blocks.get(rank - 1).copied().unwrap_or_default()
+        };
+
+        Self {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: blocks.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl QualityReport {
+    fn new(encoded_title: &EncodedTitle, start: Duration) -> Option<Self> {
+        let EncodedTitle { quality, title, .. } = encoded_title;
+
+        if quality.is_empty() {
+            return None;
+        }
+
+        let average = |score: fn(&FrameQuality) -> f64| {
+            quality.iter().map(score).sum::<f64>() / quality.len() as f64
+        };
+        let min = |frames: &[FrameQuality], score: fn(&FrameQuality) -> f64| {
+            frames.iter().map(score).fold(f64::INFINITY, f64::min)
+        };
+
+        let scenes = title
+            .quality_floor
+            .as_ref()
+            .map(|quality_floor| quality_floor.settings().scenes_below(quality))
+            .unwrap_or_default();
+
+        Some(Self {
+            average_psnr: average(|quality| quality.psnr),
+            min_psnr: min(quality, |quality| quality.psnr),
+            average_ssim: average(|quality| quality.ssim),
+            min_ssim: min(quality, |quality| quality.ssim),
+            scenes_below_floor: scenes
+                .into_iter()
+                .map(|scene| {
+                    let frames = &quality[scene.start as usize..scene.end as usize];
+                    SceneSummary {
+                        start_frame: scene.start,
+                        end_frame: scene.end,
+                        timestamp: frame_timestamp(start, title.fps, scene.start),
+                        min_psnr: min(frames, |quality| quality.psnr),
+                        min_ssim: min(frames, |quality| quality.ssim),
+                    }
+                })
+                .collect(),
+            frames: quality.clone(),
+        })
+    }
+}
+
+impl TitleBuildReport {
+    fn new(encoded_title: &EncodedTitle) -> Self {
+        let EncodedTitle {
+            frames,
+            title,
+            encode_time,
+            ..
+        } = encoded_title;
+        let block_size = usize::from(BLOCK_SIZE);
+        let start = title.start.map(Duration::from).unwrap_or_default();
+
+        let mut codec_frames = BTreeMap::new();
+        let mut histogram = Vec::<usize>::new();
+
+        for frame in frames {
+            *codec_frames.entry(frame.codec).or_insert(0) += 1;
+
+            let bucket = frame.size / SIZE_HISTOGRAM_BUCKET;
+            if histogram.len() <= bucket {
+                histogram.resize(bucket + 1, 0);
+            }
+            histogram[bucket] += 1;
+        }
+
+        let blocks = frames.iter().map(picture_chunk_blocks).collect::<Vec<_>>();
+        let image_bytes = frames.iter().map(|frame| frame.size as u64).sum::<u64>();
+        let chunk_bytes = (blocks.iter().sum::<usize>() * block_size) as u64;
+        let used_bytes = image_bytes + (frames.len() * PICTURE_CHUNK_HEADER_SIZE) as u64;
+        let raw_bytes = (frames.len() * usize::from(LCD_WIDTH) * usize::from(title.height)) as u64;
+
+        let mut worst_frames = (0..).zip(frames).collect::<Vec<_>>();
+        // Stable, so ties keep the earlier frame first
+        worst_frames.sort_by_key(|(_, frame)| std::cmp::Reverse(frame.size));
+        worst_frames.truncate(WORST_FRAMES);
+
+        Self {
+            name: title.name.clone(),
+            fps: title.fps,
+            height: title.height,
+            frame_count: frames.len(),
+            keyframes: frames
+                .iter()
+                .filter(|frame| frame.frame_type == FrameType::Key)
+                .count(),
+            encode_seconds: encode_time.as_secs_f64(),
+            caption_tracks: title.captions.len(),
+            chapters: title.chapters.len(),
+            codec_frames,
+            raw_bytes,
+            image_bytes,
+            chunk_bytes,
+            padding_bytes: chunk_bytes - used_bytes,
+            compression_ratio: raw_bytes as f64 / image_bytes.max(1) as f64,
+            frame_size_histogram: histogram
+                .into_iter()
+                .enumerate()
+                .filter(|&(_, frames)| frames != 0)
+                .map(|(bucket, frames)| SizeBucket {
+                    min_bytes: bucket * SIZE_HISTOGRAM_BUCKET,
+                    max_bytes: (bucket + 1) * SIZE_HISTOGRAM_BUCKET,
+                    frames,
+                })
+                .collect(),
+            worst_frames: worst_frames
+                .into_iter()
+                .map(|(frame_index, frame)| FrameSummary {
+                    frame_index,
+                    timestamp: frame_timestamp(start, title.fps, frame_index),
+                    size: frame.size,
+                    blocks: blocks[frame_index as usize],
+                    frame_type: frame.frame_type,
+                    codec: frame.codec,
+                })
+                .collect(),
+            blocks_per_frame: BlockPercentiles::new(blocks),
+            quality: QualityReport::new(encoded_title, start),
+        }
+    }
+}
+
+/// Escapes text for HTML.
+struct Html<'a>(&'a str);
+
+impl fmt::Display for Html<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for character in self.0.chars() {
+            match character {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                character => f.write_char(character)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BuildReport {
+    /// `layout` is read back from the written container, since the header's layout is left to serseg.
+    pub fn new(titles: &[EncodedTitle], layout: Vec<LayoutSector>) -> Self {
+        Self {
+            file_size: layout
+                .last()
+                .map_or(0, |sector| sector.offset + sector.size + sector.padding),
+            titles: titles.iter().map(TitleBuildReport::new).collect(),
+            layout,
+        }
+    }
+
+    /// A standalone page of the report's tables.
+    pub fn html(&self) -> String {
+        let mut html = String::new();
+        self.write_html(&mut html)
+            .expect("Writing to a string doesn't fail");
+        html
+    }
+
+    fn write_html(&self, html: &mut String) -> fmt::Result {
+        writeln!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Build report</title>"
+        )?;
+        writeln!(
+            html,
+            "<style>body{{font-family:sans-serif}}table{{border-collapse:collapse;margin-bottom:1em}}\
+             td,th{{border:1px solid #999;padding:2px 6px;text-align:right}}\
+             .bar{{background:#48c;height:1em}}</style>\n</head>\n<body>"
+        )?;
+        writeln!(
+            html,
+            "<h1>Build report</h1>\n<p>File size: {} bytes</p>",
+            self.file_size
+        )?;
+
+        for (title_index, title) in self.titles.iter().enumerate() {
+            writeln!(html, "<h2>Title {title_index}: {}</h2>", Html(&title.name))?;
+            writeln!(html, "<table>")?;
+
+            let rows = [
+                ("Frames", title.frame_count.to_string()),
+                ("Keyframes", title.keyframes.to_string()),
+                ("FPS", title.fps.to_string()),
+                ("Height", format!("{} px", title.height)),
+                ("Encode time", format!("{:.2} s", title.encode_seconds)),
+                ("Caption tracks", title.caption_tracks.to_string()),
+                ("Chapters", title.chapters.to_string()),
+                ("Image bytes", title.image_bytes.to_string()),
+                ("Chunk bytes", title.chunk_bytes.to_string()),
+                ("Padding bytes", title.padding_bytes.to_string()),
+                (
+                    "Compression ratio",
+                    format!("{:.2}", title.compression_ratio),
+                ),
+                (
+                    "Blocks per frame",
+                    format!(
+                        "p50 {}, p90 {}, p99 {}, max {}",
+                        title.blocks_per_frame.p50,
+                        title.blocks_per_frame.p90,
+                        title.blocks_per_frame.p99,
+                        title.blocks_per_frame.max
+                    ),
+                ),
+            ];
+
+            for (name, value) in rows {
+                writeln!(html, "<tr><th>{name}</th><td>{value}</td></tr>")?;
+            }
+
+            for (codec, frames) in &title.codec_frames {
+                writeln!(html, "<tr><th>{codec:?} frames</th><td>{frames}</td></tr>")?;
+            }
+
+            writeln!(html, "</table>\n<h3>Frame sizes</h3>\n<table>")?;
+            writeln!(html, "<tr><th>Bytes</th><th>Frames</th><th></th></tr>")?;
+
+            let most_frames = title
+                .frame_size_histogram
+                .iter()
+                .map(|bucket| bucket.frames)
+                .max()
+                .unwrap_or(1);
+
+            for bucket in &title.frame_size_histogram {
+                writeln!(
+                    html,
+                    "<tr><td>{}&ndash;{}</td><td>{}</td><td style=\"width:300px;text-align:left\">\
+                     <div class=\"bar\" style=\"width:{:.1}%\"></div></td></tr>",
+                    bucket.min_bytes,
+                    bucket.max_bytes,
+                    bucket.frames,
+                    bucket.frames as f64 * 100.0 / most_frames as f64
+                )?;
+            }
+
+            writeln!(html, "</table>\n<h3>Largest frames</h3>\n<table>")?;
+            writeln!(
+                html,
+                "<tr><th>Frame</th><th>Time</th><th>Bytes</th><th>Blocks</th><th>Type</th><th>Codec</th></tr>"
+            )?;
+
+            for frame in &title.worst_frames {
+                writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:?}</td><td>{:?}</td></tr>",
+                    frame.frame_index,
+                    frame.timestamp,
+                    frame.size,
+                    frame.blocks,
+                    frame.frame_type,
+                    frame.codec
+                )?;
+            }
+
+            writeln!(html, "</table>")?;
+
+            if let Some(quality) = &title.quality {
+                writeln!(html, "<h3>Quality</h3>\n<table>")?;
+                writeln!(
+                    html,
+                    "<tr><th>PSNR</th><td>average {:.2} dB, min {:.2} dB</td></tr>",
+                    quality.average_psnr, quality.min_psnr
+                )?;
+                writeln!(
+                    html,
+                    "<tr><th>SSIM</th><td>average {:.4}, min {:.4}</td></tr>",
+                    quality.average_ssim, quality.min_ssim
+                )?;
+                writeln!(html, "</table>")?;
+
+                if !quality.scenes_below_floor.is_empty() {
+                    writeln!(html, "<h3>Below the quality floor</h3>\n<table>")?;
+                    writeln!(
+                        html,
+                        "<tr><th>Frames</th><th>Time</th><th>Min PSNR</th><th>Min SSIM</th></tr>"
+                    )?;
+
+                    for scene in &quality.scenes_below_floor {
+                        writeln!(
+                            html,
+                            "<tr><td>{}&ndash;{}</td><td>{}</td><td>{:.2}</td><td>{:.4}</td></tr>",
+                            scene.start_frame,
+                            scene.end_frame,
+                            scene.timestamp,
+                            scene.min_psnr,
+                            scene.min_ssim
+                        )?;
+                    }
+
+                    writeln!(html, "</table>")?;
+                }
+            }
+        }
+
+        writeln!(html, "<h2>Layout</h2>\n<table>")?;
+        writeln!(
+            html,
+            "<tr><th>Sector</th><th>Offset</th><th>Size</th><th>Padding</th></tr>"
+        )?;
+
+        for sector in &self.layout {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{:#x}</td><td>{}</td><td>{}</td></tr>",
+                Html(&format!("{:?}", sector.sector)),
+                sector.offset,
+                sector.size,
+                sector.padding
+            )?;
+        }
+
+        writeln!(html, "</table>\n</body>\n</html>")
+    }
+}
+
+/// Writes the JSON report, and optionally an HTML one, next to the output.
+pub async fn write_reports(report: &BuildReport, output: &Path, html: bool) -> anyhow::Result<()> {
+    let mut report_paths = vec![(
+        report_path(output, "json"),
+        serde_json::to_string_pretty(report)?,
+    )];
+
+    if html {
+        report_paths.push((report_path(output, "html"), report.html()));
+    }
+
+    for (path, contents) in report_paths {
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("Failed to write report: {}", path.display()))?;
+        info!("Wrote report: {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn report_path(output: &Path, extension: &str) -> std::path::PathBuf {
+    let mut report_path = output.to_path_buf();
+    report_path
+        .as_mut_os_string()
+        .push(format!(".report.{extension}"));
+    report_path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HEADER_SIZE, encode::EncodedFrame, serialize::SectorId};
+
+    fn frame(size: usize, frame_type: FrameType, codec: Codec) -> EncodedFrame {
+        EncodedFrame {
+            size,
+            frame_type,
+            codec,
+        }
+    }
+
+    #[test]
+    fn build_report() {
+        let title = toml::from_str(
+            r#"
+            name = "Title"
+            video = "video.mp4"
+            fps = 10
+            height = 100
+            start = { seconds = 1 }
+            quality_floor = { psnr = 30.0 }
+
+            [[chapters]]
+            name = "Start"
+            "#,
+        )
+        .unwrap();
+        let frames = vec![
+            frame(1000, FrameType::Key, Codec::Qoi),
+            frame(100, FrameType::Delta, Codec::Qoi),
+            frame(3000, FrameType::Delta, Codec::Motion),
+        ];
+        let encoded_title = EncodedTitle {
+            image_offsets: vec![0; frames.len()],
+            frames,
+            title,
+            encode_time: Duration::from_millis(1500),
+            quality: [(40.0, 0.99), (20.0, 0.8), (25.0, 0.9)]
+                .map(|(psnr, ssim)| FrameQuality { psnr, ssim })
+                .to_vec(),
+        };
+
+        let title_index = 0;
+        let layout = vec![
+            LayoutSector {
+                sector: SectorId::Header,
+                offset: 0,
+                size: 200,
+                padding: u64::from(HEADER_SIZE) - 200,
+            },
+            LayoutSector {
+                sector: SectorId::PictureChunkTable { title_index },
+                offset: 8192,
+                size: 15,
+                padding: 512 - 15,
+            },
+            LayoutSector {
+                sector: SectorId::PictureChunks { title_index },
+                offset: 8704,
+                size: 4112,
+                padding: 9 * 512 - 4112,
+            },
+        ];
+        let report = BuildReport::new(&[encoded_title], layout);
+        let title = &report.titles[0];
+
+        assert_eq!(title.keyframes, 1);
+        assert_eq!(title.chapters, 1);
+        assert_eq!(
+            title.codec_frames,
+            BTreeMap::from([(Codec::Qoi, 2), (Codec::Motion, 1)])
+        );
+        assert_eq!(title.image_bytes, 4100);
+        // 2 + 1 + 6 blocks
+        assert_eq!(title.chunk_bytes, 9 * 512);
+        assert_eq!(title.padding_bytes, 9 * 512 - 4100 - 3 * 4);
+        assert!((title.compression_ratio - 96_000.0 / 4100.0).abs() < 1e-9);
+
+        let histogram = title
+            .frame_size_histogram
+            .iter()
+            .map(|bucket| (bucket.min_bytes, bucket.frames))
+            .collect::<Vec<_>>();
+        assert_eq!(histogram, [(0, 2), (2048, 1)]);
+
+        assert_eq!(title.blocks_per_frame.p50, 2);
+        assert_eq!(title.blocks_per_frame.max, 6);
+
+        let worst = &title.worst_frames[0];
+        assert_eq!(
+            (worst.frame_index, worst.timestamp.as_str(), worst.blocks),
+            (2, "00:00:01.200", 6)
+        );
+        assert_eq!(title.worst_frames.len(), 3);
+
+        let layout = report
+            .layout
+            .iter()
+            .map(|sector| (sector.sector, sector.offset, sector.size, sector.padding))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            layout,
+            [
+                (SectorId::Header, 0, 200, u64::from(HEADER_SIZE) - 200),
+                (
+                    SectorId::PictureChunkTable { title_index },
+                    8192,
+                    15,
+                    512 - 15
+                ),
+                (
+                    SectorId::PictureChunks { title_index },
+                    8704,
+                    4112,
+                    9 * 512 - 4112
+                ),
+            ]
+        );
+        assert_eq!(report.file_size, 8704 + 9 * 512);
+
+        let quality = title.quality.as_ref().unwrap();
+        assert!((quality.average_psnr - 85.0 / 3.0).abs() < 1e-9);
+        assert_eq!(quality.min_ssim, 0.8);
+
+        let [scene] = &quality.scenes_below_floor[..] else {
+            panic!("Expected one scene below the floor");
+        };
+        assert_eq!(
+            (scene.start_frame, scene.end_frame, scene.timestamp.as_str()),
+            (1, 3, "00:00:01.100")
+        );
+        assert_eq!(scene.min_psnr, 20.0);
+
+        assert!(report.html().contains("<h2>Title 0: Title</h2>"));
+    }
+}
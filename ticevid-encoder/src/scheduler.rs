@@ -0,0 +1,155 @@
+use std::{future::Future, sync::Arc};
+
+use anyhow::Context;
+use futures_util::{StreamExt, TryStreamExt, stream};
+use tokio::sync::Semaphore;
+
+/// Shares `-j` threads between every title being encoded at once.
+///
+/// Titles run as their own tasks, waiting on FFmpeg without holding a thread,
+/// then take a thread from the scheduler for each frame's CPU heavy work.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    permits: Arc<Semaphore>,
+    threads: usize,
+}
+
+impl Scheduler {
+    pub fn new(threads: usize) -> Self {
+        let threads = threads.max(1);
+
+        Self {
+            permits: Arc::new(Semaphore::new(threads)),
+            threads,
+        }
+    }
+
+    /// How many jobs run at once.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Runs a job on a blocking thread once one of the scheduler's threads is free.
+    ///
+    /// Jobs own what they work on, handing it back in their output.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .context("Job scheduler was closed")?;
+
+        match tokio::task::spawn_blocking(job).await {
+            Ok(output) => Ok(output),
+            Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+            Err(error) => Err(error).context("Job was cancelled"),
+        }
+    }
+}
+
+/// Spawns a task for each item, up to `at_once` at a time, collecting their outputs in the items' order.
+///
+/// Tasks can finish in any order; the first to fail is returned once every task before it is done.
+pub async fn spawn_in_order<I, F, T>(
+    items: I,
+    at_once: usize,
+    mut task: impl FnMut(I::Item) -> F,
+) -> anyhow::Result<Vec<T>>
+where
+    I: IntoIterator,
+    F: Future<Output = anyhow::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    stream::iter(items)
+        .map(|item| tokio::spawn(task(item)))
+        .buffered(at_once.max(1))
+        .map(|join| join?)
+        .try_collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// `#[tokio::test]` uses a current-thread runtime.
+    #[tokio::test]
+    async fn runs_on_current_thread_runtime() {
+        let scheduler = Scheduler::new(1);
+        let frame = [1, 2, 3];
+        let sum = scheduler
+            .run(move || frame.iter().sum::<u8>())
+            .await
+            .unwrap();
+        assert_eq!(sum, 6);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn limits_jobs_at_once() {
+        let scheduler = Scheduler::new(2);
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+
+        spawn_in_order(0..8, 8, |_| {
+            let scheduler = scheduler.clone();
+            let running = Arc::clone(&running);
+            let most_running = Arc::clone(&most_running);
+
+            async move {
+                scheduler
+                    .run(move || {
+                        let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most_running.fetch_max(now_running, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "job panicked")]
+    async fn job_panics_propagate() {
+        Scheduler::new(1)
+            .run(|| panic!("job panicked"))
+            .await
+            .unwrap();
+    }
+
+    /// Later titles finishing first still come back in definition order.
+    #[tokio::test]
+    async fn keeps_definition_order() {
+        let outputs = spawn_in_order(0..4u64, 4, |index| async move {
+            tokio::time::sleep(Duration::from_millis(40 - index * 10)).await;
+            Ok(index)
+        })
+        .await
+        .unwrap();
+        assert_eq!(outputs, [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn first_error_in_order() {
+        let error = spawn_in_order(0..3u64, 3, |index| async move {
+            tokio::time::sleep(Duration::from_millis(30 - index * 10)).await;
+            anyhow::ensure!(index == 0, "Title {index} failed");
+            Ok(index)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Title 1 failed");
+    }
+}
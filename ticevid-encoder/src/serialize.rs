@@ -1,25 +1,33 @@
-use std::{
-    io::Cursor,
-    path::{Path, PathBuf},
-};
+use std::{io::SeekFrom, time::Duration};
 
 use anyhow::Context;
-use image::{DynamicImage, ImageReader};
-use log::debug;
-use tokio::io::AsyncWriteExt;
+use serde::Serialize;
+use tokio::io::{AsyncSeekExt, AsyncWrite, AsyncWriteExt};
 use u24::u24;
 
 use crate::{
-    BLOCK_SIZE, FRAME_FORMAT, FRAME_FORMAT_EXTENSION, HEADER_SIZE, LCD_HEIGHT, LCD_WIDTH,
+    BLOCK_SIZE, HEADER_SIZE,
     definition::title::TitleDefinition,
-    encode::{FrameEncoder, QoiEncoder},
+    encode::{EncodedFrame, PICTURE_CHUNK_HEADER_SIZE},
+    metrics::FrameQuality,
+    spool::SpoolReader,
 };
 
-pub const VERSION: (u16, u8, u8) = (0, 1, 0);
-
-async fn open_frame(path: PathBuf) -> anyhow::Result<DynamicImage> {
-    let buffer = tokio::fs::read(path).await?;
-    Ok(ImageReader::with_format(Cursor::new(buffer), FRAME_FORMAT).decode()?)
+pub const VERSION: (u16, u8, u8) = (0, 4, 0);
+
+/// Bytes per frame in a title's picture chunk table.
+pub const PICTURE_CHUNK_TABLE_ENTRY_SIZE: usize = 5;
+
+#[derive(Debug)]
+pub struct EncodedTitle {
+    pub frames: Vec<EncodedFrame>,
+    /// Where each frame's image starts in the spool.
+    pub image_offsets: Vec<u64>,
+    pub title: TitleDefinition,
+    /// How long encoding the title's frames took.
+    pub encode_time: Duration,
+    /// The quality of each frame, if it was measured.
+    pub quality: Vec<FrameQuality>,
 }
 
 fn compress_color_space(rgb: [u8; 3]) -> u8 {
@@ -30,68 +38,162 @@ fn compress_color_space(rgb: [u8; 3]) -> u8 {
     red | green | blue
 }
 
-async fn encode_frame(frame: DynamicImage) -> anyhow::Result<Vec<u8>> {
-    Ok(frame
-        .as_rgb8()
-        .context("Image wasn't 8-bit color.")?
-        .pixels()
-        .map(|pixel| compress_color_space(pixel.0))
-        .collect())
+/// The default xlibc palette's color for a pixel, the inverse of [`compress_color_space`].
+pub fn color_space_to_rgb(pixel: u8) -> [u8; 3] {
+    let scale = |value: u8, max: u8| (u16::from(value) * 255 / u16::from(max)) as u8;
+    let red = scale(pixel >> 5, 0b111);
+    let green = scale(pixel & 0b111, 0b111);
+    let blue = scale((pixel >> 3) & 0b11, 0b11);
+    [red, green, blue]
 }
 
-fn picture_chunk_path(frame_index: usize, frames_folder: &Path) -> PathBuf {
-    frames_folder.join(format!("{frame_index}.picture.bin"))
+/// How many times a frame can be requantized.
+pub const MAX_REQUANTIZE_LEVEL: u8 = 3;
+
+/// Rounds a channel to the nearest of `2^(bits - dropped_bits)` levels spread over its whole range.
+///
+/// The lowest and highest levels are `0` and the channel's maximum, so black and white are kept.
+fn coarsen_channel(value: u8, bits: u8, dropped_bits: u8) -> u8 {
+    if dropped_bits == 0 {
+        return value;
+    }
+
+    let max = u16::from((1u8 << bits) - 1);
+    let top_level = u16::from((1u8 << (bits - dropped_bits)) - 1);
+    let level = (u16::from(value) * top_level * 2 + max) / (max * 2);
+    ((level * max * 2 + top_level) / (top_level * 2)) as u8
 }
 
-pub async fn serialize_frame(
-    frames_folder: &Path,
-    frame_index: u32,
-    frame_count_digits: usize,
-) -> anyhow::Result<usize> {
-    let frame_name = frames_folder.join(format!("{frame_index}.{FRAME_FORMAT_EXTENSION}"));
-    let frame = open_frame(frame_name).await.map(encode_frame)?.await?;
-
-    let mut output_buffer = vec![0; LCD_WIDTH as usize * LCD_HEIGHT as usize];
-    let compressed_bytes = QoiEncoder::default().encode(&frame, &mut output_buffer)?;
-
-    debug!(
-        "Compressed frame {frame_index:>frame_count_digits$}: {} bytes => {} bytes, {:>6.2}%",
-        frame.len(),
-        compressed_bytes,
-        (compressed_bytes as f32 / frame.len() as f32) * 100.0,
-    );
+/// How far apart two colors in the calculator's color space are, in steps of the coarsest channel.
+pub fn color_distance(a: u8, b: u8) -> u8 {
+    let channels = |pixel: u8| [pixel >> 5, ((pixel >> 3) & 0b11) << 1, pixel & 0b111];
+    let [a, b] = [channels(a), channels(b)];
 
-    let path = picture_chunk_path(frame_index as usize, frames_folder);
-    tokio::fs::File::create(&path)
-        .await
-        .with_context(|| format!("Failed to open chunk at: {}", path.display()))?
-        .write_all(&output_buffer[..compressed_bytes])
-        .await
-        .with_context(|| format!("Failed to write chunk at: {}", path.display()))?;
+    a.iter()
+        .zip(&b)
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap_or_default()
+}
 
-    Ok(compressed_bytes)
+/// Reduces the colors of a frame already in the calculator's color space, making it easier to compress.
+///
+/// Higher levels are coarser, up to [`MAX_REQUANTIZE_LEVEL`].
+pub fn requantize(frame: &[u8], level: u8) -> Vec<u8> {
+    // Bits dropped from red, green, then blue
+    let (red_bits, green_bits, blue_bits) = match level {
+        0 => (0, 0, 0),
+        1 => (1, 1, 0),
+        2 => (1, 1, 1),
+        _ => (2, 2, 1),
+    };
+
+    frame
+        .iter()
+        .map(|&pixel| {
+            let red = coarsen_channel(pixel >> 5, 3, red_bits);
+            let blue = coarsen_channel((pixel >> 3) & 0b11, 2, blue_bits);
+            let green = coarsen_channel(pixel & 0b111, 3, green_bits);
+            (red << 5) | (blue << 3) | green
+        })
+        .collect()
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct PictureChunkId {
-    title_index: u8,
-    frame_index: usize,
+/// Converts 8-bit RGB pixels to the calculator's color space.
+pub fn rgb_to_color_space(rgb: &[u8]) -> Vec<u8> {
+    let (pixels, _) = rgb.as_chunks();
+    pixels
+        .iter()
+        .map(|&pixel| compress_color_space(pixel))
+        .collect()
+}
+
+/// How many blocks a frame's picture chunk takes up.
+pub fn picture_chunk_blocks(frame: &EncodedFrame) -> usize {
+    (PICTURE_CHUNK_HEADER_SIZE + frame.size).div_ceil(BLOCK_SIZE.into())
+}
+
+/// How many blocks a title's picture chunk table takes up.
+fn picture_chunk_table_blocks(frame_count: usize) -> usize {
+    (frame_count * PICTURE_CHUNK_TABLE_ENTRY_SIZE).div_ceil(BLOCK_SIZE.into())
+}
+
+/// The block the first picture chunk starts at, right after every title's picture chunk table.
+fn first_picture_chunk_block(titles: &[EncodedTitle]) -> usize {
+    usize::from(HEADER_SIZE) / usize::from(BLOCK_SIZE)
+        + titles
+            .iter()
+            .map(|title| picture_chunk_table_blocks(title.frames.len()))
+            .sum::<usize>()
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum SectorId {
+/// Copies each title's picture chunks from the spool, in the order of the picture chunk tables.
+async fn write_picture_chunks(
+    titles: &[EncodedTitle],
+    spool: &mut SpoolReader,
+    output_buffer: &mut (impl AsyncWrite + Unpin),
+) -> anyhow::Result<()> {
+    let mut image = Vec::new();
+    let block_size = usize::from(BLOCK_SIZE);
+
+    for EncodedTitle {
+        frames,
+        image_offsets,
+        title,
+        ..
+    } in titles
+    {
+        for (frame_index, (frame, &offset)) in frames.iter().zip(image_offsets).enumerate() {
+            image.resize(frame.size, 0);
+            spool.read(offset, &mut image).await.with_context(|| {
+                format!(
+                    "Failed to read frame {frame_index} of title \"{}\" from spool",
+                    title.name
+                )
+            })?;
+
+            let image_size = u16::try_from(frame.size)
+                .with_context(|| format!("Frame {frame_index} is too large: {}", frame.size))?;
+            let padding =
+                picture_chunk_blocks(frame) * block_size - (PICTURE_CHUNK_HEADER_SIZE + frame.size);
+
+            output_buffer.write_all(&image_size.to_le_bytes()).await?;
+            output_buffer
+                .write_all(&[frame.frame_type as u8, frame.codec as u8])
+                .await?;
+            output_buffer.write_all(&image).await?;
+            output_buffer.write_all(&vec![0; padding]).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A part of the container, as laid out by serseg, or the picture chunks copied after its sectors.
+///
+/// The encoder doesn't write palettes, icons, font packs or captions,
+/// but their sectors are still found when reading a container back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum SectorId {
     Header,
     TitleTable,
     Title { title_index: u8 },
     TitleName { title_index: u8 },
+    ColorPalette { title_index: u8 },
+    Icon { title_index: u8 },
+    CaptionTrackTable { title_index: u8 },
+    CaptionTrack { title_index: u8, track_index: u8 },
+    CaptionTrackName { title_index: u8, track_index: u8 },
+    ChapterTable { title_index: u8 },
+    Chapter { title_index: u8, chapter_index: u8 },
+    ChapterName { title_index: u8, chapter_index: u8 },
+    FontPack,
     HeaderEnd,
     Chunks,
     PictureChunkTable { title_index: u8 },
     PictureChunkTablePadding { title_index: u8 },
-    PictureChunk(PictureChunkId),
-    PictureChunkImage(PictureChunkId),
-    PictureChunkEnd(PictureChunkId),
-    PictureChunkPadding(PictureChunkId),
+    PictureChunks { title_index: u8 },
+    CaptionChunks { title_index: u8, track_index: u8 },
 }
 
 type SerialBuilder = serseg::prelude::SerialBuilder<SectorId>;
@@ -101,10 +203,84 @@ fn try_into_u24(value: impl TryInto<u32>) -> Option<u24> {
     value.try_into().ok().and_then(u24::checked_from_u32)
 }
 
-pub async fn serialize_container(
-    titles: Vec<(Vec<usize>, PathBuf, TitleDefinition)>,
-    mut output_buffer: impl tokio::io::AsyncWrite + tokio::io::AsyncSeek + Unpin,
-) -> anyhow::Result<()> {
+fn serialize_chapters(
+    mut builder: SerialBuilder,
+    title_index: u8,
+    title: &TitleDefinition,
+    frame_count: usize,
+) -> anyhow::Result<SerialBuilder> {
+    if title.chapters.is_empty() {
+        return Ok(builder);
+    }
+
+    let mut chapter_table_builder = SectorBuilder::default();
+
+    for chapter_index in (0..).take(title.chapters.len()) {
+        chapter_table_builder = chapter_table_builder.dynamic_u24(
+            SectorId::Header,
+            SectorId::Chapter {
+                title_index,
+                chapter_index,
+            },
+            0,
+        );
+    }
+
+    builder = builder.sector(
+        SectorId::ChapterTable { title_index },
+        chapter_table_builder,
+    );
+
+    for (chapter_index, chapter) in (0..).zip(&title.chapters) {
+        let start_frame = chapter.start_frame(title.fps);
+
+        anyhow::ensure!(
+            (start_frame as usize) < frame_count,
+            "Chapter \"{}\" starts after the end of title \"{}\"; frame {start_frame} >= {frame_count}",
+            chapter.name,
+            title.name
+        );
+
+        let mut chapter_builder = SectorBuilder::default()
+            .u24(try_into_u24(start_frame).context("Chapter start exceeded maximum")?);
+
+        chapter_builder = if chapter.name.is_empty() {
+            chapter_builder.null_24()
+        } else {
+            chapter_builder.dynamic_u24(
+                SectorId::Header,
+                SectorId::ChapterName {
+                    title_index,
+                    chapter_index,
+                },
+                0,
+            )
+        };
+
+        builder = builder.sector(
+            SectorId::Chapter {
+                title_index,
+                chapter_index,
+            },
+            chapter_builder,
+        );
+
+        if !chapter.name.is_empty() {
+            builder = builder.sector(
+                SectorId::ChapterName {
+                    title_index,
+                    chapter_index,
+                },
+                SectorBuilder::default().string(chapter.name.clone()),
+            );
+        }
+    }
+
+    Ok(builder)
+}
+
+/// Lays out everything but the picture chunks.
+fn container_builder(titles: &[EncodedTitle]) -> anyhow::Result<SerialBuilder> {
     let title_len = titles.len();
     let title_count = u8::try_from(title_len)
         .with_context(|| format!("Title count over maximum; {title_len} > {}", u8::MAX))?;
@@ -139,12 +315,16 @@ pub async fn serialize_container(
 
     // Title header
 
-    for (title_index, (frame_sizes, _, title)) in (0..title_count).zip(&titles) {
-        let frame_count = frame_sizes.len();
+    for (title_index, EncodedTitle { frames, title, .. }) in (0..title_count).zip(titles) {
+        let frame_count = frames.len();
         let frame_count = try_into_u24(frame_count).with_context(|| {
             format!("Frame count exceeded maximum; {frame_count} > {}", u24::MAX)
         })?;
 
+        let chapter_len = title.chapters.len();
+        let chapter_count = u8::try_from(chapter_len)
+            .with_context(|| format!("Chapter count over maximum; {chapter_len} > {}", u8::MAX))?;
+
         let mut title_builder = SectorBuilder::default();
 
         title_builder = if title.name.is_empty() {
@@ -153,39 +333,43 @@ pub async fn serialize_container(
             title_builder.dynamic_u24(SectorId::Header, SectorId::TitleName { title_index }, 0)
         };
 
+        let mut title_builder = title_builder
+            // Color palette count
+            .u8(0)
+            // Color palette
+            .null_24()
+            // Icon
+            .null_24()
+            .u8(title.height)
+            .u24(frame_count)
+            .u8(title.fps)
+            // Caption track count
+            .null_8()
+            // Caption tracks
+            .null_24()
+            // Caption foreground
+            .u8(0xFF)
+            // Caption background
+            .null_8()
+            // Caption transparent
+            // TODO: Add bools to serseg
+            .u8(1)
+            .u8(chapter_count);
+
+        title_builder = if chapter_count == 0 {
+            title_builder.null_24()
+        } else {
+            title_builder.dynamic_u24(SectorId::Header, SectorId::ChapterTable { title_index }, 0)
+        };
+
         builder = builder.sector(
             SectorId::Title { title_index },
-            title_builder
-                // Color palette count
-                .u8(0)
-                // Color palette
-                .null_24()
-                // Icon
-                .null_24()
-                .u8(title.height)
-                .u24(frame_count)
-                .u8(title.fps)
-                // Caption track count
-                .null_8()
-                // Caption tracks
-                .null_24()
-                // Caption foreground
-                .u8(0xFF)
-                // Caption background
-                .null_8()
-                // Caption transparent
-                // TODO: Add bools to serseg
-                .u8(1)
-                // Chapter count
-                .null_8()
-                // Chapter table
-                .null_24()
-                .dynamic_u24_chunk(
-                    SectorId::Header,
-                    SectorId::PictureChunkTable { title_index },
-                    0,
-                    BLOCK_SIZE as usize,
-                ),
+            title_builder.dynamic_u24_chunk(
+                SectorId::Header,
+                SectorId::PictureChunkTable { title_index },
+                0,
+                BLOCK_SIZE as usize,
+            ),
         );
 
         if !title.name.is_empty() {
@@ -194,6 +378,8 @@ pub async fn serialize_container(
                 SectorBuilder::default().string(title.name.clone()),
             );
         }
+
+        builder = serialize_chapters(builder, title_index, title, frames.len())?;
     }
 
     // End of header
@@ -205,31 +391,23 @@ pub async fn serialize_container(
         )
         .sector_default(SectorId::Chunks);
 
+    // Picture chunks are copied from the spool after the tables, so their blocks are worked out here
+    let mut block_index = first_picture_chunk_block(titles);
+
     // Picture chunk tables
-    for (title_index, (frame_count, _, _)) in (0..title_count).zip(&titles) {
+    for (title_index, EncodedTitle { frames, .. }) in (0..title_count).zip(titles) {
         let mut picture_chunk_table_builder = SectorBuilder::default();
 
-        for frame_index in 0..frame_count.len() {
-            let chunk_id = PictureChunkId {
-                title_index,
-                frame_index,
-            };
+        for frame in frames {
+            let blocks = picture_chunk_blocks(frame);
 
             picture_chunk_table_builder = picture_chunk_table_builder
                 // Block count
-                .dynamic_u16_chunk(
-                    SectorId::PictureChunk(chunk_id),
-                    SectorId::PictureChunkEnd(chunk_id),
-                    0,
-                    BLOCK_SIZE as usize,
-                )
+                .u16(blocks as u16)
                 // Block index
-                .dynamic_u24_chunk(
-                    SectorId::Header,
-                    SectorId::PictureChunk(chunk_id),
-                    0,
-                    BLOCK_SIZE as usize,
-                )
+                .u24(try_into_u24(block_index).context("Container exceeded maximum size")?);
+
+            block_index += blocks;
         }
 
         builder = builder
@@ -243,36 +421,188 @@ pub async fn serialize_container(
             );
     }
 
+    Ok(builder)
+}
+
+/// Serializes the header and picture chunk tables without any picture chunks, for laying out a container.
+pub async fn serialize_header(titles: &[EncodedTitle]) -> anyhow::Result<Vec<u8>> {
+    let mut header = std::io::Cursor::new(Vec::new());
+    container_builder(titles)?.build(&mut header).await?;
+    Ok(header.into_inner())
+}
+
+pub async fn serialize_container(
+    titles: &[EncodedTitle],
+    spool: &mut SpoolReader,
+    mut output_buffer: impl tokio::io::AsyncWrite + tokio::io::AsyncSeek + Unpin,
+) -> anyhow::Result<()> {
+    container_builder(titles)?.build(&mut output_buffer).await?;
+
     // Picture chunks
-    for (title_index, (frame_count, frames_directory, _)) in (0..title_count).zip(&titles) {
-        for (frame_index, frame_size) in frame_count.iter().enumerate() {
-            let chunk_id = PictureChunkId {
-                title_index,
-                frame_index,
-            };
-
-            let frame_path = picture_chunk_path(frame_index + 1, frames_directory);
-
-            builder = builder
-                .sector(
-                    SectorId::PictureChunk(chunk_id),
-                    SectorBuilder::default().dynamic_u16(
-                        SectorId::PictureChunkImage(chunk_id),
-                        SectorId::PictureChunkEnd(chunk_id),
-                        0,
-                    ),
-                )
-                .sector(
-                    SectorId::PictureChunkImage(chunk_id),
-                    SectorBuilder::default().external(frame_path, *frame_size),
-                )
-                .sector_default(SectorId::PictureChunkEnd(chunk_id))
-                .sector(
-                    SectorId::PictureChunkPadding(chunk_id),
-                    SectorBuilder::default().align(SectorId::Header, BLOCK_SIZE as usize),
+    let chunks_position = output_buffer.seek(SeekFrom::End(0)).await?;
+    let first_chunk_position = first_picture_chunk_block(titles) as u64 * u64::from(BLOCK_SIZE);
+    anyhow::ensure!(
+        chunks_position == first_chunk_position,
+        "Picture chunk tables end at {chunks_position}, but their entries start at {first_chunk_position}"
+    );
+    write_picture_chunks(titles, spool, &mut output_buffer).await?;
+    output_buffer.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::Arc};
+
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        LCD_HEIGHT, LCD_WIDTH,
+        encode::{PictureEncoder, tests::grain_frames},
+        reader::{ContainerReader, PictureChunkInfo},
+        spool::{ImageSpool, SpoolSegment},
+    };
+
+    /// Encodes a title's frames into its own segments of the spool, returning each frame's image too.
+    async fn encode_title(
+        spool: &Arc<Mutex<ImageSpool>>,
+        name: &str,
+        frames: Vec<Vec<u8>>,
+    ) -> (EncodedTitle, Vec<Vec<u8>>) {
+        let title = toml::from_str(&format!(
+            r#"
+            name = "{name}"
+            video = "video.mp4"
+            fps = 30
+            height = 4
+            "#
+        ))
+        .unwrap();
+
+        let mut picture_encoder = PictureEncoder::new(30, 0.5, []);
+        let mut segment = SpoolSegment::new(Arc::clone(spool));
+        let mut encoded_frames = Vec::new();
+        let mut images = Vec::new();
+
+        for frame in frames {
+            let mut image = vec![0; usize::from(LCD_WIDTH) * usize::from(LCD_HEIGHT)];
+            let encoded_frame = picture_encoder.encode(frame, &mut image).unwrap();
+            image.truncate(encoded_frame.size);
+
+            segment.append(&image).await.unwrap();
+            encoded_frames.push(encoded_frame);
+            images.push(image);
+        }
+
+        let encoded_title = EncodedTitle {
+            frames: encoded_frames,
+            image_offsets: segment.finish().await.unwrap(),
+            title,
+            encode_time: Duration::ZERO,
+            quality: Vec::new(),
+        };
+        (encoded_title, images)
+    }
+
+    /// Writes a container through the spool, then reads every picture chunk back.
+    async fn assert_round_trips(spool: ImageSpool) {
+        let spool = Arc::new(Mutex::new(spool));
+        // 110 entries take up 550 bytes, so the first table is padded to 2 blocks
+        let (first, first_images) = encode_title(&spool, "First", grain_frames(110, 1)).await;
+        let (second, second_images) = encode_title(&spool, "Second", grain_frames(3, 4)).await;
+        let titles = [first, second];
+
+        let mut spool = Arc::try_unwrap(spool)
+            .ok()
+            .unwrap()
+            .into_inner()
+            .into_reader()
+            .await
+            .unwrap();
+        let mut container = Cursor::new(Vec::new());
+        serialize_container(&titles, &mut spool, &mut container)
+            .await
+            .unwrap();
+        spool.remove().await.unwrap();
+
+        let mut reader = ContainerReader::new(Cursor::new(container.into_inner()))
+            .await
+            .unwrap();
+        assert_eq!(reader.header().titles.len(), 2);
+
+        // Header, then 2 + 1 blocks of picture chunk tables
+        let mut block_index = 16 + 3;
+
+        for (title_index, (title, images)) in
+            titles.iter().zip([first_images, second_images]).enumerate()
+        {
+            let table = reader.picture_chunk_table(title_index).await.unwrap();
+            assert_eq!(table.len(), title.frames.len());
+
+            for (frame_index, ((info, frame), image)) in
+                table.into_iter().zip(&title.frames).zip(images).enumerate()
+            {
+                let block_count = picture_chunk_blocks(frame);
+                assert_eq!(
+                    info,
+                    PictureChunkInfo {
+                        block_count: block_count as u16,
+                        block_index,
+                    },
+                    "frame {frame_index} of title {title_index}"
                 );
+                block_index += block_count as u32;
+
+                let chunk = reader.picture_chunk(info).await.unwrap();
+                assert_eq!(
+                    (chunk.frame_type, chunk.codec, chunk.image),
+                    (frame.frame_type, frame.codec, image),
+                    "frame {frame_index} of title {title_index}"
+                );
+            }
         }
+
+        // Grain in 4 rows needs more than one block
+        assert!(
+            titles[1]
+                .frames
+                .iter()
+                .all(|frame| picture_chunk_blocks(frame) > 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn container_round_trips_through_memory_spool() {
+        assert_round_trips(ImageSpool::memory()).await;
     }
 
-    builder.build(&mut output_buffer).await
+    #[tokio::test]
+    async fn container_round_trips_through_file_spool() {
+        let path =
+            std::env::temp_dir().join(format!("ticevid-round-trip-{}.spool", std::process::id()));
+        assert_round_trips(ImageSpool::create(path.clone()).await.unwrap()).await;
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn requantize_rounds_within_range() {
+        // Black and white are kept at every level
+        assert_eq!(
+            requantize(&[0x00, 0xFF], MAX_REQUANTIZE_LEVEL),
+            vec![0x00, 0xFF]
+        );
+        assert_eq!(requantize(&[0x00, 0xFF], 0), vec![0x00, 0xFF]);
+        // Red with a bit dropped has the levels 0, 2, 5 and 7
+        assert_eq!(
+            requantize(&[0b0110_0000, 0b1000_0000], 1),
+            vec![0b0100_0000, 0b1010_0000]
+        );
+        // Green with two bits dropped is either off or at its maximum
+        assert_eq!(
+            requantize(&[0b011, 0b100], MAX_REQUANTIZE_LEVEL),
+            vec![0, 0b111]
+        );
+    }
 }
@@ -0,0 +1,425 @@
+//! Plays a container back on a virtual block device, timing reads and decodes the way the player makes them.
+
+use std::{
+    fmt::{self, Display},
+    io::SeekFrom,
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, BufReader},
+};
+
+use crate::{
+    BLOCK_SIZE, BLOCKS_PER_CHUNK, BLOCKS_PER_HEADER, EZ80_CLOCK_HZ, LCD_WIDTH,
+    encode::registry::decode_cycles,
+    reader::{ContainerHeader, PictureChunk, PictureChunkInfo},
+    serialize::PICTURE_CHUNK_TABLE_ENTRY_SIZE,
+};
+
+/// The player loads the picture chunk table in pages of one entry per byte of a block.
+const TABLE_PAGE_ENTRIES: usize = BLOCK_SIZE as usize;
+const TABLE_PAGE_BLOCKS: u16 = PICTURE_CHUNK_TABLE_ENTRY_SIZE as u16;
+
+/// Rough eZ80 cycles to swap and blit the screen after each frame.
+pub const DEFAULT_DRAW_CYCLES: u64 = 230_400;
+
+/// Late frames printed before the rest are only counted.
+const MAX_LATE_FRAMES_SHOWN: usize = 50;
+
+/// How long a drive takes to answer reads.
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceModel {
+    /// Time spent on every read, however many blocks it is.
+    pub command_latency_us: f64,
+    pub block_latency_us: f64,
+}
+
+impl DeviceModel {
+    fn read_time_us(&self, block_count: u16) -> f64 {
+        self.command_latency_us + f64::from(block_count) * self.block_latency_us
+    }
+}
+
+/// How long the player takes to decode and draw frames.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeModel {
+    pub clock_hz: u32,
+    /// Multiplies the encoder's estimates of each codec's decode cycles.
+    pub cost_scale: f64,
+    /// Cycles spent every frame after decoding.
+    pub draw_cycles: u64,
+}
+
+impl Default for DecodeModel {
+    fn default() -> Self {
+        Self {
+            clock_hz: EZ80_CLOCK_HZ,
+            cost_scale: 1.0,
+            draw_cycles: DEFAULT_DRAW_CYCLES,
+        }
+    }
+}
+
+impl DecodeModel {
+    fn decode_time_us(&self, chunk: &PictureChunk, pixels: usize) -> f64 {
+        let cycles = decode_cycles(chunk.codec, chunk.image.len(), pixels) as f64 * self.cost_scale
+            + self.draw_cycles as f64;
+        cycles * 1_000_000.0 / f64::from(self.clock_hz)
+    }
+}
+
+/// A drive of 512 byte blocks holding a container, counting the time of every read.
+pub struct BlockDevice<R> {
+    reader: R,
+    model: DeviceModel,
+    reads: u64,
+    blocks_read: u64,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> BlockDevice<R> {
+    pub fn new(reader: R, model: DeviceModel) -> Self {
+        Self {
+            reader,
+            model,
+            reads: 0,
+            blocks_read: 0,
+        }
+    }
+
+    /// Reads blocks into the start of `buffer`, returning how long it took.
+    ///
+    /// Blocks past the end of the container read as zeros, like the rest of a drive.
+    async fn read(
+        &mut self,
+        block_index: u32,
+        block_count: u16,
+        buffer: &mut [u8],
+    ) -> anyhow::Result<f64> {
+        let size = usize::from(block_count) * usize::from(BLOCK_SIZE);
+        let buffer_size = buffer.len();
+        let buffer = buffer.get_mut(..size).with_context(|| {
+            format!("Read of {block_count} blocks doesn't fit the buffer of {buffer_size} bytes")
+        })?;
+
+        self.reader
+            .seek(SeekFrom::Start(
+                u64::from(block_index) * u64::from(BLOCK_SIZE),
+            ))
+            .await?;
+
+        let mut filled = 0;
+
+        while filled < size {
+            match self.reader.read(&mut buffer[filled..]).await? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+
+        buffer[filled..].fill(0);
+
+        self.reads += 1;
+        self.blocks_read += u64::from(block_count);
+
+        Ok(self.model.read_time_us(block_count))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LateFrame {
+    pub frame_index: u32,
+    /// How long after its slot ended the frame finished.
+    pub late_ms: f64,
+    pub read_ms: f64,
+    pub decode_ms: f64,
+    pub block_count: u16,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulationReport {
+    pub title_index: usize,
+    pub fps: u8,
+    pub frames: u32,
+    pub header_ms: f64,
+    pub reads: u64,
+    pub blocks_read: u64,
+    pub read_ms: f64,
+    pub decode_ms: f64,
+    pub average_frame_ms: f64,
+    pub max_frame_ms: f64,
+    /// The most blocks of the player's buffer any frame used.
+    pub max_buffer_blocks: u16,
+    pub late_frames: Vec<LateFrame>,
+}
+
+/// Simulates playing a title from the start, loading everything the same way the player does.
+///
+/// Frames start once the previous one is drawn, but never before their slot at the title's fps.
+pub async fn simulate(
+    device: &mut BlockDevice<impl AsyncRead + AsyncSeek + Unpin>,
+    title_index: usize,
+    decode_model: DecodeModel,
+) -> anyhow::Result<SimulationReport> {
+    let mut buffer = vec![0; usize::from(BLOCKS_PER_CHUNK) * usize::from(BLOCK_SIZE)];
+
+    let header_ms = device
+        .read(0, BLOCKS_PER_HEADER.into(), &mut buffer)
+        .await?
+        / 1_000.0;
+    let header = ContainerHeader::parse(&buffer)?;
+    let title = header
+        .titles
+        .get(title_index)
+        .with_context(|| {
+            format!(
+                "Title {title_index} doesn't exist; the container has {} titles",
+                header.titles.len()
+            )
+        })?
+        .clone();
+
+    anyhow::ensure!(title.fps != 0, "Title has no frame rate to play at");
+    anyhow::ensure!(title.frame_count != 0, "Title has no frames to play");
+
+    let pixels = usize::from(LCD_WIDTH) * usize::from(title.height);
+    let frame_us = 1_000_000.0 / f64::from(title.fps);
+
+    let mut table_page = vec![0; usize::from(TABLE_PAGE_BLOCKS) * usize::from(BLOCK_SIZE)];
+    let mut table_block = title.picture_chunk_table;
+
+    let mut clock_us = 0.0f64;
+    let mut read_us = 0.0;
+    let mut decode_us = 0.0;
+    let mut max_frame_us = 0.0f64;
+    let mut max_buffer_blocks = 0;
+    let mut late_frames = Vec::new();
+
+    for frame_index in 0..title.frame_count {
+        let frame_start_us = clock_us.max(f64::from(frame_index) * frame_us);
+        let mut frame_read_us = 0.0;
+
+        let entry = frame_index as usize % TABLE_PAGE_ENTRIES;
+
+        if entry == 0 {
+            if frame_index != 0 {
+                table_block += u32::from(TABLE_PAGE_BLOCKS);
+            }
+
+            frame_read_us += device
+                .read(table_block, TABLE_PAGE_BLOCKS, &mut table_page)
+                .await?;
+        }
+
+        let (entries, _) = table_page.as_chunks::<PICTURE_CHUNK_TABLE_ENTRY_SIZE>();
+        let info = PictureChunkInfo::parse(entries[entry]);
+
+        anyhow::ensure!(
+            (1..=BLOCKS_PER_CHUNK.into()).contains(&info.block_count),
+            "Picture chunk of frame {frame_index} doesn't fit the player's buffer; {} blocks not in 1..={BLOCKS_PER_CHUNK}",
+            info.block_count
+        );
+
+        frame_read_us += device
+            .read(info.block_index, info.block_count, &mut buffer)
+            .await?;
+
+        let chunk = PictureChunk::parse(&buffer[..info.size()], info)
+            .with_context(|| format!("Invalid picture chunk of frame {frame_index}"))?;
+        let frame_decode_us = decode_model.decode_time_us(&chunk, pixels);
+
+        let frame_time_us = frame_read_us + frame_decode_us;
+        clock_us = frame_start_us + frame_time_us;
+
+        read_us += frame_read_us;
+        decode_us += frame_decode_us;
+        max_frame_us = max_frame_us.max(frame_time_us);
+        max_buffer_blocks = max_buffer_blocks.max(info.block_count);
+
+        let deadline_us = f64::from(frame_index + 1) * frame_us;
+
+        if clock_us > deadline_us {
+            late_frames.push(LateFrame {
+                frame_index,
+                late_ms: (clock_us - deadline_us) / 1_000.0,
+                read_ms: frame_read_us / 1_000.0,
+                decode_ms: frame_decode_us / 1_000.0,
+                block_count: info.block_count,
+            });
+        }
+    }
+
+    Ok(SimulationReport {
+        title_index,
+        fps: title.fps,
+        frames: title.frame_count,
+        header_ms,
+        reads: device.reads,
+        blocks_read: device.blocks_read,
+        read_ms: read_us / 1_000.0,
+        decode_ms: decode_us / 1_000.0,
+        average_frame_ms: (read_us + decode_us) / f64::from(title.frame_count) / 1_000.0,
+        max_frame_ms: max_frame_us / 1_000.0,
+        max_buffer_blocks,
+        late_frames,
+    })
+}
+
+impl Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frame_ms = 1_000.0 / f64::from(self.fps);
+
+        writeln!(
+            f,
+            "Title {}: {} frames at {} FPS ({frame_ms:.2} ms per frame)",
+            self.title_index, self.frames, self.fps
+        )?;
+        writeln!(f, "  Header: {:.2} ms", self.header_ms)?;
+        writeln!(
+            f,
+            "  Reads: {} reads of {} blocks, {:.2} ms",
+            self.reads, self.blocks_read, self.read_ms
+        )?;
+        writeln!(f, "  Decoding: {:.2} ms", self.decode_ms)?;
+        writeln!(
+            f,
+            "  Frame time: {:.2} ms average, {:.2} ms max",
+            self.average_frame_ms, self.max_frame_ms
+        )?;
+        writeln!(
+            f,
+            "  Buffer: {}/{BLOCKS_PER_CHUNK} blocks max",
+            self.max_buffer_blocks
+        )?;
+        writeln!(
+            f,
+            "  Late frames: {}/{} ({:.2}%)",
+            self.late_frames.len(),
+            self.frames,
+            // Empty titles have no late frames, rather than a NaN share of them
+            self.late_frames.len() as f64 / f64::from(self.frames.max(1)) * 100.0
+        )?;
+
+        for frame in self.late_frames.iter().take(MAX_LATE_FRAMES_SHOWN) {
+            writeln!(
+                f,
+                "    Frame {}: {:.2} ms late; read {} blocks in {:.2} ms, decoded in {:.2} ms",
+                frame.frame_index, frame.late_ms, frame.block_count, frame.read_ms, frame.decode_ms
+            )?;
+        }
+
+        if let Some(hidden) = self.late_frames.len().checked_sub(MAX_LATE_FRAMES_SHOWN)
+            && hidden != 0
+        {
+            writeln!(f, "    ...and {hidden} more")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn simulate_file(
+    path: &Path,
+    title_index: usize,
+    device_model: DeviceModel,
+    decode_model: DecodeModel,
+    json: bool,
+) -> anyhow::Result<()> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open container: {}", path.display()))?;
+
+    let mut device = BlockDevice::new(BufReader::new(file), device_model);
+    let report = simulate(&mut device, title_index, decode_model)
+        .await
+        .with_context(|| format!("Failed to play container: {}", path.display()))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::tests::sample_container;
+
+    const INSTANT_DECODE: DecodeModel = DecodeModel {
+        clock_hz: EZ80_CLOCK_HZ,
+        cost_scale: 0.0,
+        draw_cycles: 0,
+    };
+
+    async fn simulate_sample(device_model: DeviceModel) -> SimulationReport {
+        let mut device = BlockDevice::new(Cursor::new(sample_container()), device_model);
+        simulate(&mut device, 0, INSTANT_DECODE).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn simulate_access_pattern() {
+        let report = simulate_sample(DeviceModel {
+            command_latency_us: 1_000.0,
+            block_latency_us: 100.0,
+        })
+        .await;
+
+        // The header, one page of the table, then each frame
+        assert_eq!((report.reads, report.blocks_read), (4, 16 + 5 + 2));
+        assert_eq!(report.header_ms, 2.6);
+        assert!((report.read_ms - 3.7).abs() < 1e-9, "{report:?}");
+        assert_eq!(report.max_buffer_blocks, 1);
+        assert!(report.late_frames.is_empty());
+    }
+
+    #[tokio::test]
+    async fn simulate_late_frames() {
+        // Slower than the 33 ms of a frame at 30 FPS
+        let report = simulate_sample(DeviceModel {
+            command_latency_us: 40_000.0,
+            block_latency_us: 0.0,
+        })
+        .await;
+
+        let late = report
+            .late_frames
+            .iter()
+            .map(|frame| (frame.frame_index, frame.late_ms.round()))
+            .collect::<Vec<_>>();
+        // The first frame also waits on the table, and the second starts late
+        assert_eq!(late, [(0, 47.0), (1, 53.0)]);
+
+        let text = report.to_string();
+        assert!(text.contains("Late frames: 2/2 (100.00%)"), "{text}");
+    }
+
+    #[test]
+    fn empty_report() {
+        let report = SimulationReport {
+            title_index: 0,
+            fps: 30,
+            frames: 0,
+            header_ms: 0.0,
+            reads: 1,
+            blocks_read: 16,
+            read_ms: 0.0,
+            decode_ms: 0.0,
+            average_frame_ms: 0.0,
+            max_frame_ms: 0.0,
+            max_buffer_blocks: 0,
+            late_frames: Vec::new(),
+        };
+
+        let text = report.to_string();
+        assert!(text.contains("Late frames: 0/0 (0.00%)"), "{text}");
+        assert!(!text.contains("NaN"), "{text}");
+    }
+}
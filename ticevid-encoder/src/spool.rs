@@ -0,0 +1,300 @@
+use std::{io::SeekFrom, path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter},
+    sync::Mutex,
+};
+
+/// How many bytes of a title's images are held before appending them to the spool.
+const SEGMENT_SIZE: usize = 1 << 20;
+
+/// Holds encoded images until the container is written.
+///
+/// Images from every title go into the one spool, so only a single file is ever open for them.
+/// Titles append through a [`SpoolSegment`] so their images stay together.
+pub enum ImageSpool {
+    Memory(Vec<u8>),
+    File {
+        path: PathBuf,
+        writer: BufWriter<File>,
+        len: u64,
+    },
+}
+
+impl ImageSpool {
+    pub fn memory() -> Self {
+        Self::Memory(Vec::new())
+    }
+
+    pub async fn create(path: PathBuf) -> anyhow::Result<Self> {
+        let file = File::create(&path)
+            .await
+            .with_context(|| format!("Failed to create spool: {}", path.display()))?;
+
+        Ok(Self::File {
+            path,
+            writer: BufWriter::new(file),
+            len: 0,
+        })
+    }
+
+    /// Returns where the image starts in the spool.
+    pub async fn append(&mut self, image: &[u8]) -> anyhow::Result<u64> {
+        match self {
+            Self::Memory(buffer) => {
+                let offset = buffer.len() as u64;
+                buffer.extend_from_slice(image);
+                Ok(offset)
+            }
+            Self::File { path, writer, len } => {
+                let offset = *len;
+                writer
+                    .write_all(image)
+                    .await
+                    .with_context(|| format!("Failed to write spool: {}", path.display()))?;
+                *len += image.len() as u64;
+                Ok(offset)
+            }
+        }
+    }
+
+    /// Finishes writing so images can be read back.
+    pub async fn into_reader(self) -> anyhow::Result<SpoolReader> {
+        match self {
+            Self::Memory(buffer) => Ok(SpoolReader::Memory(buffer)),
+            Self::File {
+                path, mut writer, ..
+            } => {
+                writer
+                    .flush()
+                    .await
+                    .with_context(|| format!("Failed to write spool: {}", path.display()))?;
+                drop(writer);
+
+                let file = File::open(&path)
+                    .await
+                    .with_context(|| format!("Failed to open spool: {}", path.display()))?;
+
+                Ok(SpoolReader::File {
+                    path,
+                    reader: BufReader::new(file),
+                    position: 0,
+                })
+            }
+        }
+    }
+}
+
+/// A title's images, appended to a shared [`ImageSpool`] a segment at a time.
+///
+/// Titles encoded at once would otherwise interleave their images,
+/// making the spool seek for almost every image when the container is written.
+pub struct SpoolSegment {
+    spool: Arc<Mutex<ImageSpool>>,
+    buffer: Vec<u8>,
+    /// Where each image not yet appended starts in `buffer`.
+    buffered_offsets: Vec<usize>,
+    /// Where each appended image starts in the spool.
+    offsets: Vec<u64>,
+}
+
+impl SpoolSegment {
+    pub fn new(spool: Arc<Mutex<ImageSpool>>) -> Self {
+        Self {
+            spool,
+            buffer: Vec::with_capacity(SEGMENT_SIZE),
+            buffered_offsets: Vec::new(),
+            offsets: Vec::new(),
+        }
+    }
+
+    pub async fn append(&mut self, image: &[u8]) -> anyhow::Result<()> {
+        self.buffered_offsets.push(self.buffer.len());
+        self.buffer.extend_from_slice(image);
+
+        if self.buffer.len() >= SEGMENT_SIZE {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        if self.buffered_offsets.is_empty() {
+            return Ok(());
+        }
+
+        let start = self.spool.lock().await.append(&self.buffer).await?;
+        self.offsets.extend(
+            self.buffered_offsets
+                .drain(..)
+                .map(|offset| start + offset as u64),
+        );
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Appends the rest of the title's images, returning where each image starts in the spool.
+    pub async fn finish(mut self) -> anyhow::Result<Vec<u64>> {
+        self.flush().await?;
+        Ok(self.offsets)
+    }
+}
+
+/// Reads images back from an [`ImageSpool`].
+pub enum SpoolReader {
+    Memory(Vec<u8>),
+    File {
+        path: PathBuf,
+        reader: BufReader<File>,
+        position: u64,
+    },
+}
+
+impl SpoolReader {
+    /// Fills `image` from `offset` in the spool.
+    ///
+    /// Reading images in the order they were appended avoids seeking.
+    pub async fn read(&mut self, offset: u64, image: &mut [u8]) -> anyhow::Result<()> {
+        match self {
+            Self::Memory(buffer) => {
+                let spooled = usize::try_from(offset)
+                    .ok()
+                    .and_then(|start| buffer.get(start..start.checked_add(image.len())?))
+                    .with_context(|| {
+                        format!(
+                            "Image out of bounds of spool; {offset} + {} > {}",
+                            image.len(),
+                            buffer.len()
+                        )
+                    })?;
+                image.copy_from_slice(spooled);
+            }
+            Self::File {
+                path,
+                reader,
+                position,
+            } => {
+                if *position != offset {
+                    reader.seek(SeekFrom::Start(offset)).await?;
+                }
+
+                reader.read_exact(image).await.with_context(|| {
+                    format!(
+                        "Spool ended before image at {offset} of {} bytes: {}",
+                        image.len(),
+                        path.display()
+                    )
+                })?;
+                *position = offset + image.len() as u64;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the spool's file, if it has one.
+    pub async fn remove(self) -> anyhow::Result<()> {
+        if let Self::File { path, reader, .. } = self {
+            drop(reader);
+            tokio::fs::remove_file(&path)
+                .await
+                .with_context(|| format!("Failed to remove spool: {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Titles appending at the same time each keep their images together.
+    #[tokio::test]
+    async fn segments_keep_titles_together() {
+        let spool = Arc::new(Mutex::new(ImageSpool::memory()));
+        let mut first = SpoolSegment::new(Arc::clone(&spool));
+        let mut second = SpoolSegment::new(Arc::clone(&spool));
+
+        for image in [[1; 3], [2; 3]] {
+            first.append(&image).await.unwrap();
+            second.append(&image.map(|pixel| pixel + 10)).await.unwrap();
+        }
+
+        let first_offsets = first.finish().await.unwrap();
+        let second_offsets = second.finish().await.unwrap();
+        assert_eq!(first_offsets, [0, 3]);
+        assert_eq!(second_offsets, [6, 9]);
+
+        let mut reader = Arc::try_unwrap(spool)
+            .ok()
+            .unwrap()
+            .into_inner()
+            .into_reader()
+            .await
+            .unwrap();
+        let mut image = [0; 3];
+        reader.read(second_offsets[1], &mut image).await.unwrap();
+        assert_eq!(image, [12; 3]);
+    }
+
+    /// Appends images, then reads them back in order and out of order.
+    async fn assert_reads_back(mut spool: ImageSpool) -> SpoolReader {
+        let images = [vec![1; 5], vec![], vec![2; 700], vec![3; 1]];
+        let mut offsets = Vec::new();
+
+        for image in &images {
+            offsets.push(spool.append(image).await.unwrap());
+        }
+        assert_eq!(offsets, [0, 5, 5, 705]);
+
+        let mut reader = spool.into_reader().await.unwrap();
+        for image_index in [0, 1, 2, 3, 2, 0, 3] {
+            let mut image = vec![0; images[image_index].len()];
+            reader.read(offsets[image_index], &mut image).await.unwrap();
+            assert_eq!(image, images[image_index], "image {image_index}");
+        }
+
+        let mut past_end = [0; 2];
+        assert!(reader.read(705, &mut past_end).await.is_err());
+        reader
+    }
+
+    #[tokio::test]
+    async fn memory_spool_reads_back() {
+        assert_reads_back(ImageSpool::memory())
+            .await
+            .remove()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_spool_reads_back() {
+        let path = std::env::temp_dir().join(format!("ticevid-spool-{}", std::process::id()));
+        let reader = assert_reads_back(ImageSpool::create(path.clone()).await.unwrap()).await;
+
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 706);
+        reader.remove().await.unwrap();
+        assert!(!path.exists());
+    }
+
+    /// Segments go to the spool once they're full, then start where the last one ended.
+    #[tokio::test]
+    async fn full_segments_append() {
+        let spool = Arc::new(Mutex::new(ImageSpool::memory()));
+        let mut first = SpoolSegment::new(Arc::clone(&spool));
+        let mut second = SpoolSegment::new(Arc::clone(&spool));
+
+        first.append(&[1; 10]).await.unwrap();
+        second.append(&vec![2; SEGMENT_SIZE]).await.unwrap();
+        first.append(&[]).await.unwrap();
+
+        let start = SEGMENT_SIZE as u64;
+        assert_eq!(first.finish().await.unwrap(), [start, start + 10]);
+        assert_eq!(second.finish().await.unwrap(), [0]);
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    fmt::{self, Display},
+    path::Path,
+};
+
+use anyhow::Context;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncSeek, BufReader},
+};
+
+use crate::{
+    BLOCK_SIZE, BLOCKS_PER_HEADER, LCD_WIDTH,
+    encode::{Codec, FrameType, PICTURE_CHUNK_HEADER_SIZE},
+    reader::{ContainerReader, PictureChunk, PictureChunkInfo, Title},
+    serialize::PICTURE_CHUNK_TABLE_ENTRY_SIZE,
+};
+
+/// Something in a container the player would misbehave on.
+#[derive(Debug)]
+pub struct Problem {
+    pub title_index: Option<usize>,
+    pub frame_index: Option<usize>,
+    pub error: anyhow::Error,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.title_index, self.frame_index) {
+            (Some(title_index), Some(frame_index)) => {
+                write!(f, "Title {title_index}, frame {frame_index}: ")?;
+            }
+            (Some(title_index), None) => write!(f, "Title {title_index}: ")?,
+            _ => (),
+        }
+
+        write!(f, "{:#}", self.error)
+    }
+}
+
+/// Collects every problem in a container instead of stopping at the first.
+#[derive(Debug, Default)]
+struct Problems(Vec<Problem>);
+
+impl Problems {
+    fn check(
+        &mut self,
+        title_index: Option<usize>,
+        frame_index: Option<usize>,
+        result: anyhow::Result<()>,
+    ) {
+        if let Err(error) = result {
+            self.0.push(Problem {
+                title_index,
+                frame_index,
+                error,
+            });
+        }
+    }
+}
+
+/// The player only reads whole blocks.
+fn check_file_size(file_size: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        file_size.is_multiple_of(BLOCK_SIZE.into()),
+        "Container isn't a whole number of blocks; {file_size} bytes"
+    );
+
+    Ok(())
+}
+
+/// Checks a title's picture chunk table sits after the header and inside the container.
+fn check_picture_chunk_table(title: &Title, file_size: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        title.picture_chunk_table >= BLOCKS_PER_HEADER.into(),
+        "Picture chunk table overlaps the header; block {} < {BLOCKS_PER_HEADER}",
+        title.picture_chunk_table
+    );
+
+    let table_end = title.picture_chunk_table_position()
+        + u64::from(title.frame_count) * PICTURE_CHUNK_TABLE_ENTRY_SIZE as u64;
+    anyhow::ensure!(
+        table_end <= file_size,
+        "Picture chunk table runs past the end of the container; {table_end} > {file_size}"
+    );
+
+    Ok(())
+}
+
+fn check_chapters(title: &Title, keyframes: Option<&[bool]>) -> anyhow::Result<()> {
+    for (chapter_index, chapter) in title.chapters.iter().enumerate() {
+        anyhow::ensure!(
+            chapter.start_frame < title.frame_count,
+            "Chapter {chapter_index} starts after the last frame; {} >= {}",
+            chapter.start_frame,
+            title.frame_count
+        );
+
+        if let Some(keyframes) = keyframes {
+            anyhow::ensure!(
+                keyframes
+                    .get(chapter.start_frame as usize)
+                    .copied()
+                    .unwrap_or_default(),
+                "Chapter {chapter_index} starts on frame {}, which isn't a keyframe",
+                chapter.start_frame
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks what [`ContainerReader::picture_chunk`] doesn't, given the chunk parsed.
+fn check_picture_chunk(
+    title: &Title,
+    frame_index: usize,
+    info: PictureChunkInfo,
+    chunk: &PictureChunk,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        info.block_index >= BLOCKS_PER_HEADER.into(),
+        "Picture chunk overlaps the header; block {} < {BLOCKS_PER_HEADER}",
+        info.block_index
+    );
+
+    let blocks = (PICTURE_CHUNK_HEADER_SIZE + chunk.image.len()).div_ceil(BLOCK_SIZE.into());
+    anyhow::ensure!(
+        usize::from(info.block_count) == blocks,
+        "Block count doesn't match the image size of {} bytes; {} != {blocks}",
+        chunk.image.len(),
+        info.block_count
+    );
+
+    anyhow::ensure!(
+        frame_index != 0 || chunk.frame_type == FrameType::Key,
+        "The first frame isn't a keyframe"
+    );
+
+    match chunk.codec {
+        Codec::Motion => anyhow::ensure!(
+            chunk.frame_type == FrameType::Delta,
+            "Motion images can only be used by delta frames"
+        ),
+        Codec::Raw => {
+            let pixels = usize::from(LCD_WIDTH) * usize::from(title.height);
+            anyhow::ensure!(
+                chunk.image.len() == pixels,
+                "Raw image size doesn't match the title's pixel count; {} != {pixels}",
+                chunk.image.len()
+            );
+        }
+        Codec::Qoi | Codec::Vq | Codec::Lzss => (),
+    }
+
+    Ok(())
+}
+
+/// Checks a container against everything the player relies on.
+///
+/// Returns every problem found, which is empty for valid containers.
+pub async fn verify_container(
+    reader: impl AsyncRead + AsyncSeek + Unpin,
+    file_size: u64,
+) -> anyhow::Result<Vec<Problem>> {
+    let mut problems = Problems::default();
+
+    problems.check(None, None, check_file_size(file_size));
+
+    // Nothing else can be trusted if the header is invalid
+    let mut reader = match ContainerReader::new(reader).await {
+        Ok(reader) => reader,
+        Err(error) => {
+            problems.check(None, None, Err(error));
+            return Ok(problems.0);
+        }
+    };
+
+    let titles = reader.header().titles.clone();
+
+    for (title_index, title) in titles.iter().enumerate() {
+        let title_problem = Some(title_index);
+
+        let table = match check_picture_chunk_table(title, file_size) {
+            Ok(()) => reader.picture_chunk_table(title_index).await,
+            Err(error) => Err(error),
+        };
+
+        let table = match table {
+            Ok(table) => table,
+            Err(error) => {
+                problems.check(title_problem, None, Err(error));
+                problems.check(title_problem, None, check_chapters(title, None));
+                continue;
+            }
+        };
+
+        let mut keyframes = vec![false; table.len()];
+
+        for (frame_index, &info) in table.iter().enumerate() {
+            let result = match reader.picture_chunk(info).await {
+                Ok(chunk) => {
+                    keyframes[frame_index] = chunk.frame_type == FrameType::Key;
+                    check_picture_chunk(title, frame_index, info, &chunk)
+                }
+                Err(error) => Err(error),
+            };
+
+            problems.check(title_problem, Some(frame_index), result);
+        }
+
+        problems.check(title_problem, None, check_chapters(title, Some(&keyframes)));
+    }
+
+    Ok(problems.0)
+}
+
+pub async fn verify(path: &Path) -> anyhow::Result<()> {
+    let file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open container: {}", path.display()))?;
+    let file_size = file.metadata().await?.len();
+
+    let problems = verify_container(BufReader::new(file), file_size).await?;
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    anyhow::ensure!(
+        problems.is_empty(),
+        "Found {} problems in container: {}",
+        problems.len(),
+        path.display()
+    );
+
+    println!("No problems found in container: {}", path.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{HEADER_SIZE, reader::tests::sample_container};
+
+    async fn container_problems(edit: impl FnOnce(&mut Vec<u8>)) -> Vec<String> {
+        let mut container = sample_container();
+        edit(&mut container);
+        let file_size = container.len() as u64;
+
+        verify_container(Cursor::new(container), file_size)
+            .await
+            .unwrap()
+            .iter()
+            .map(Problem::to_string)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn verify_sample_container() {
+        assert_eq!(container_problems(|_| ()).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn verify_invalid_header() {
+        assert_eq!(
+            container_problems(|container| container[2] = 3).await,
+            ["Unsupported format version 0.3.0; expected 0.4.x"]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_picture_chunks() {
+        let table = usize::from(HEADER_SIZE);
+        let chunks = table + usize::from(BLOCK_SIZE);
+
+        let problems = container_problems(|container| {
+            // Over allocated blocks
+            container[table] = 2;
+            // Delta frame on a chapter start
+            container[chunks + usize::from(BLOCK_SIZE) + 2] = FrameType::Delta as u8;
+            // Not block aligned
+            container.push(0);
+        })
+        .await;
+
+        assert_eq!(
+            problems,
+            [
+                "Container isn't a whole number of blocks; 9729 bytes",
+                "Title 0, frame 0: Block count doesn't match the image size of 2 bytes; 2 != 1",
+                "Title 0: Chapter 0 starts on frame 1, which isn't a keyframe",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_first_frame_keyframe() {
+        let chunks = usize::from(HEADER_SIZE + BLOCK_SIZE);
+
+        assert_eq!(
+            container_problems(|container| container[chunks + 2] = FrameType::Delta as u8).await,
+            ["Title 0, frame 0: The first frame isn't a keyframe"]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+// Host stand-ins for the eZ80 toolchain types used by the player
+#pragma once
+
+#include <stdint.h>
+
+typedef uint32_t uint24_t;
+typedef int32_t int24_t;
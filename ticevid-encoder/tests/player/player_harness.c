@@ -0,0 +1,117 @@
+// Host harness around the player's picture decoders
+//
+// Usage: player_harness <pixels>
+// Reads frames from stdin as a codec byte, a frame type byte, a little endian u32 length,
+// then the image bytes.
+// Writes each decoded frame to stdout as raw palette indices.
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "lzss.h"
+#include "motion.h"
+#include "qoi.h"
+#include "vq.h"
+
+#define HARNESS_WIDTH 320
+#define HARNESS_MAX_PIXELS (HARNESS_WIDTH * 240)
+
+uint8_t *ticevid_vbuffer;
+uint8_t *ticevid_vscreen;
+
+static uint8_t vbuffer[HARNESS_MAX_PIXELS];
+// Mirrors the player blitting each frame to the screen
+static uint8_t vscreen[HARNESS_MAX_PIXELS];
+
+void ticevid_error_set_file_line(char *file, uint24_t line) {
+    fprintf(stderr, "Decoder error at %s:%u\n", file, (unsigned int)line);
+}
+
+static uint32_t read_u32(uint8_t bytes[4]) {
+    return bytes[0] | (bytes[1] << 8) | (bytes[2] << 16) | ((uint32_t)bytes[3] << 24);
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "Usage: %s <pixels>\n", argv[0]);
+        return EXIT_FAILURE;
+    }
+
+    uint24_t pixels = strtoul(argv[1], NULL, 10);
+
+    if (pixels > HARNESS_MAX_PIXELS) {
+        fprintf(stderr, "Pixel count over maximum: %u\n", (unsigned int)pixels);
+        return EXIT_FAILURE;
+    }
+
+    ticevid_vbuffer = vbuffer;
+    ticevid_vscreen = vscreen;
+
+    uint8_t header[6];
+
+    while (fread(header, 1, sizeof(header), stdin) == sizeof(header)) {
+        uint8_t codec = header[0];
+        bool keyframe = header[1] == 0;
+        uint32_t length = read_u32(&header[2]);
+
+        if (length > UINT16_MAX) {
+            fprintf(stderr, "Image size over maximum: %u\n", (unsigned int)length);
+            return EXIT_FAILURE;
+        }
+
+        uint8_t *input = malloc(length);
+
+        if (input == NULL || fread(input, 1, length, stdin) != length) {
+            fprintf(stderr, "Failed to read image of %u bytes\n", (unsigned int)length);
+            return EXIT_FAILURE;
+        }
+
+        if ((codec == 1 || codec == 2) && pixels % HARNESS_WIDTH != 0) {
+            fprintf(stderr, "Block based frames must be whole rows\n");
+            return EXIT_FAILURE;
+        }
+
+        ticevid_qoi_init_frame(0);
+
+        uint24_t remaining_pixels = pixels;
+        ticevid_result_t result;
+
+        switch (codec) {
+            case 0:
+                result = ticevid_qoi_decode(length, &remaining_pixels, input);
+                break;
+            case 1:
+                result = ticevid_motion_decode(length, input, 0, HARNESS_WIDTH, pixels / HARNESS_WIDTH);
+                break;
+            case 2:
+                result = ticevid_vq_decode(length, input, 0, HARNESS_WIDTH, pixels / HARNESS_WIDTH, keyframe);
+                break;
+            case 3:
+                if (length != pixels) {
+                    fprintf(stderr, "Raw image size doesn't match: %u\n", (unsigned int)length);
+                    return EXIT_FAILURE;
+                }
+
+                memcpy(vbuffer, input, length);
+                result = TICEVID_SUCCESS;
+                break;
+            case 4:
+                result = ticevid_lzss_decode(length, input, 0, pixels);
+                break;
+            default:
+                fprintf(stderr, "Unknown codec: %u\n", (unsigned int)codec);
+                return EXIT_FAILURE;
+        }
+
+        if (result != TICEVID_SUCCESS) {
+            return EXIT_FAILURE;
+        }
+
+        free(input);
+        memcpy(vscreen, vbuffer, pixels);
+        fwrite(vbuffer, 1, pixels, stdout);
+    }
+
+    return EXIT_SUCCESS;
+}